@@ -4,13 +4,79 @@ use na::Matrix4;
 use wasm_bindgen::prelude::wasm_bindgen;
 
 use crate::{
-    linear_transform::LinearTransform, math::matrix::from_fov_and_aspect,
+    gl::render_state::Scissor,
+    linear_transform::LinearTransform,
+    math::matrix::{from_fov_and_aspect, transform_point},
+    types::Vec3,
 };
 
+/// Layers every shape is on unless set otherwise.
+pub const DEFAULT_LAYERS: u32 = 1;
+
+/// Rectangle in fractions of the size of a target, from its bottom-left
+/// corner.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[wasm_bindgen]
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn full() -> Rect {
+        Rect::new(0.0, 0.0, 1.0, 1.0)
+    }
+}
+
+impl Rect {
+    /// Pixels covered in a `width` by `height` target. Edges are rounded so
+    /// that adjacent rectangles share them, without gap or overlap.
+    pub fn to_pixels(&self, width: u32, height: u32) -> Scissor {
+        let edge = |fraction: f32, size: u32| {
+            (fraction.clamp(0.0, 1.0) * size as f32).round() as i32
+        };
+        let x = edge(self.x, width);
+        let y = edge(self.y, height);
+        Scissor {
+            x,
+            y,
+            width: (edge(self.x + self.width, width) - x).max(0),
+            height: (edge(self.y + self.height, height) - y).max(0),
+        }
+    }
+}
+
 #[wasm_bindgen]
 pub struct Camera {
     projection: Matrix4<f32>,
+    near: f32,
+    far: f32,
     pub transform: LinearTransform,
+    /// Part of the target drawn into.
+    pub viewport: Rect,
+    /// Part of the viewport drawn into, when only some of it is.
+    scissor: Option<Rect>,
+    /// Whether the viewport is cleared to the background color, then the
+    /// skybox drawn, before the shapes are.
+    pub clear_color: bool,
+    pub clear_depth: bool,
+    /// Cameras rendering into the same target are drawn by increasing
+    /// order, later ones over earlier ones.
+    pub order: i32,
+    /// Layers of the shapes drawn, one per bit.
+    pub layer_mask: u32,
 }
 
 #[wasm_bindgen]
@@ -25,12 +91,147 @@ impl Camera {
             // projection: Matrix4::identity(),
             projection: from_fov_and_aspect(near, far, fov, aspect),
             // projection: from_frustrum(1.0, 100.0, -10.0, 10.0, 10.0, -10.0),
+            near,
+            far,
+            viewport: Rect::full(),
+            scissor: None,
+            clear_color: true,
+            clear_depth: true,
+            order: 0,
+            layer_mask: u32::MAX,
         }
     }
+
+    /// Camera without perspective, seeing a `width` by `height` box along
+    /// its -z axis.
+    pub fn orthographic(near: f32, far: f32, width: f32, height: f32) -> Self {
+        Self {
+            projection: Matrix4::new_orthographic(
+                -width / 2.0,
+                width / 2.0,
+                -height / 2.0,
+                height / 2.0,
+                near,
+                far,
+            )
+            .transpose(),
+            ..Camera::new(near, far, 1.0, 1.0)
+        }
+    }
+
+    pub fn set_viewport(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.viewport = Rect::new(x, y, width, height);
+    }
+
+    /// Only draws the part of the viewport in the rectangle, in fractions
+    /// of the target size like the viewport.
+    pub fn set_scissor(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.scissor = Some(Rect::new(x, y, width, height));
+    }
+
+    pub fn clear_scissor(&mut self) {
+        self.scissor = None;
+    }
 }
 
 impl Camera {
     pub fn get_projection_matrix(&self) -> &Matrix4<f32> {
         &self.projection
     }
+
+    pub fn near(&self) -> f32 {
+        self.near
+    }
+
+    pub fn far(&self) -> f32 {
+        self.far
+    }
+
+    /// Pixels drawn into in a `width` by `height` target: the viewport,
+    /// and the part of it inside the scissor.
+    pub fn pixel_rects(&self, width: u32, height: u32) -> (Scissor, Scissor) {
+        let viewport = self.viewport.to_pixels(width, height);
+        let clip = match &self.scissor {
+            Some(scissor) => {
+                scissor.to_pixels(width, height).intersection(&viewport)
+            }
+            None => viewport,
+        };
+        (viewport, clip)
+    }
+
+    /// World space corners of the part of the view frustum between the view
+    /// depths `near` and `far`, near corners first.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vec3; 8] {
+        // The shaders receive the transpose of the stored projection. The
+        // frustum edges are where clip x and y equal ±w.
+        let p = self.projection.transpose();
+        let extent = |row: usize, z: f32, sign: f32| {
+            (sign * (p[(3, 2)] * z + p[(3, 3)]) - p[(row, 2)] * z - p[(row, 3)])
+                / (p[(row, row)] - sign * p[(3, row)])
+        };
+        let camera_matrix = self.transform.get_matrix();
+        let mut corners = [Vec3::zero(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let z = -if i < 4 { near } else { far };
+            let sign_x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let sign_y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let view_corner =
+                Vec3::new(extent(0, z, sign_x), extent(1, z, sign_y), z);
+            *corner = transform_point(camera_matrix, view_corner);
+        }
+        corners
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_viewports_share_edges() {
+        let left = Rect::new(0.0, 0.0, 0.5, 1.0).to_pixels(101, 60);
+        let right = Rect::new(0.5, 0.0, 0.5, 1.0).to_pixels(101, 60);
+        assert_eq!(left.x + left.width, right.x);
+        assert_eq!(right.x + right.width, 101);
+        assert_eq!(left.height, 60);
+    }
+
+    #[test]
+    fn scissor_is_clipped_to_viewport() {
+        let mut camera = Camera::new(0.1, 100.0, 1.0, 1.0);
+        camera.set_viewport(0.75, 0.75, 0.25, 0.25);
+        camera.set_scissor(0.5, 0.5, 0.3, 0.3);
+        let (viewport, clip) = camera.pixel_rects(200, 100);
+        assert_eq!(
+            viewport,
+            Scissor {
+                x: 150,
+                y: 75,
+                width: 50,
+                height: 25,
+            }
+        );
+        assert_eq!(
+            clip,
+            Scissor {
+                x: 150,
+                y: 75,
+                width: 10,
+                height: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn orthographic_frustum_is_a_box() {
+        let camera = Camera::orthographic(1.0, 10.0, 4.0, 2.0);
+        let corners = camera.frustum_corners(1.0, 10.0);
+        for (i, corner) in corners.iter().enumerate() {
+            assert!((corner.x.abs() - 2.0).abs() < 1e-5, "{:?}", corner);
+            assert!((corner.y.abs() - 1.0).abs() < 1e-5, "{:?}", corner);
+            let z = if i < 4 { -1.0 } else { -10.0 };
+            assert!((corner.z - z).abs() < 1e-5, "{:?}", corner);
+        }
+    }
 }
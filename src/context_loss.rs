@@ -0,0 +1,246 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{Event, EventTarget, WebglLoseContext};
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        cubemap::TextureCube, mesh::Mesh, render_target::RenderTarget,
+        resources::Resources, texture::Texture2D,
+    },
+    material::Material,
+};
+
+static CONTEXT_LOST_EVENT: &str = "webglcontextlost";
+static CONTEXT_RESTORED_EVENT: &str = "webglcontextrestored";
+
+/// State of a context, as reported by the events of its canvas.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContextStatus {
+    Active,
+    /// Nothing can be drawn until the context is restored.
+    Lost,
+    /// The context can be used again once its objects are recreated.
+    Restored,
+}
+
+/// Listens for the loss and restoration of a context on its canvas.
+pub struct ContextLoss {
+    status: Rc<Cell<ContextStatus>>,
+    canvas: EventTarget,
+    on_lost: Closure<dyn FnMut(Event)>,
+    on_restored: Closure<dyn FnMut(Event)>,
+    /// `WEBGL_lose_context`, requested up front since a lost context
+    /// returns no extension.
+    extension: Option<WebglLoseContext>,
+}
+
+impl ContextLoss {
+    /// Starts listening on the canvas of the context of `resources`, whose
+    /// objects are invalidated when the context is lost.
+    pub fn new(resources: &Resources) -> Result<ContextLoss, EngineError> {
+        let context = resources.context();
+        let canvas = context
+            .canvas()
+            .ok_or_else(|| {
+                EngineError::Context(String::from("The context has no canvas."))
+            })?
+            .dyn_into::<EventTarget>()
+            .map_err(|_| {
+                EngineError::Context(String::from(
+                    "The canvas is not an event target.",
+                ))
+            })?;
+        let status = Rc::new(Cell::new(ContextStatus::Active));
+
+        let on_lost = {
+            let status = status.clone();
+            let resources = resources.clone();
+            Closure::wrap(Box::new(move |event: Event| {
+                // Tells the browser the context will be restored.
+                event.prevent_default();
+                resources.invalidate();
+                status.set(ContextStatus::Lost);
+            }) as Box<dyn FnMut(Event)>)
+        };
+        let on_restored = {
+            let status = status.clone();
+            Closure::wrap(Box::new(move |_: Event| {
+                status.set(ContextStatus::Restored);
+            }) as Box<dyn FnMut(Event)>)
+        };
+        for (event, listener) in [
+            (CONTEXT_LOST_EVENT, &on_lost),
+            (CONTEXT_RESTORED_EVENT, &on_restored),
+        ]
+        .iter()
+        {
+            canvas
+                .add_event_listener_with_callback(
+                    event,
+                    listener.as_ref().unchecked_ref(),
+                )
+                .map_err(|_| {
+                    EngineError::Context(format!(
+                        "Could not listen to {}.",
+                        event
+                    ))
+                })?;
+        }
+
+        let extension = match context.get_extension("WEBGL_lose_context") {
+            Ok(Some(extension)) => Some(extension.unchecked_into()),
+            _ => None,
+        };
+        Ok(ContextLoss {
+            status,
+            canvas,
+            on_lost,
+            on_restored,
+            extension,
+        })
+    }
+
+    pub fn status(&self) -> ContextStatus {
+        self.status.get()
+    }
+
+    /// Records that the objects were recreated after a restoration.
+    pub fn set_active(&self) {
+        self.status.set(ContextStatus::Active);
+    }
+
+    /// Records that the objects could not be recreated, waiting for the
+    /// next restoration.
+    pub fn set_lost(&self) {
+        self.status.set(ContextStatus::Lost);
+    }
+
+    /// Loses the context through `WEBGL_lose_context`, to test restoration.
+    pub fn simulate_loss(&self) -> Result<(), EngineError> {
+        self.lose_context_extension()?.lose_context();
+        Ok(())
+    }
+
+    /// Restores a context lost with `simulate_loss`.
+    pub fn simulate_restore(&self) -> Result<(), EngineError> {
+        self.lose_context_extension()?.restore_context();
+        Ok(())
+    }
+
+    fn lose_context_extension(&self) -> Result<&WebglLoseContext, EngineError> {
+        self.extension.as_ref().ok_or_else(|| {
+            EngineError::Unsupported(String::from(
+                "WEBGL_lose_context is not supported.",
+            ))
+        })
+    }
+}
+
+impl Drop for ContextLoss {
+    fn drop(&mut self) {
+        for (event, listener) in [
+            (CONTEXT_LOST_EVENT, &self.on_lost),
+            (CONTEXT_RESTORED_EVENT, &self.on_restored),
+        ]
+        .iter()
+        {
+            let _ = self.canvas.remove_event_listener_with_callback(
+                event,
+                listener.as_ref().unchecked_ref(),
+            );
+        }
+    }
+}
+
+/// Objects recreated after a context restoration, by the address of the
+/// lost object. Lost objects are kept alive so that their addresses are not
+/// reused.
+type Restored<T> = HashMap<*const T, (Rc<T>, Rc<T>)>;
+
+/// Recreates the meshes and textures shared between shapes and materials
+/// once each.
+#[derive(Default)]
+pub struct Restorer {
+    meshes: Restored<Mesh>,
+    textures: Restored<Texture2D>,
+    cubemaps: Restored<TextureCube>,
+}
+
+impl Restorer {
+    pub fn new() -> Restorer {
+        Restorer::default()
+    }
+
+    pub fn mesh(
+        &mut self,
+        env: &GlEnvironment,
+        lost: &Rc<Mesh>,
+    ) -> Result<Rc<Mesh>, EngineError> {
+        restore(&mut self.meshes, lost, |mesh| mesh.recreate(env))
+    }
+
+    pub fn texture(
+        &mut self,
+        env: &GlEnvironment,
+        lost: &Rc<Texture2D>,
+    ) -> Result<Rc<Texture2D>, EngineError> {
+        restore(&mut self.textures, lost, |texture| texture.recreate(env))
+    }
+
+    pub fn cubemap(
+        &mut self,
+        env: &GlEnvironment,
+        lost: &Rc<TextureCube>,
+    ) -> Result<Rc<TextureCube>, EngineError> {
+        restore(&mut self.cubemaps, lost, |cubemap| cubemap.recreate(env))
+    }
+
+    /// Replaces the textures of `lost` with those of `restored`, its
+    /// recreation, rather than recreating them empty.
+    pub fn replace_target(
+        &mut self,
+        lost: &RenderTarget,
+        restored: &RenderTarget,
+    ) {
+        for (lost, restored) in lost.textures().zip(restored.textures()) {
+            self.textures
+                .insert(Rc::as_ptr(lost), (lost.clone(), restored.clone()));
+        }
+    }
+
+    /// Replaces the textures and environment map of `material`.
+    pub fn material(
+        &mut self,
+        env: &GlEnvironment,
+        material: &mut Material,
+    ) -> Result<(), EngineError> {
+        for texture in material.textures_mut() {
+            *texture = self.texture(env, texture)?;
+        }
+        if let Some(cubemap) = material.environment_map_mut() {
+            *cubemap = self.cubemap(env, cubemap)?;
+        }
+        Ok(())
+    }
+}
+
+fn restore<T, F>(
+    restored: &mut Restored<T>,
+    lost: &Rc<T>,
+    recreate: F,
+) -> Result<Rc<T>, EngineError>
+where
+    F: FnOnce(&T) -> Result<T, EngineError>,
+{
+    if let Some((_, object)) = restored.get(&Rc::as_ptr(lost)) {
+        return Ok(object.clone());
+    }
+    let object = Rc::new(recreate(lost)?);
+    restored.insert(Rc::as_ptr(lost), (lost.clone(), object.clone()));
+    Ok(object)
+}
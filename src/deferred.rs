@@ -0,0 +1,161 @@
+use web_sys::WebGl2RenderingContext;
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        gl_utils::{set_viewport, Program},
+        render_state::RenderState,
+        render_target::{
+            AttachmentSettings, RenderTarget, RenderTargetSettings,
+        },
+        texture::{Filter, TextureFormat},
+    },
+    lights::{Light, LightKind},
+    types::Vec3,
+};
+
+pub static DEFERRED_EMISSION_PROGRAM: &str = "deferred_emission";
+pub static DEFERRED_LIGHT_PROGRAM: &str = "deferred_light";
+
+/// How a renderer shades opaque shapes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RenderPath {
+    /// Shapes are shaded by every light as they are drawn, up to
+    /// `MAX_LIGHTS` lights.
+    Forward,
+    /// Opaque shapes are drawn to a G-buffer, then each light shades the
+    /// pixels within its range, which scales to many lights. Transparent
+    /// shapes are still drawn forward. Needs `EXT_color_buffer_float`,
+    /// without it shapes are drawn forward.
+    Deferred,
+}
+
+/// Surface attributes of the opaque shapes, shaded by the deferred lighting
+/// passes. The encoding is described by the g_buffer and output shader
+/// chunks.
+pub struct GBuffer {
+    /// Albedo, normal, material parameters and emission in colors 0 to 3,
+    /// and a sampleable depth texture.
+    pub target: RenderTarget,
+}
+
+impl GBuffer {
+    /// Creates a G-buffer of `width` by `height` pixels. Needs
+    /// `EXT_color_buffer_float`.
+    pub fn new(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+    ) -> Result<GBuffer, EngineError> {
+        let settings = RenderTargetSettings {
+            color: [
+                TextureFormat::Rgba8,
+                TextureFormat::Rgba16F,
+                TextureFormat::Rgba8,
+                TextureFormat::Rgba16F,
+            ]
+            .iter()
+            .map(|format| AttachmentSettings::texture(*format, Filter::Nearest))
+            .collect(),
+            depth: Some(AttachmentSettings::texture(
+                TextureFormat::Depth24,
+                Filter::Nearest,
+            )),
+            samples: 1,
+        };
+        Ok(GBuffer {
+            target: RenderTarget::new(env, width, height, &settings)?,
+        })
+    }
+
+    /// Binds and clears the G-buffer, where nothing drawn reads as unlit
+    /// and at the far plane, drawing into `viewport`.
+    pub fn begin(&self, env: &GlEnvironment, viewport: [i32; 4]) {
+        let context = &env.context;
+        self.target.bind(context);
+        set_viewport(context, viewport);
+        // Clears are masked by the depth and color write state.
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::default());
+        for buffer in 0..4 {
+            context.clear_bufferfv_with_f32_array(
+                WebGl2RenderingContext::COLOR,
+                buffer,
+                &[0.0; 4],
+            );
+        }
+        context.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+    }
+
+    /// Binds the attachments to units 0 to 4 and sets the matching sampler
+    /// uniforms of `program`.
+    pub fn bind_textures(
+        &self,
+        context: &WebGl2RenderingContext,
+        program: &Program,
+    ) -> Result<(), EngineError> {
+        let samplers = [
+            "albedoBuffer",
+            "normalBuffer",
+            "materialBuffer",
+            "emissionBuffer",
+        ];
+        for (unit, sampler) in samplers.iter().enumerate() {
+            let texture = self.target.color_texture(unit).ok_or_else(|| {
+                EngineError::Internal(String::from("G-buffer has no texture."))
+            })?;
+            texture.bind(context, unit as u32);
+            context.uniform1i(program.uniform_location(sampler), unit as i32);
+        }
+        let depth = self.target.depth_texture().ok_or_else(|| {
+            EngineError::Internal(String::from(
+                "G-buffer has no depth texture.",
+            ))
+        })?;
+        depth.bind(context, samplers.len() as u32);
+        context.uniform1i(
+            program.uniform_location("depthBuffer"),
+            samplers.len() as i32,
+        );
+        Ok(())
+    }
+
+    /// Unbinds the units of `bind_textures`, so that the attachments aren't
+    /// sampled while the G-buffer is drawn to.
+    pub fn unbind_textures(&self, context: &WebGl2RenderingContext) {
+        for unit in 0..5 {
+            context.active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
+            context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        }
+    }
+}
+
+/// Center and radius of the sphere lit by `light`, `None` when it reaches
+/// everywhere.
+pub fn light_volume(light: &Light) -> Option<(Vec3, f32)> {
+    if light.kind() == LightKind::Directional || light.range() <= 0.0 {
+        return None;
+    }
+    Some((light.position(), light.range()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_bounded_lights_have_volumes() {
+        let directional =
+            Light::directional(0.0, -1.0, 0.0, 1.0, 1.0, 1.0, 1.0);
+        let unbounded = Light::point(1.0, 2.0, 3.0, 1.0, 1.0, 1.0, 1.0, 0.0);
+        let bounded = Light::point(1.0, 2.0, 3.0, 1.0, 1.0, 1.0, 1.0, 5.0);
+        assert_eq!(light_volume(&directional), None);
+        assert_eq!(light_volume(&unbounded), None);
+        assert_eq!(
+            light_volume(&bounded),
+            Some((Vec3::new(1.0, 2.0, 3.0), 5.0))
+        );
+    }
+}
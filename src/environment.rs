@@ -1,19 +1,121 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
 use wasm_bindgen::prelude::*;
-use web_sys::WebGl2RenderingContext;
+use web_sys::{HtmlImageElement, WebGl2RenderingContext};
 
+use crate::camera::Camera;
+use crate::context_loss::{ContextLoss, ContextStatus};
+use crate::deferred::RenderPath;
+use crate::error::EngineError;
+use crate::fog::{DistanceFog, HeightFog};
+use crate::gl::cubemap::TextureCube;
+use crate::gl::gl_shape::GlShape;
 use crate::gl::gl_utils;
-use crate::gl::gl_utils::Program;
+use crate::gl::gl_utils::{
+    CanvasTarget, ContextAttributes, Program, ShaderFeatures,
+};
+use crate::gl::instances::{
+    set_default_instance_attributes, Instance, InstanceId, Instances,
+};
+use crate::gl::render_state::StateCache;
+use crate::gl::render_target::{RenderTarget, RenderTargetSettings};
+use crate::gl::resources::{ResourceStats, Resources};
+use crate::gl::texture::{SamplerSettings, Texture2D, TextureFormat};
+use crate::lights::Light;
+use crate::linear_transform::LinearTransform;
+use crate::material::{
+    AlphaMode, BlinnPhong, Material, Pbr, PbrTexture, Shading,
+};
+use crate::post_process::{EffectKind, ToneMapping};
+use crate::render_queue::RenderStats;
 use crate::renderer::Renderer;
+use crate::scene::{OffscreenView, Scene};
 use crate::shapes::cuboid::Cuboid;
+use crate::skybox::Skybox;
 use crate::types::Color;
 use crate::types::Vec3;
 use crate::Environment;
 
 pub struct GlEnvironment {
     pub context: WebGl2RenderingContext,
-    pub program: Program,
+    /// Creates the GPU objects of `context` and counts the live ones.
+    pub resources: Resources,
+    pub programs: HashMap<&'static str, Program>,
+    pub max_anisotropy: f32,
+    /// Whether float textures can be rendered to (EXT_color_buffer_float).
+    pub color_buffer_float: bool,
+    /// Whether 32-bit float textures can be filtered
+    /// (OES_texture_float_linear).
+    pub texture_float_linear: bool,
+    /// Fixed function state, only changed through `RenderState`s.
+    pub state_cache: RefCell<StateCache>,
+    /// Counts of the frame being rendered, or of the last one.
+    pub render_stats: RefCell<RenderStats>,
+    /// Whether `context` is lost, in which case nothing is rendered.
+    pub context_loss: ContextLoss,
+    /// Material programs compiled with non-default `ShaderFeatures`,
+    /// compiled on first use.
+    program_variants:
+        RefCell<HashMap<(&'static str, ShaderFeatures), Rc<Program>>>,
 }
 
+impl GlEnvironment {
+    /// Default program `name`.
+    pub fn program(&self, name: &str) -> Result<&Program, EngineError> {
+        self.programs
+            .get(name)
+            .ok_or_else(|| EngineError::MissingProgram(String::from(name)))
+    }
+
+    /// Compiles the programs again and resets the state tracked for the
+    /// context after it was restored.
+    pub fn restore(&mut self) -> Result<(), EngineError> {
+        set_default_instance_attributes(&self.context);
+        self.programs = gl_utils::create_default_programs(&self.resources)?;
+        self.program_variants.borrow_mut().clear();
+        self.max_anisotropy = gl_utils::get_max_anisotropy(&self.context);
+        self.color_buffer_float =
+            gl_utils::enable_color_buffer_float(&self.context);
+        self.texture_float_linear =
+            gl_utils::enable_texture_float_linear(&self.context);
+        *self.state_cache.borrow_mut() = StateCache::new();
+        Ok(())
+    }
+
+    /// Material program `name` compiled with `features`.
+    pub fn program_variant(
+        &self,
+        name: &'static str,
+        features: ShaderFeatures,
+    ) -> Result<Rc<Program>, EngineError> {
+        if let Some(program) =
+            self.program_variants.borrow().get(&(name, features))
+        {
+            return Ok(program.clone());
+        }
+        let (_, vert_source, frag_source) = gl_utils::material_programs()
+            .iter()
+            .copied()
+            .find(|(program_name, _, _)| *program_name == name)
+            .ok_or_else(|| EngineError::MissingProgram(String::from(name)))?;
+        let program = Rc::new(gl_utils::create_program_with_defines(
+            &self.resources,
+            vert_source,
+            frag_source,
+            &features.defines(),
+        )?);
+        self.program_variants
+            .borrow_mut()
+            .insert((name, features), program.clone());
+        Ok(program)
+    }
+}
+
+/// Canvas the environment renders to when none is given.
+static DEFAULT_CANVAS_ID: &str = "canvas";
+
 static RED: Color = Color {
     r: 1.0,
     g: 0.0,
@@ -37,15 +139,56 @@ static BLUE: Color = Color {
 
 #[wasm_bindgen]
 impl Environment {
+    /// Creates the environment on the canvas of id `canvas`.
     pub fn new(renderer: Renderer) -> Result<Environment, JsValue> {
-        console_error_panic_hook::set_once();
-        let context = gl_utils::get_context()?;
-        let program = gl_utils::create_default_program(&context)?;
+        Environment::new_with_attributes(renderer, ContextAttributes::new())
+    }
 
-        context.use_program(Some(&program.gl_program));
-        context.enable(WebGl2RenderingContext::CULL_FACE);
+    /// Creates the environment on the canvas of id `canvas`, with a context
+    /// created with `attributes`.
+    pub fn new_with_attributes(
+        renderer: Renderer,
+        attributes: ContextAttributes,
+    ) -> Result<Environment, JsValue> {
+        Environment::new_with_canvas(
+            renderer,
+            &JsValue::from_str(DEFAULT_CANVAS_ID),
+            attributes,
+        )
+    }
+
+    /// Creates the environment on `canvas`, a canvas element, its id or an
+    /// `OffscreenCanvas`, with a context created with `attributes`. Several
+    /// environments can coexist, each on its own canvas.
+    pub fn new_with_canvas(
+        renderer: Renderer,
+        canvas: &JsValue,
+        attributes: ContextAttributes,
+    ) -> Result<Environment, JsValue> {
+        console_error_panic_hook::set_once();
+        let target = CanvasTarget::from_js(canvas)?;
+        let context = gl_utils::get_context(&target, &attributes)?;
+        set_default_instance_attributes(&context);
+        let resources = Resources::new(&context);
+        let programs = gl_utils::create_default_programs(&resources)?;
+        let max_anisotropy = gl_utils::get_max_anisotropy(&context);
+        let color_buffer_float = gl_utils::enable_color_buffer_float(&context);
+        let texture_float_linear =
+            gl_utils::enable_texture_float_linear(&context);
+        let context_loss = ContextLoss::new(&resources)?;
 
-        let gl_environment = GlEnvironment { context, program };
+        let gl_environment = GlEnvironment {
+            context,
+            resources,
+            programs,
+            max_anisotropy,
+            color_buffer_float,
+            texture_float_linear,
+            state_cache: RefCell::new(StateCache::new()),
+            render_stats: RefCell::new(RenderStats::default()),
+            context_loss,
+            program_variants: RefCell::new(HashMap::new()),
+        };
 
         let mut cuboid = Cuboid::new(
             &gl_environment,
@@ -55,27 +198,1114 @@ impl Environment {
             &[RED, BLUE, GREEN, RED],
         )?;
         cuboid.shape.transform.translate(0.0, 0.0, -5.0);
+        cuboid.shape.material = Material::blinn_phong(BlinnPhong::default());
+
+        let mut scene = Scene::new();
+        scene.add_shape(cuboid.shape);
+        scene.add_light(Light::directional(
+            -0.5, -1.0, -0.5, 1.0, 1.0, 1.0, 1.0,
+        ));
 
         Ok(Environment {
             renderer,
             gl_environment,
-            shapes: vec![cuboid.shape],
+            scene,
             tick_count: 0,
         })
     }
 
-    pub fn tick(&mut self) {
-        self.shapes[0]
-            .transform
-            .rotate(Vec3::new(0.0, 1.0, 0.0), 1.0);
+    /// Renders a frame, unless the context is lost. Throws an
+    /// `EngineError` if the frame or the restoration of the context fails.
+    pub fn tick(&mut self) -> Result<(), JsValue> {
+        match self.gl_environment.context_loss.status() {
+            ContextStatus::Active => {}
+            ContextStatus::Lost => return Ok(()),
+            ContextStatus::Restored => {
+                if let Err(error) = self.restore() {
+                    // Retried on the next restoration.
+                    self.gl_environment.context_loss.set_lost();
+                    return Err(error.into());
+                }
+                self.gl_environment.context_loss.set_active();
+            }
+        }
+
+        if let Some(shape) = self.scene.shapes.first_mut() {
+            shape.transform.rotate(Vec3::new(0.0, 1.0, 0.0), 1.0);
+        }
 
         // TODO: prepare for render asynchronously.
-        for shape in &mut self.shapes {
+        for shape in &mut self.scene.shapes {
             shape.prepare_for_render(&self.gl_environment.context);
         }
+        self.scene.update_static_batches(&self.gl_environment)?;
+        for view in &mut self.scene.offscreen_views {
+            view.camera.transform.update_matrix();
+        }
 
-        self.renderer.render(&self.gl_environment, &self.shapes);
+        self.renderer.render(&self.gl_environment, &self.scene)?;
 
         self.tick_count += 1;
+        Ok(())
+    }
+
+    /// Whether the context is lost and not yet recreated, during which
+    /// `tick` draws nothing.
+    pub fn is_context_lost(&self) -> bool {
+        self.gl_environment.context_loss.status() != ContextStatus::Active
+    }
+
+    /// Loses the context through `WEBGL_lose_context`, to test that the
+    /// scene is recreated once `simulate_context_restore` is called.
+    pub fn simulate_context_loss(&self) -> Result<(), JsValue> {
+        Ok(self.gl_environment.context_loss.simulate_loss()?)
+    }
+
+    pub fn simulate_context_restore(&self) -> Result<(), JsValue> {
+        Ok(self.gl_environment.context_loss.simulate_restore()?)
+    }
+
+    /// Draw calls and binds of the last frame.
+    pub fn render_stats(&self) -> RenderStats {
+        *self.gl_environment.render_stats.borrow()
+    }
+
+    /// GPU objects currently alive and their estimated memory, to detect
+    /// leaks.
+    pub fn resource_stats(&self) -> ResourceStats {
+        self.gl_environment.resources.stats()
+    }
+
+    /// Adds a light to the scene and returns its index.
+    pub fn add_light(&mut self, light: Light) -> usize {
+        self.scene.add_light(light)
+    }
+
+    /// Textures shape `index` with a loaded image.
+    pub fn set_shape_texture(
+        &mut self,
+        index: usize,
+        image: &HtmlImageElement,
+    ) -> Result<(), JsValue> {
+        let texture = Texture2D::from_image(
+            &self.gl_environment,
+            image,
+            TextureFormat::Srgb8Alpha8,
+            SamplerSettings {
+                anisotropy: self.gl_environment.max_anisotropy,
+                ..SamplerSettings::default()
+            },
+        )?;
+        self.set_shape_material(index, Material::textured(Rc::new(texture)))
+    }
+
+    /// Textures shape `index` with PNG or JPEG bytes.
+    #[cfg(feature = "image-decoding")]
+    pub fn set_shape_texture_from_bytes(
+        &mut self,
+        index: usize,
+        bytes: &[u8],
+        flip_y: bool,
+    ) -> Result<(), JsValue> {
+        let image = crate::image::decode(
+            bytes,
+            crate::image::DecodeOptions {
+                flip_y,
+                ..crate::image::DecodeOptions::default()
+            },
+        )?;
+        let texture = Texture2D::from_decoded_image(
+            &self.gl_environment,
+            &image,
+            SamplerSettings {
+                anisotropy: self.gl_environment.max_anisotropy,
+                ..SamplerSettings::default()
+            },
+        )?;
+        self.set_shape_material(index, Material::textured(Rc::new(texture)))
+    }
+
+    /// Shades shape `index` with the glTF metallic-roughness model, with
+    /// the base color `(r, g, b, a)` and without textures.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_shape_pbr_material(
+        &mut self,
+        index: usize,
+        r: f32,
+        g: f32,
+        b: f32,
+        a: f32,
+        metallic: f32,
+        roughness: f32,
+    ) -> Result<(), JsValue> {
+        let params =
+            Pbr::with_factors(Color::new(r, g, b, a), metallic, roughness);
+        self.set_shape_material(index, Material::pbr(params))
+    }
+
+    /// Sets the emitted color of the PBR material of shape `index`.
+    pub fn set_shape_pbr_emissive(
+        &mut self,
+        index: usize,
+        r: f32,
+        g: f32,
+        b: f32,
+    ) -> Result<(), JsValue> {
+        self.pbr_mut(index)?.emissive = Color::rgb(r, g, b);
+        Ok(())
+    }
+
+    /// Sets how much the occlusion texture darkens and the normal texture
+    /// bends the PBR material of shape `index`.
+    pub fn set_shape_pbr_strengths(
+        &mut self,
+        index: usize,
+        occlusion_strength: f32,
+        normal_scale: f32,
+    ) -> Result<(), JsValue> {
+        let params = self.pbr_mut(index)?;
+        params.occlusion_strength = occlusion_strength;
+        params.normal_scale = normal_scale;
+        Ok(())
+    }
+
+    /// Sets texture `slot` of the PBR material of shape `index` from a
+    /// loaded image. `slot` is `"base_color"`, `"metallic_roughness"`,
+    /// `"occlusion"`, `"emissive"` or `"normal"`.
+    pub fn set_shape_pbr_texture(
+        &mut self,
+        index: usize,
+        slot: &str,
+        image: &HtmlImageElement,
+    ) -> Result<(), JsValue> {
+        let slot = pbr_texture(slot)?;
+        self.pbr_mut(index)?;
+        let texture = Texture2D::from_image(
+            &self.gl_environment,
+            image,
+            slot.format(),
+            SamplerSettings {
+                anisotropy: self.gl_environment.max_anisotropy,
+                ..SamplerSettings::default()
+            },
+        )?;
+        *self.pbr_mut(index)?.texture_mut(slot) = Some(Rc::new(texture));
+        Ok(())
+    }
+
+    /// Sets texture `slot` of the PBR material of shape `index` from PNG or
+    /// JPEG bytes, see `set_shape_pbr_texture`.
+    #[cfg(feature = "image-decoding")]
+    pub fn set_shape_pbr_texture_from_bytes(
+        &mut self,
+        index: usize,
+        slot: &str,
+        bytes: &[u8],
+        flip_y: bool,
+    ) -> Result<(), JsValue> {
+        let slot = pbr_texture(slot)?;
+        self.pbr_mut(index)?;
+        let image = crate::image::decode(
+            bytes,
+            crate::image::DecodeOptions {
+                flip_y,
+                srgb: slot.is_srgb(),
+                ..crate::image::DecodeOptions::default()
+            },
+        )?;
+        let texture = Texture2D::from_decoded_image(
+            &self.gl_environment,
+            &image,
+            SamplerSettings {
+                anisotropy: self.gl_environment.max_anisotropy,
+                ..SamplerSettings::default()
+            },
+        )?;
+        *self.pbr_mut(index)?.texture_mut(slot) = Some(Rc::new(texture));
+        Ok(())
+    }
+
+    pub fn set_ambient_light(&mut self, r: f32, g: f32, b: f32) {
+        self.scene.ambient_light = Color::rgb(r, g, b);
+    }
+
+    /// Color the frame is cleared with where no skybox is drawn.
+    pub fn set_background_color(&mut self, r: f32, g: f32, b: f32) {
+        self.scene.background_color = Color::rgb(r, g, b);
+    }
+
+    /// Sets the skybox from six loaded square images ordered +X, -X, +Y,
+    /// -Y, +Z, -Z. The cube map is also reflected by the lit materials
+    /// without an environment map of their own.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_skybox_from_images(
+        &mut self,
+        positive_x: &HtmlImageElement,
+        negative_x: &HtmlImageElement,
+        positive_y: &HtmlImageElement,
+        negative_y: &HtmlImageElement,
+        positive_z: &HtmlImageElement,
+        negative_z: &HtmlImageElement,
+        intensity: f32,
+    ) -> Result<(), JsValue> {
+        let cubemap = TextureCube::from_images(
+            &self.gl_environment,
+            [
+                positive_x, negative_x, positive_y, negative_y, positive_z,
+                negative_z,
+            ],
+            TextureFormat::Srgb8Alpha8,
+            TextureCube::default_sampler(),
+        )?;
+        self.set_skybox(Rc::new(cubemap), intensity)
+    }
+
+    /// Sets the skybox from a loaded equirectangular panorama, projected
+    /// onto cube faces of `size` pixels.
+    pub fn set_skybox_from_equirectangular_image(
+        &mut self,
+        image: &HtmlImageElement,
+        size: u32,
+        intensity: f32,
+    ) -> Result<(), JsValue> {
+        let panorama = Texture2D::from_image(
+            &self.gl_environment,
+            image,
+            TextureFormat::Srgb8Alpha8,
+            SamplerSettings {
+                mipmap_filter: None,
+                ..TextureCube::default_sampler()
+            },
+        )?;
+        let cubemap = TextureCube::from_equirectangular(
+            &self.gl_environment,
+            &panorama,
+            size,
+            TextureFormat::Srgb8Alpha8,
+            TextureCube::default_sampler(),
+        )?;
+        self.set_skybox(Rc::new(cubemap), intensity)
+    }
+
+    pub fn set_skybox_intensity(&mut self, intensity: f32) {
+        if let Some(skybox) = &mut self.scene.skybox {
+            skybox.intensity = intensity;
+        }
+    }
+
+    pub fn remove_skybox(&mut self) {
+        self.scene.skybox = None;
+    }
+
+    /// Sets whether shape `index` casts and receives shadows.
+    pub fn set_shape_shadows(
+        &mut self,
+        index: usize,
+        cast: bool,
+        receive: bool,
+    ) -> Result<(), JsValue> {
+        let shape = self.shape_mut(index)?;
+        shape.cast_shadows = cast;
+        shape.receive_shadows = receive;
+        Ok(())
+    }
+
+    /// Puts shape `index` on the layers of the bits of `layers`. Shapes
+    /// start on layer 0, the first bit.
+    pub fn set_shape_layers(
+        &mut self,
+        index: usize,
+        layers: u32,
+    ) -> Result<(), JsValue> {
+        self.shape_mut(index)?.layers = layers;
+        Ok(())
+    }
+
+    /// Shows or hides shape `index` in every view, its shadows included.
+    pub fn set_shape_visible(
+        &mut self,
+        index: usize,
+        visible: bool,
+    ) -> Result<(), JsValue> {
+        self.shape_mut(index)?.visible = visible;
+        Ok(())
+    }
+
+    /// Sets how shape `index` uses alpha: `"opaque"`, `"mask"` (fragments
+    /// below `cutoff` are discarded) or `"blend"`.
+    pub fn set_shape_alpha_mode(
+        &mut self,
+        index: usize,
+        mode: &str,
+        cutoff: f32,
+    ) -> Result<(), JsValue> {
+        let alpha_mode = match mode {
+            "opaque" => AlphaMode::Opaque,
+            "mask" => AlphaMode::Mask(cutoff),
+            "blend" => AlphaMode::Blend,
+            _ => {
+                return Err(EngineError::InvalidArgument(format!(
+                    "Unknown alpha mode {}.",
+                    mode
+                ))
+                .into())
+            }
+        };
+        let shape = self.shape_mut(index)?;
+        shape.alpha_mode = Some(alpha_mode);
+        Ok(())
+    }
+
+    /// Sets whether the material of shape `index` uses order-independent
+    /// transparency when blended.
+    pub fn set_shape_order_independent(
+        &mut self,
+        index: usize,
+        order_independent: bool,
+    ) -> Result<(), JsValue> {
+        self.shape_mut(index)?.material.order_independent = order_independent;
+        Ok(())
+    }
+
+    /// Draws shape `index` once per instance added with
+    /// `add_shape_instance`, in a single draw call. The shape has no
+    /// instances at first.
+    pub fn enable_shape_instancing(
+        &mut self,
+        index: usize,
+    ) -> Result<(), JsValue> {
+        let shape = self
+            .scene
+            .shapes
+            .get_mut(index)
+            .ok_or_else(|| no_shape(index))?;
+        shape.enable_instancing(&self.gl_environment)?;
+        if shape.is_static {
+            self.scene.static_batches.invalidate();
+        }
+        Ok(())
+    }
+
+    /// Sets whether shape `index` never moves. Static shapes are merged
+    /// with the static shapes drawn the same way and drawn in one call,
+    /// their transform is only read when a static shape is added, removed
+    /// or changed.
+    pub fn set_shape_static(
+        &mut self,
+        index: usize,
+        is_static: bool,
+    ) -> Result<(), JsValue> {
+        let shape = self.shape_mut(index)?;
+        shape.is_static = is_static;
+        self.scene.static_batches.invalidate();
+        Ok(())
+    }
+
+    /// Adds a shape drawing the same mesh as shape `index`, without copying
+    /// its vertices, and returns its index. The new shape starts with the
+    /// transform, material and settings of shape `index`, but without
+    /// instances.
+    pub fn clone_shape(&mut self, index: usize) -> Result<usize, JsValue> {
+        let source = self
+            .scene
+            .shapes
+            .get(index)
+            .ok_or_else(|| no_shape(index))?;
+        let mut shape = GlShape::with_mesh(source.mesh.clone());
+        shape.transform = source.transform;
+        shape.material = source.material.clone();
+        shape.alpha_mode = source.alpha_mode;
+        shape.cast_shadows = source.cast_shadows;
+        shape.receive_shadows = source.receive_shadows;
+        shape.is_static = source.is_static;
+        shape.layers = source.layers;
+        shape.visible = source.visible;
+        Ok(self.scene.add_shape(shape))
+    }
+
+    /// Removes shape `index`, shifting the indices of the next shapes. Its
+    /// GPU objects are deleted, and its mesh too unless other shapes share
+    /// it.
+    pub fn remove_shape(&mut self, index: usize) -> Result<(), JsValue> {
+        self.scene
+            .remove_shape(index)
+            .ok_or_else(|| no_shape(index))?;
+        Ok(())
+    }
+
+    /// Adds an untransformed white instance to instanced shape `index` and
+    /// returns its id.
+    pub fn add_shape_instance(&mut self, index: usize) -> Result<u32, JsValue> {
+        let InstanceId(id) =
+            self.instances_mut(index)?.add(Instance::default());
+        Ok(id)
+    }
+
+    pub fn remove_shape_instance(
+        &mut self,
+        index: usize,
+        id: u32,
+    ) -> Result<(), JsValue> {
+        self.instances_mut(index)?
+            .remove(InstanceId(id))
+            .ok_or_else(|| {
+                EngineError::InvalidArgument(format!(
+                    "No instance {} of shape {}.",
+                    id, index
+                ))
+            })?;
+        Ok(())
+    }
+
+    pub fn shape_instance_count(
+        &mut self,
+        index: usize,
+    ) -> Result<usize, JsValue> {
+        Ok(self.instances_mut(index)?.len())
+    }
+
+    /// Places instance `id` of shape `index` relative to the shape, rotated
+    /// by `angle` degrees around the axis then scaled and translated.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_instance_transform(
+        &mut self,
+        index: usize,
+        id: u32,
+        x: f32,
+        y: f32,
+        z: f32,
+        axis_x: f32,
+        axis_y: f32,
+        axis_z: f32,
+        angle: f32,
+        scale: f32,
+    ) -> Result<(), JsValue> {
+        let mut transform = LinearTransform::new();
+        transform.translate(x, y, z);
+        transform.rotate(Vec3::new(axis_x, axis_y, axis_z), angle);
+        transform.scale(scale);
+        transform.update_matrix();
+        self.update_instance(index, id, |instance| {
+            instance.matrix = *transform.get_matrix();
+        })
+    }
+
+    /// Sets the color the vertex colors of instance `id` of shape `index`
+    /// are multiplied with.
+    pub fn set_instance_color(
+        &mut self,
+        index: usize,
+        id: u32,
+        r: f32,
+        g: f32,
+        b: f32,
+        a: f32,
+    ) -> Result<(), JsValue> {
+        self.update_instance(index, id, |instance| {
+            instance.color = Color::new(r, g, b, a);
+        })
+    }
+
+    /// Sets the `instanceData` attribute of instance `id` of shape `index`.
+    pub fn set_instance_data(
+        &mut self,
+        index: usize,
+        id: u32,
+        data: &[f32],
+    ) -> Result<(), JsValue> {
+        if data.len() > 4 {
+            return Err(EngineError::InvalidArgument(String::from(
+                "Instances carry at most 4 data values.",
+            ))
+            .into());
+        }
+        self.update_instance(index, id, |instance| {
+            instance.data = [0.0; 4];
+            instance.data[..data.len()].copy_from_slice(data);
+        })
+    }
+
+    /// Adds a camera drawing into the canvas, over or beside the others
+    /// depending on its viewport and order, and returns its index. The
+    /// camera of the renderer is camera 0.
+    pub fn add_camera(&mut self, camera: Camera) -> usize {
+        self.renderer.add_camera(camera)
+    }
+
+    /// Removes camera `index`, shifting the indices of the next cameras.
+    pub fn remove_camera(&mut self, index: usize) -> Result<(), JsValue> {
+        self.renderer
+            .remove_camera(index)
+            .map(drop)
+            .ok_or_else(|| no_camera(index).into())
+    }
+
+    /// Moves camera `index` by `(x, y, z)`.
+    pub fn translate_camera(
+        &mut self,
+        index: usize,
+        x: f32,
+        y: f32,
+        z: f32,
+    ) -> Result<(), JsValue> {
+        self.camera_mut(index)?.transform.translate(x, y, z);
+        Ok(())
+    }
+
+    /// Rotates camera `index` by `angle` degrees around the axis
+    /// `(x, y, z)`.
+    pub fn rotate_camera(
+        &mut self,
+        index: usize,
+        x: f32,
+        y: f32,
+        z: f32,
+        angle: f32,
+    ) -> Result<(), JsValue> {
+        self.camera_mut(index)?
+            .transform
+            .rotate(Vec3::new(x, y, z), angle);
+        Ok(())
+    }
+
+    /// Sets the part of the canvas camera `index` draws into, in fractions
+    /// of its size from the bottom-left corner.
+    pub fn set_camera_viewport(
+        &mut self,
+        index: usize,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) -> Result<(), JsValue> {
+        self.camera_mut(index)?.set_viewport(x, y, width, height);
+        Ok(())
+    }
+
+    /// Sets whether camera `index` clears the color, drawing the
+    /// background and skybox, and the depth of its viewport.
+    pub fn set_camera_clear(
+        &mut self,
+        index: usize,
+        color: bool,
+        depth: bool,
+    ) -> Result<(), JsValue> {
+        let camera = self.camera_mut(index)?;
+        camera.clear_color = color;
+        camera.clear_depth = depth;
+        Ok(())
+    }
+
+    /// Sets when camera `index` is drawn, after the cameras of lower order.
+    pub fn set_camera_order(
+        &mut self,
+        index: usize,
+        order: i32,
+    ) -> Result<(), JsValue> {
+        self.camera_mut(index)?.order = order;
+        Ok(())
+    }
+
+    /// Only draws the shapes on a layer of `mask` with camera `index`.
+    pub fn set_camera_layer_mask(
+        &mut self,
+        index: usize,
+        mask: u32,
+    ) -> Result<(), JsValue> {
+        self.camera_mut(index)?.layer_mask = mask;
+        Ok(())
+    }
+
+    /// Renders the view of `camera` into a `width` by `height` texture each
+    /// frame, with `samples` samples per pixel, and returns the index of the
+    /// view.
+    pub fn add_offscreen_view(
+        &mut self,
+        camera: Camera,
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> Result<usize, JsValue> {
+        let target = RenderTarget::new(
+            &self.gl_environment,
+            width,
+            height,
+            &RenderTargetSettings {
+                samples,
+                ..RenderTargetSettings::default()
+            },
+        )?;
+        self.scene
+            .offscreen_views
+            .push(OffscreenView { camera, target });
+        Ok(self.scene.offscreen_views.len() - 1)
+    }
+
+    /// Moves the camera of offscreen view `index` by `(x, y, z)`.
+    pub fn translate_offscreen_camera(
+        &mut self,
+        index: usize,
+        x: f32,
+        y: f32,
+        z: f32,
+    ) -> Result<(), JsValue> {
+        self.offscreen_view_mut(index)?
+            .camera
+            .transform
+            .translate(x, y, z);
+        Ok(())
+    }
+
+    /// Rotates the camera of offscreen view `index` by `angle` degrees
+    /// around the axis `(x, y, z)`.
+    pub fn rotate_offscreen_camera(
+        &mut self,
+        index: usize,
+        x: f32,
+        y: f32,
+        z: f32,
+        angle: f32,
+    ) -> Result<(), JsValue> {
+        self.offscreen_view_mut(index)?
+            .camera
+            .transform
+            .rotate(Vec3::new(x, y, z), angle);
+        Ok(())
+    }
+
+    /// Only renders the shapes on a layer of `mask` into offscreen view
+    /// `index`.
+    pub fn set_offscreen_layer_mask(
+        &mut self,
+        index: usize,
+        mask: u32,
+    ) -> Result<(), JsValue> {
+        self.offscreen_view_mut(index)?.camera.layer_mask = mask;
+        Ok(())
+    }
+
+    /// Textures shape `index` with the color of offscreen view `view`. The
+    /// shape isn't drawn in that view.
+    pub fn set_shape_offscreen_texture(
+        &mut self,
+        index: usize,
+        view: usize,
+    ) -> Result<(), JsValue> {
+        let texture = self
+            .scene
+            .offscreen_views
+            .get(view)
+            .and_then(|view| view.target.color_texture(0))
+            .ok_or_else(|| no_offscreen_view(view))?
+            .clone();
+        self.set_shape_material(index, Material::textured(texture))
+    }
+
+    /// Enables or disables post effect `name`. The built-in effects are
+    /// `"bloom"`, `"tone_mapping"`, `"vignette"`, `"srgb"` and `"fxaa"`.
+    pub fn set_post_effect_enabled(
+        &mut self,
+        name: &str,
+        enabled: bool,
+    ) -> Result<(), JsValue> {
+        self.renderer.post_process_mut().effect_mut(name)?.enabled = enabled;
+        Ok(())
+    }
+
+    /// Sets the samples per pixel of the scene while post-processing, which
+    /// then replaces the antialiasing of the canvas.
+    pub fn set_post_process_samples(&mut self, samples: u32) {
+        self.renderer.post_process_mut().samples = samples;
+    }
+
+    /// Moves post effect `name` to position `index` of the chain.
+    pub fn move_post_effect(
+        &mut self,
+        name: &str,
+        index: usize,
+    ) -> Result<(), JsValue> {
+        self.renderer.post_process_mut().move_effect(name, index)?;
+        Ok(())
+    }
+
+    /// Sets the tone mapping operator, `"reinhard"` or `"aces"`, and the
+    /// exposure colors are scaled by before.
+    pub fn set_tone_mapping(
+        &mut self,
+        operator: &str,
+        exposure: f32,
+    ) -> Result<(), JsValue> {
+        let operator = match operator {
+            "reinhard" => ToneMapping::Reinhard,
+            "aces" => ToneMapping::Aces,
+            _ => {
+                return Err(EngineError::InvalidArgument(format!(
+                    "Unknown tone mapping {}.",
+                    operator
+                ))
+                .into())
+            }
+        };
+        self.set_post_effect_kind(
+            "tone_mapping",
+            EffectKind::ToneMapping { operator, exposure },
+        )
+    }
+
+    pub fn set_bloom(
+        &mut self,
+        threshold: f32,
+        intensity: f32,
+        iterations: u32,
+    ) -> Result<(), JsValue> {
+        self.set_post_effect_kind(
+            "bloom",
+            EffectKind::Bloom {
+                threshold,
+                intensity,
+                iterations,
+            },
+        )
+    }
+
+    pub fn set_vignette(
+        &mut self,
+        intensity: f32,
+        smoothness: f32,
+    ) -> Result<(), JsValue> {
+        self.set_post_effect_kind(
+            "vignette",
+            EffectKind::Vignette {
+                intensity,
+                smoothness,
+            },
+        )
+    }
+
+    /// Appends an enabled post effect drawn with a GLSL ES 3.00 fragment
+    /// shader, reading the previous result from `uniform sampler2D source`
+    /// at `in vec2 vUv`.
+    pub fn add_post_effect(
+        &mut self,
+        name: &str,
+        fragment_source: &str,
+    ) -> Result<(), JsValue> {
+        self.renderer.post_process_mut().add_custom(
+            &self.gl_environment,
+            name,
+            fragment_source,
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_post_effect(&mut self, name: &str) -> Result<(), JsValue> {
+        self.renderer.post_process_mut().remove(name)?;
+        Ok(())
+    }
+
+    /// Sets float uniform `parameter` of the user post effect `name`, which
+    /// its shader must use.
+    pub fn set_post_effect_parameter(
+        &mut self,
+        name: &str,
+        parameter: &str,
+        value: f32,
+    ) -> Result<(), JsValue> {
+        let effect = self.renderer.post_process_mut().effect_mut(name)?;
+        let parameters = match &mut effect.kind {
+            EffectKind::Custom {
+                program,
+                parameters,
+                ..
+            } => {
+                if program.uniform_location(parameter).is_none() {
+                    return Err(EngineError::MissingUniform {
+                        program: String::from(name),
+                        uniform: String::from(parameter),
+                    }
+                    .into());
+                }
+                parameters
+            }
+            _ => {
+                return Err(EngineError::InvalidArgument(format!(
+                    "Post effect {} is not a user effect.",
+                    name
+                ))
+                .into())
+            }
+        };
+        match parameters.iter_mut().find(|(key, _)| key == parameter) {
+            Some((_, current)) => *current = value,
+            None => parameters.push((String::from(parameter), value)),
+        }
+        Ok(())
+    }
+
+    /// Sets the width and height of shadow maps, in texels.
+    pub fn set_shadow_map_size(&mut self, size: u32) {
+        self.scene.shadows.map_size = size.max(1);
+    }
+
+    /// Only renders the shapes on a layer of `mask` into shadow maps.
+    pub fn set_shadow_layer_mask(&mut self, mask: u32) {
+        self.scene.shadows.layer_mask = mask;
+    }
+
+    /// Sets the view distance up to which directional lights cast shadows.
+    pub fn set_shadow_distance(&mut self, distance: f32) {
+        self.scene.shadows.max_distance = distance;
+    }
+
+    /// Sets how opaque shapes are shaded, `"forward"` or `"deferred"`. The
+    /// deferred path scales to many lights and needs float render targets.
+    pub fn set_render_path(&mut self, path: &str) -> Result<(), JsValue> {
+        let path = match path {
+            "forward" => RenderPath::Forward,
+            "deferred" => RenderPath::Deferred,
+            _ => {
+                return Err(EngineError::InvalidArgument(format!(
+                    "Unknown render path {}.",
+                    path
+                ))
+                .into())
+            }
+        };
+        self.renderer.set_path(path);
+        Ok(())
+    }
+
+    pub fn set_fog_color(&mut self, r: f32, g: f32, b: f32) {
+        self.scene.fog.color = Color::rgb(r, g, b);
+    }
+
+    /// Fogs shapes from `start` to fully fogged at `end` from the camera.
+    pub fn set_linear_fog(&mut self, start: f32, end: f32) {
+        self.scene.fog.distance = Some(DistanceFog::Linear { start, end });
+    }
+
+    /// Fogs shapes exponentially with their distance to the camera, or with
+    /// its square when `squared`, which keeps the foreground clearer.
+    pub fn set_exponential_fog(&mut self, density: f32, squared: bool) {
+        self.scene.fog.distance = Some(if squared {
+            DistanceFog::ExponentialSquared { density }
+        } else {
+            DistanceFog::Exponential { density }
+        });
+    }
+
+    /// Adds fog of `density` at `height`, thinning exponentially above it
+    /// by `falloff` per unit of height.
+    pub fn set_height_fog(&mut self, height: f32, density: f32, falloff: f32) {
+        self.scene.fog.height = Some(HeightFog {
+            height,
+            density,
+            falloff,
+        });
+    }
+
+    /// Sets the distance the skybox is fogged as if it were at.
+    pub fn set_fog_sky_distance(&mut self, distance: f32) {
+        self.scene.fog.sky_distance = distance;
+    }
+
+    /// Removes distance and height fog.
+    pub fn clear_fog(&mut self) {
+        self.scene.fog.distance = None;
+        self.scene.fog.height = None;
+    }
+
+    /// Enables screen-space ambient occlusion of the ambient light.
+    pub fn set_ambient_occlusion(&mut self, enabled: bool) {
+        self.scene.ambient_occlusion.enabled = enabled;
+    }
+
+    /// Sets the view space radius searched for occluders, the darkening of
+    /// fully occluded points and the samples per pixel, up to 64.
+    pub fn set_ambient_occlusion_parameters(
+        &mut self,
+        radius: f32,
+        intensity: f32,
+        sample_count: u32,
+    ) {
+        let settings = &mut self.scene.ambient_occlusion;
+        settings.radius = radius;
+        settings.intensity = intensity;
+        settings.sample_count = sample_count;
+    }
+
+    /// Shows the blurred ambient occlusion buffer instead of the scene.
+    pub fn set_ambient_occlusion_debug_view(&mut self, enabled: bool) {
+        self.scene.ambient_occlusion.debug_view = enabled;
+    }
+}
+
+impl Environment {
+    /// Recreates every GPU object from its CPU-side data after the context
+    /// was restored.
+    fn restore(&mut self) -> Result<(), EngineError> {
+        self.gl_environment.restore()?;
+        self.scene.restore(&self.gl_environment)?;
+        self.renderer.restore(&self.gl_environment)
+    }
+
+    /// Shape `index`, whose static batch is rebuilt if it is static since
+    /// it may change.
+    fn shape_mut(&mut self, index: usize) -> Result<&mut GlShape, EngineError> {
+        let shape = self
+            .scene
+            .shapes
+            .get_mut(index)
+            .ok_or_else(|| no_shape(index))?;
+        if shape.is_static {
+            self.scene.static_batches.invalidate();
+        }
+        Ok(shape)
+    }
+
+    /// Parameters of the PBR material of shape `index`.
+    fn pbr_mut(&mut self, index: usize) -> Result<&mut Pbr, EngineError> {
+        match &mut self.shape_mut(index)?.material.shading {
+            Shading::Pbr(params) => Ok(params),
+            _ => Err(EngineError::InvalidArgument(format!(
+                "Shape {} has no PBR material.",
+                index
+            ))),
+        }
+    }
+
+    fn camera_mut(&mut self, index: usize) -> Result<&mut Camera, EngineError> {
+        self.renderer
+            .camera_mut(index)
+            .ok_or_else(|| no_camera(index))
+    }
+
+    fn instances_mut(
+        &mut self,
+        index: usize,
+    ) -> Result<&mut Instances, EngineError> {
+        self.shape_mut(index)?.instances_mut().ok_or_else(|| {
+            EngineError::InvalidArgument(format!(
+                "Shape {} is not instanced.",
+                index
+            ))
+        })
+    }
+
+    fn update_instance<F>(
+        &mut self,
+        index: usize,
+        id: u32,
+        update: F,
+    ) -> Result<(), JsValue>
+    where
+        F: FnOnce(&mut Instance),
+    {
+        if !self.instances_mut(index)?.update(InstanceId(id), update) {
+            return Err(EngineError::InvalidArgument(format!(
+                "No instance {} of shape {}.",
+                id, index
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn offscreen_view_mut(
+        &mut self,
+        index: usize,
+    ) -> Result<&mut OffscreenView, EngineError> {
+        self.scene
+            .offscreen_views
+            .get_mut(index)
+            .ok_or_else(|| no_offscreen_view(index))
+    }
+
+    /// Replaces the settings of built-in post effect `name`, which must be
+    /// of the same kind as `kind`.
+    fn set_post_effect_kind(
+        &mut self,
+        name: &str,
+        kind: EffectKind,
+    ) -> Result<(), JsValue> {
+        let effect = self.renderer.post_process_mut().effect_mut(name)?;
+        if std::mem::discriminant(&effect.kind) != std::mem::discriminant(&kind)
+        {
+            return Err(EngineError::InvalidArgument(format!(
+                "Post effect {} has another kind.",
+                name
+            ))
+            .into());
+        }
+        effect.kind = kind;
+        Ok(())
+    }
+
+    fn set_shape_material(
+        &mut self,
+        index: usize,
+        material: Material,
+    ) -> Result<(), JsValue> {
+        let shape = self.shape_mut(index)?;
+        shape.material = material;
+        Ok(())
+    }
+
+    /// Draws `cubemap` behind the scene. Lit materials without an
+    /// environment map of their own reflect it.
+    fn set_skybox(
+        &mut self,
+        cubemap: Rc<TextureCube>,
+        intensity: f32,
+    ) -> Result<(), JsValue> {
+        self.scene.skybox =
+            Some(Skybox::new(&self.gl_environment, cubemap, intensity)?);
+        Ok(())
+    }
+}
+
+fn no_shape(index: usize) -> EngineError {
+    EngineError::InvalidArgument(format!("No shape at index {}.", index))
+}
+
+fn pbr_texture(slot: &str) -> Result<PbrTexture, EngineError> {
+    PbrTexture::from_name(slot).ok_or_else(|| {
+        EngineError::InvalidArgument(format!("Unknown PBR texture {}.", slot))
+    })
+}
+
+fn no_camera(index: usize) -> EngineError {
+    EngineError::InvalidArgument(format!("No camera at index {}.", index))
+}
+
+fn no_offscreen_view(index: usize) -> EngineError {
+    EngineError::InvalidArgument(format!(
+        "No offscreen view at index {}.",
+        index
+    ))
+}
+
+/// Browser tests, run with `wasm-pack test --headless --chrome`.
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+    use web_sys::OffscreenCanvas;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_dedicated_worker);
+
+    #[wasm_bindgen_test]
+    fn renders_on_offscreen_canvas_in_worker() {
+        let canvas = OffscreenCanvas::new(64, 48).unwrap();
+        let camera = Camera::new(1.0, 100.0, 50.0, 64.0 / 48.0);
+        let mut environment = Environment::new_with_canvas(
+            Renderer::new(camera),
+            &canvas.into(),
+            ContextAttributes::new(),
+        )
+        .unwrap();
+        environment.tick().unwrap();
+        environment.tick().unwrap();
     }
 }
@@ -0,0 +1,207 @@
+use std::fmt;
+
+use js_sys::Reflect;
+use wasm_bindgen::JsValue;
+
+use crate::gl::resources::ResourceKind;
+
+/// Everything that can go wrong in the engine. Thrown to JavaScript as an
+/// `Error` named `EngineError`, with a `kind` property and the fields of
+/// the variant.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineError {
+    /// No WebGL 2 context could be obtained, or its canvas can't be used.
+    Context(String),
+    /// A shader failed to compile. `line` is the first line the log
+    /// reports an error on, 1-based, and `source_line` its text.
+    ShaderCompile {
+        log: String,
+        line: Option<u32>,
+        source_line: Option<String>,
+    },
+    ProgramLink {
+        log: String,
+    },
+    /// The context could not create a GPU object, usually because it is
+    /// lost.
+    Resource(ResourceKind),
+    /// Attribute sizes or indices that don't describe a mesh.
+    InvalidMesh(String),
+    /// Pixels that don't match the size or format of a texture.
+    InvalidTexture(String),
+    MissingProgram(String),
+    /// A uniform that `program` doesn't declare or doesn't use.
+    MissingUniform {
+        program: String,
+        uniform: String,
+    },
+    /// Status returned by `checkFramebufferStatus`.
+    IncompleteFramebuffer(u32),
+    /// A feature the context doesn't support, such as rendering to float
+    /// textures.
+    Unsupported(String),
+    /// An index, name or value passed to the engine that it can't use.
+    InvalidArgument(String),
+    /// Bytes that can't be decoded as an image.
+    Image(String),
+    /// A pass missing what an earlier pass should have prepared.
+    Internal(String),
+}
+
+impl EngineError {
+    /// Error compiling `source` whose log is `log`.
+    pub fn shader_compile(log: String, source: &str) -> EngineError {
+        let line = error_line(&log);
+        let source_line = line
+            .and_then(|line| source.lines().nth(line.checked_sub(1)? as usize))
+            .map(|line| String::from(line.trim()));
+        EngineError::ShaderCompile {
+            log,
+            line,
+            source_line,
+        }
+    }
+
+    /// Name of the variant, the `kind` of the JavaScript error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EngineError::Context(_) => "context",
+            EngineError::ShaderCompile { .. } => "shader_compile",
+            EngineError::ProgramLink { .. } => "program_link",
+            EngineError::Resource(_) => "resource",
+            EngineError::InvalidMesh(_) => "invalid_mesh",
+            EngineError::InvalidTexture(_) => "invalid_texture",
+            EngineError::MissingProgram(_) => "missing_program",
+            EngineError::MissingUniform { .. } => "missing_uniform",
+            EngineError::IncompleteFramebuffer(_) => "incomplete_framebuffer",
+            EngineError::Unsupported(_) => "unsupported",
+            EngineError::InvalidArgument(_) => "invalid_argument",
+            EngineError::Image(_) => "image",
+            EngineError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Context(message)
+            | EngineError::InvalidMesh(message)
+            | EngineError::InvalidTexture(message)
+            | EngineError::Unsupported(message)
+            | EngineError::InvalidArgument(message)
+            | EngineError::Image(message)
+            | EngineError::Internal(message) => f.write_str(message),
+            EngineError::ShaderCompile {
+                log,
+                line: Some(line),
+                source_line: Some(source_line),
+            } => write!(
+                f,
+                "Shader compilation failed at line {} `{}`: {}",
+                line,
+                source_line,
+                log.trim()
+            ),
+            EngineError::ShaderCompile { log, .. } => {
+                write!(f, "Shader compilation failed: {}", log.trim())
+            }
+            EngineError::ProgramLink { log } => {
+                write!(f, "Program linking failed: {}", log.trim())
+            }
+            EngineError::Resource(kind) => {
+                write!(f, "Failed to create {:?}.", kind)
+            }
+            EngineError::MissingProgram(name) => {
+                write!(f, "Could not find program {}.", name)
+            }
+            EngineError::MissingUniform { program, uniform } => {
+                write!(f, "Program {} has no uniform {}.", program, uniform)
+            }
+            EngineError::IncompleteFramebuffer(status) => {
+                write!(f, "Incomplete framebuffer: {:#x}", status)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<EngineError> for JsValue {
+    fn from(error: EngineError) -> JsValue {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name("EngineError");
+        let set = |key: &str, value: JsValue| {
+            // Only fails on frozen objects.
+            let _ = Reflect::set(&js_error, &JsValue::from(key), &value);
+        };
+        set("kind", error.kind().into());
+        match &error {
+            EngineError::ShaderCompile {
+                log,
+                line,
+                source_line,
+            } => {
+                set("log", log.into());
+                set("line", line.map_or(JsValue::NULL, JsValue::from));
+                set(
+                    "sourceLine",
+                    source_line.as_deref().map_or(JsValue::NULL, JsValue::from),
+                );
+            }
+            EngineError::ProgramLink { log } => set("log", log.into()),
+            EngineError::MissingUniform { program, uniform } => {
+                set("program", program.into());
+                set("uniform", uniform.into());
+            }
+            EngineError::IncompleteFramebuffer(status) => {
+                set("status", (*status).into())
+            }
+            _ => {}
+        }
+        js_error.into()
+    }
+}
+
+/// First line an `ERROR: <string>:<line>: ...` entry of a shader log
+/// points at.
+fn error_line(log: &str) -> Option<u32> {
+    log.lines().find_map(|entry| {
+        let mut parts = entry.trim().strip_prefix("ERROR:")?.split(':');
+        parts.next()?;
+        parts.next()?.trim().parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shader_compile_points_at_first_error() {
+        let source = "#version 300 es\nvoid main() {\n  foo = 1.0;\n}\n";
+        let log = "WARNING: 0:1: something\n\
+                   ERROR: 0:3: 'foo' : undeclared identifier\n\
+                   ERROR: 0:4: '' : compilation terminated\n";
+        let error = EngineError::shader_compile(String::from(log), source);
+        assert_eq!(
+            error,
+            EngineError::ShaderCompile {
+                log: String::from(log),
+                line: Some(3),
+                source_line: Some(String::from("foo = 1.0;")),
+            }
+        );
+        assert_eq!(error.kind(), "shader_compile");
+    }
+
+    #[test]
+    fn shader_compile_without_line() {
+        let error =
+            EngineError::shader_compile(String::from("Out of memory"), "");
+        assert_eq!(
+            error.to_string(),
+            "Shader compilation failed: Out of memory"
+        );
+    }
+}
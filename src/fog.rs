@@ -0,0 +1,56 @@
+use crate::types::Color;
+
+/// How fog thickens with the distance to the camera.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DistanceFog {
+    /// No fog before `start`, rising linearly to full fog at `end`.
+    Linear { start: f32, end: f32 },
+    /// `1 - exp(-density * distance)`.
+    Exponential { density: f32 },
+    /// `1 - exp(-(density * distance)²)`, clearer near the camera.
+    ExponentialSquared { density: f32 },
+}
+
+impl DistanceFog {
+    /// Mode constant and parameters of the fog shader chunk.
+    pub fn shader_values(&self) -> (i32, [f32; 2]) {
+        match *self {
+            DistanceFog::Linear { start, end } => (1, [start, end]),
+            DistanceFog::Exponential { density } => (2, [density, 0.0]),
+            DistanceFog::ExponentialSquared { density } => (3, [density, 0.0]),
+        }
+    }
+}
+
+/// Fog pooling at low altitudes, whose density decreases exponentially
+/// above `height`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeightFog {
+    pub height: f32,
+    /// Density at `height`, per world unit.
+    pub density: f32,
+    /// How fast the density decreases, per world unit of height.
+    pub falloff: f32,
+}
+
+/// Fog of a scene, blending shapes and the skybox towards `color`. Distance
+/// and height fog combine when both are set.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fog {
+    pub color: Color,
+    pub distance: Option<DistanceFog>,
+    pub height: Option<HeightFog>,
+    /// Distance the skybox is fogged as if it were at.
+    pub sky_distance: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            color: Color::rgb(0.5, 0.5, 0.5),
+            distance: None,
+            height: None,
+            sky_distance: 1000.0,
+        }
+    }
+}
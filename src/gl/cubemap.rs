@@ -0,0 +1,325 @@
+use web_sys::{
+    HtmlImageElement, WebGl2RenderingContext, WebGlFramebuffer, WebGlTexture,
+};
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        gl_utils::{get_viewport, set_viewport},
+        render_state::RenderState,
+        resources::GlResource,
+        texture::{
+            apply_sampler, supported_sampler, texture_bytes, upload, Pixels,
+            SamplerSettings, Texture2D, TextureFormat, TextureSource, Wrap,
+        },
+    },
+};
+
+pub static EQUIRECT_TO_CUBE_PROGRAM: &str = "equirect_to_cube";
+
+/// Cube map face targets, in the order faces are given to `from_faces`.
+pub static CUBE_FACES: [u32; 6] = [
+    WebGl2RenderingContext::TEXTURE_CUBE_MAP_POSITIVE_X,
+    WebGl2RenderingContext::TEXTURE_CUBE_MAP_NEGATIVE_X,
+    WebGl2RenderingContext::TEXTURE_CUBE_MAP_POSITIVE_Y,
+    WebGl2RenderingContext::TEXTURE_CUBE_MAP_NEGATIVE_Y,
+    WebGl2RenderingContext::TEXTURE_CUBE_MAP_POSITIVE_Z,
+    WebGl2RenderingContext::TEXTURE_CUBE_MAP_NEGATIVE_Z,
+];
+
+/// What a cube map was created from, kept to recreate it after the context
+/// is lost.
+#[derive(Clone, Debug)]
+enum CubeSource {
+    Faces(Box<[Pixels; 6]>),
+    /// The pixels of an equirectangular texture, projected again on restore.
+    Equirectangular {
+        pixels: Pixels,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        sampler: SamplerSettings,
+    },
+}
+
+/// Compares by identity, like `Texture2D`.
+#[derive(Debug)]
+pub struct TextureCube {
+    pub gl_texture: GlResource<WebGlTexture>,
+    /// Width and height of a face.
+    pub size: u32,
+    pub format: TextureFormat,
+    sampler: SamplerSettings,
+    source: CubeSource,
+}
+
+impl PartialEq for TextureCube {
+    fn eq(&self, other: &TextureCube) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl TextureCube {
+    /// Clamped trilinear sampling, which avoids seams between faces.
+    pub fn default_sampler() -> SamplerSettings {
+        SamplerSettings {
+            wrap_s: Wrap::ClampToEdge,
+            wrap_t: Wrap::ClampToEdge,
+            ..SamplerSettings::default()
+        }
+    }
+
+    /// Creates a cube map from six square faces ordered +X, -X, +Y, -Y, +Z,
+    /// -Z.
+    pub fn from_faces(
+        env: &GlEnvironment,
+        size: u32,
+        format: TextureFormat,
+        faces: [TextureSource; 6],
+        sampler: SamplerSettings,
+    ) -> Result<TextureCube, EngineError> {
+        let context = &env.context;
+        let source =
+            CubeSource::Faces(Box::new(faces.each_ref().map(Pixels::retain)));
+        let target = WebGl2RenderingContext::TEXTURE_CUBE_MAP;
+        let gl_texture = env.resources.create::<WebGlTexture>()?;
+        context.bind_texture(target, Some(&gl_texture));
+        let uploaded =
+            CUBE_FACES.iter().zip(faces).try_for_each(|(face, source)| {
+                upload(context, *face, size, size, format, source)
+            });
+        context.bind_texture(target, None);
+        uploaded?;
+
+        let cube = TextureCube::with_texture(
+            env, gl_texture, size, format, sampler, source,
+        );
+        if cube.sampler.uses_mipmaps() {
+            cube.generate_mipmaps(context);
+        }
+        Ok(cube)
+    }
+
+    /// Creates a cube map from six loaded images ordered +X, -X, +Y, -Y,
+    /// +Z, -Z.
+    pub fn from_images(
+        env: &GlEnvironment,
+        images: [&HtmlImageElement; 6],
+        format: TextureFormat,
+        sampler: SamplerSettings,
+    ) -> Result<TextureCube, EngineError> {
+        let size = images[0].natural_width();
+        for image in images.iter() {
+            if !image.complete() || image.natural_width() == 0 {
+                return Err(EngineError::InvalidTexture(String::from(
+                    "Image is not loaded.",
+                )));
+            }
+            if image.natural_width() != size || image.natural_height() != size {
+                return Err(EngineError::InvalidTexture(String::from(
+                    "Cube map faces must be squares of the same size.",
+                )));
+            }
+        }
+        TextureCube::from_faces(
+            env,
+            size,
+            format,
+            images.map(TextureSource::Image),
+            sampler,
+        )
+    }
+
+    /// Projects an equirectangular (latitude-longitude) texture onto the
+    /// faces of a new cube map, on the GPU.
+    pub fn from_equirectangular(
+        env: &GlEnvironment,
+        equirectangular: &Texture2D,
+        size: u32,
+        format: TextureFormat,
+        sampler: SamplerSettings,
+    ) -> Result<TextureCube, EngineError> {
+        let context = &env.context;
+        if matches!(format, TextureFormat::Rgba16F | TextureFormat::Rgba32F)
+            && !env.color_buffer_float
+        {
+            return Err(EngineError::Unsupported(String::from(
+                "Rendering to float textures is not supported.",
+            )));
+        }
+        let program = env.program(EQUIRECT_TO_CUBE_PROGRAM)?;
+
+        let target = WebGl2RenderingContext::TEXTURE_CUBE_MAP;
+        let gl_texture = env.resources.create::<WebGlTexture>()?;
+        let (internal_format, _, _) = format.gl_formats();
+        context.bind_texture(target, Some(&gl_texture));
+        context.tex_storage_2d(
+            target,
+            mip_levels(size) as i32,
+            internal_format as u32,
+            size as i32,
+            size as i32,
+        );
+        context.bind_texture(target, None);
+
+        let framebuffer = env.resources.create::<WebGlFramebuffer>()?;
+        let viewport = get_viewport(context);
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&framebuffer),
+        );
+        context.viewport(0, 0, size as i32, size as i32);
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::full_screen());
+        context.use_program(Some(&program.gl_program));
+        equirectangular.bind(context, 0);
+        context.uniform1i(program.uniform_location("equirectangularMap"), 0);
+
+        let mut result = Ok(());
+        for (index, face) in CUBE_FACES.iter().enumerate() {
+            context.framebuffer_texture_2d(
+                WebGl2RenderingContext::FRAMEBUFFER,
+                WebGl2RenderingContext::COLOR_ATTACHMENT0,
+                *face,
+                Some(&gl_texture),
+                0,
+            );
+            let status = context
+                .check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER);
+            if status != WebGl2RenderingContext::FRAMEBUFFER_COMPLETE {
+                result = Err(EngineError::IncompleteFramebuffer(status));
+                break;
+            }
+            context.uniform1i(program.uniform_location("face"), index as i32);
+            context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        }
+
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        set_viewport(context, viewport);
+        result?;
+
+        let source = CubeSource::Equirectangular {
+            pixels: equirectangular.pixels().clone(),
+            width: equirectangular.width,
+            height: equirectangular.height,
+            format: equirectangular.format,
+            sampler: equirectangular.sampler(),
+        };
+        let cube = TextureCube::with_texture(
+            env, gl_texture, size, format, sampler, source,
+        );
+        if cube.sampler.uses_mipmaps() {
+            cube.generate_mipmaps(context);
+        }
+        Ok(cube)
+    }
+
+    fn with_texture(
+        env: &GlEnvironment,
+        mut gl_texture: GlResource<WebGlTexture>,
+        size: u32,
+        format: TextureFormat,
+        sampler: SamplerSettings,
+        source: CubeSource,
+    ) -> TextureCube {
+        let sampler = supported_sampler(env, format, sampler);
+        gl_texture.set_bytes(
+            6 * texture_bytes(size, size, format, sampler.uses_mipmaps()),
+        );
+        let mut cube = TextureCube {
+            gl_texture,
+            size,
+            format,
+            sampler,
+            source,
+        };
+        cube.set_sampler(env, sampler);
+        cube
+    }
+
+    /// Creates a cube map again from the faces or equirectangular pixels it
+    /// was made from.
+    pub fn recreate(
+        &self,
+        env: &GlEnvironment,
+    ) -> Result<TextureCube, EngineError> {
+        match &self.source {
+            CubeSource::Faces(faces) => TextureCube::from_faces(
+                env,
+                self.size,
+                self.format,
+                faces.each_ref().map(Pixels::source),
+                self.sampler,
+            ),
+            CubeSource::Equirectangular {
+                pixels,
+                width,
+                height,
+                format,
+                sampler,
+            } => {
+                let equirectangular = Texture2D::new(
+                    env,
+                    *width,
+                    *height,
+                    *format,
+                    pixels.source(),
+                    *sampler,
+                )?;
+                TextureCube::from_equirectangular(
+                    env,
+                    &equirectangular,
+                    self.size,
+                    self.format,
+                    self.sampler,
+                )
+            }
+        }
+    }
+
+    pub fn sampler(&self) -> SamplerSettings {
+        self.sampler
+    }
+
+    pub fn set_sampler(
+        &mut self,
+        env: &GlEnvironment,
+        sampler: SamplerSettings,
+    ) {
+        apply_sampler(
+            env,
+            WebGl2RenderingContext::TEXTURE_CUBE_MAP,
+            &self.gl_texture,
+            sampler,
+        );
+        self.sampler = sampler;
+    }
+
+    /// Number of mipmap levels of a complete mipmap chain. Rough materials
+    /// sample the upper levels to blur reflections.
+    pub fn mip_levels(&self) -> u32 {
+        mip_levels(self.size)
+    }
+
+    pub fn generate_mipmaps(&self, context: &WebGl2RenderingContext) {
+        let target = WebGl2RenderingContext::TEXTURE_CUBE_MAP;
+        context.bind_texture(target, Some(&self.gl_texture));
+        context.generate_mipmap(target);
+        context.bind_texture(target, None);
+    }
+
+    /// Binds the cube map to texture unit `unit`.
+    pub fn bind(&self, context: &WebGl2RenderingContext, unit: u32) {
+        context.active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
+        context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_CUBE_MAP,
+            Some(&self.gl_texture),
+        );
+    }
+}
+
+fn mip_levels(size: u32) -> u32 {
+    32 - size.max(1).leading_zeros()
+}
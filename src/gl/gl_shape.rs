@@ -1,30 +1,62 @@
-use std::convert::TryInto;
+use std::rc::Rc;
 
 use web_sys::WebGl2RenderingContext;
 use web_sys::WebGlBuffer;
 use web_sys::WebGlVertexArrayObject;
 
+use crate::camera::DEFAULT_LAYERS;
 use crate::environment::GlEnvironment;
+use crate::error::EngineError;
+use crate::gl::instances::{InstanceBuffer, Instances};
+use crate::gl::mesh::Mesh;
+use crate::gl::resources::GlResource;
 use crate::linear_transform::LinearTransform;
-use crate::utils::Buffer;
+use crate::material::{AlphaMode, Material};
+use crate::math::bounds::Aabb;
 
-static POSITION_DATA_SIZE: usize = 3;
-static COLOR_DATA_SIZE: usize = 4;
-static VERTEX_DATA_SIZE: usize = POSITION_DATA_SIZE + COLOR_DATA_SIZE;
-static BYTES_PER_FLOAT: usize = 4;
+pub const POSITION_LOCATION: u32 = 0;
+pub const COLOR_LOCATION: u32 = 1;
+pub const NORMAL_LOCATION: u32 = 2;
+pub const UV_LOCATION: u32 = 3;
+/// First of the four locations of the instance matrix columns.
+pub const INSTANCE_MATRIX_LOCATION: u32 = 4;
+pub const INSTANCE_COLOR_LOCATION: u32 = 8;
+pub const INSTANCE_DATA_LOCATION: u32 = 9;
 
+/// Attribute locations bound to every program before linking.
+pub static ATTRIBUTE_LOCATIONS: [(&str, u32); 7] = [
+    ("position", POSITION_LOCATION),
+    ("color", COLOR_LOCATION),
+    ("normal", NORMAL_LOCATION),
+    ("uv", UV_LOCATION),
+    ("instanceMatrix", INSTANCE_MATRIX_LOCATION),
+    ("instanceColor", INSTANCE_COLOR_LOCATION),
+    ("instanceData", INSTANCE_DATA_LOCATION),
+];
+
+/// A mesh drawn with a transform and a material.
 pub struct GlShape {
-    pub vao: WebGlVertexArrayObject,
-    pub gl_array_buffer: WebGlBuffer,
-    pub gl_element_buffer: Option<WebGlBuffer>,
-    pub mode: u32,
-    pub vertices_count: usize,
-    pub array_buffer: Vec<f32>,
-    pub element_array: Option<Vec<u32>>,
+    /// Possibly shared with other shapes.
+    pub mesh: Rc<Mesh>,
+    /// Reads the vertices of the mesh and the instances of the shape, when
+    /// instanced. Other shapes use the vertex array of their mesh.
+    instance_vao: Option<GlResource<WebGlVertexArrayObject>>,
     pub transform: LinearTransform,
-
-    positions: Buffer<Vec<f32>>,
-    colors: Buffer<Vec<f32>>,
+    pub material: Material,
+    /// Overrides the alpha mode of the material.
+    pub alpha_mode: Option<AlphaMode>,
+    pub cast_shadows: bool,
+    pub receive_shadows: bool,
+    /// When set, the shape is drawn once per instance.
+    pub instances: Option<InstanceBuffer>,
+    /// Whether the shape never moves, so that it can be merged with other
+    /// static shapes, see `StaticBatches`.
+    pub is_static: bool,
+    /// Layers the shape is on, one per bit. Cameras only draw the shapes
+    /// on a layer of their `layer_mask`.
+    pub layers: u32,
+    /// Whether the shape is drawn at all, in any view or pass.
+    pub visible: bool,
 }
 
 pub struct ShapeProps<'a> {
@@ -33,193 +65,154 @@ pub struct ShapeProps<'a> {
     pub vertices_count: usize,
     pub positions: Vec<f32>,
     pub colors: Vec<f32>,
+    pub normals: Vec<f32>,
+    pub uvs: Vec<f32>,
     pub indices: Option<Vec<u32>>, // if using element array buffer
 }
 
 impl GlShape {
-    fn pack_attributes(&mut self) {
-        if !self.positions.is_dirty && !self.colors.is_dirty {
-            return;
-        }
-        #[cfg_attr(rustfmt, rustfmt_skip)]
-        for i in 0..self.vertices_count {
-            let buf_pos_start: usize = i * VERTEX_DATA_SIZE;
-            let buf_pos_end: usize = buf_pos_start + POSITION_DATA_SIZE;
-            let pos_start: usize = i * POSITION_DATA_SIZE;
-            let pos_end: usize = pos_start + POSITION_DATA_SIZE;
-            let buf_color_start: usize = i * VERTEX_DATA_SIZE + POSITION_DATA_SIZE;
-            let buf_color_end: usize = buf_color_start + COLOR_DATA_SIZE;
-            let color_start: usize = i * COLOR_DATA_SIZE;
-            let color_end: usize = color_start + COLOR_DATA_SIZE;
-            if self.positions.is_dirty {
-                self.array_buffer[buf_pos_start..buf_pos_end].copy_from_slice(&self.positions.buffer[pos_start..pos_end]);
-            }
-            if self.colors.is_dirty {
-                self.array_buffer[buf_color_start..buf_color_end].copy_from_slice(&self.colors.buffer[color_start..color_end]);
-            }
+    pub fn prepare_for_render(&mut self, context: &WebGl2RenderingContext) {
+        self.transform.update_matrix();
+        if let Some(instances) = &mut self.instances {
+            instances.upload(context, &self.mesh.bounds);
         }
-        self.positions.is_dirty = false;
-        self.colors.is_dirty = false
     }
 
-    pub fn buffer_needs_update(&self) -> bool {
-        self.positions.is_dirty || self.colors.is_dirty
+    /// Whether the shape is drawn by a pass drawing the layers of `mask`.
+    pub fn is_drawn_on(&self, mask: u32) -> bool {
+        self.visible && self.layers & mask != 0
     }
 
-    pub fn prepare_for_render(&mut self, context: &WebGl2RenderingContext) {
-        self.transform.update_matrix();
-        if self.buffer_needs_update() {
-            self.pack_attributes();
-            with_buffer(
-                WebGl2RenderingContext::ARRAY_BUFFER,
-                context,
-                &self.gl_array_buffer,
-                |_buffer| {
-                    update_buffer(context, _buffer, &self.array_buffer);
-                },
-            );
-        }
+    pub fn new(props: ShapeProps) -> Result<GlShape, EngineError> {
+        Ok(GlShape::with_mesh(Rc::new(Mesh::new(props)?)))
     }
 
-    pub fn new(props: ShapeProps) -> Result<GlShape, String> {
-        assert!(
-            props.positions.len() == POSITION_DATA_SIZE * props.vertices_count,
-            "positions size = {}, expected {}",
-            props.positions.len(),
-            POSITION_DATA_SIZE * props.vertices_count
-        );
-
-        assert!(
-            props.colors.len() == COLOR_DATA_SIZE * props.vertices_count,
-            "colors size = {}, expected {}",
-            props.colors.len(),
-            COLOR_DATA_SIZE * props.vertices_count
-        );
+    /// Creates a shape from vertices interleaved like `Mesh::array_buffer`.
+    pub fn from_array_buffer(
+        env: &GlEnvironment,
+        mode: u32,
+        array_buffer: Vec<f32>,
+        indices: Option<Vec<u32>>,
+    ) -> Result<GlShape, EngineError> {
+        let mesh = Mesh::from_array_buffer(env, mode, array_buffer, indices)?;
+        Ok(GlShape::with_mesh(Rc::new(mesh)))
+    }
 
-        let vao = props
-            .env
-            .context
-            .create_vertex_array()
-            .ok_or("Could not create vertex array object")?;
-        let gl_array_buffer = create_buffer(&props.env)?;
+    /// Creates an untransformed shape drawing `mesh`, which other shapes
+    /// can share.
+    pub fn with_mesh(mesh: Rc<Mesh>) -> GlShape {
+        GlShape {
+            mesh,
+            instance_vao: None,
+            transform: LinearTransform::new(),
+            material: Material::default(),
+            alpha_mode: None,
+            cast_shadows: true,
+            receive_shadows: true,
+            instances: None,
+            is_static: false,
+            layers: DEFAULT_LAYERS,
+            visible: true,
+        }
+    }
 
-        let gl_element_buffer = props
-            .indices
-            .as_ref()
-            .and(Some(create_buffer(&props.env).unwrap()));
+    /// Replaces the mesh of the shape with `mesh`, recreated from it after
+    /// the context was restored, and restores the instances.
+    pub fn restore(
+        &mut self,
+        env: &GlEnvironment,
+        mesh: Rc<Mesh>,
+    ) -> Result<(), EngineError> {
+        self.mesh = mesh;
+        if let Some(instances) = &mut self.instances {
+            let vao = instance_vertex_array(env, &self.mesh)?;
+            env.context.bind_vertex_array(Some(&vao));
+            let restored = instances.restore(&env.resources);
+            env.context.bind_vertex_array(None);
+            self.instance_vao = Some(vao);
+            restored?;
+        }
+        Ok(())
+    }
 
-        with_vao(&props.env.context, &vao, |_| {
-            if let Some(element_buffer) = &gl_element_buffer {
-                props.env.context.bind_buffer(
-                    WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
-                    Some(&element_buffer),
-                );
-            }
-            with_buffer(
-                WebGl2RenderingContext::ARRAY_BUFFER,
-                &props.env.context,
-                &gl_array_buffer,
-                |_buffer: &WebGlBuffer| {
-                    init_attributes(&props.env, _buffer);
-                },
-            )
-        });
+    /// Appends the vertices of the shape, transformed to world space, to
+    /// `vertices` and its triangles to `indices`.
+    pub fn append_transformed(
+        &self,
+        vertices: &mut Vec<f32>,
+        indices: &mut Vec<u32>,
+    ) {
+        self.mesh.append_transformed(
+            self.transform.get_matrix(),
+            vertices,
+            indices,
+        );
+    }
 
-        if let Some(element_buffer) = &gl_element_buffer {
-            with_buffer(
-                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
-                &props.env.context,
-                &element_buffer,
-                |_buffer| {
-                    update_element_buffer(
-                        &props.env.context,
-                        _buffer,
-                        props.indices.as_ref().unwrap(),
-                    );
-                },
-            );
+    /// Vertex array object the shape is drawn with.
+    pub fn vertex_array(&self) -> &WebGlVertexArrayObject {
+        match &self.instance_vao {
+            Some(vao) => vao,
+            None => self.mesh.vertex_array(),
         }
+    }
 
-        let buffer_size: usize = (VERTEX_DATA_SIZE * props.vertices_count)
-            .try_into()
-            .unwrap();
+    /// Alpha mode the shape is drawn with.
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode.unwrap_or(self.material.alpha_mode)
+    }
 
-        Ok(GlShape {
-            vao,
-            gl_array_buffer,
-            gl_element_buffer: gl_element_buffer,
-            mode: props.mode,
-            vertices_count: props.vertices_count,
-            array_buffer: vec![0.0; buffer_size],
-            element_array: props.indices,
-            positions: Buffer {
-                buffer: props.positions,
-                is_dirty: true,
-            },
-            colors: Buffer {
-                buffer: props.colors,
-                is_dirty: true,
-            },
-            transform: LinearTransform::new(),
-        })
+    /// Draws the shape once per instance of `instances_mut`, starting with
+    /// none. Does nothing if the shape is already instanced.
+    pub fn enable_instancing(
+        &mut self,
+        env: &GlEnvironment,
+    ) -> Result<(), EngineError> {
+        if self.instances.is_none() {
+            let vao = instance_vertex_array(env, &self.mesh)?;
+            env.context.bind_vertex_array(Some(&vao));
+            let instances = InstanceBuffer::new(&env.resources);
+            env.context.bind_vertex_array(None);
+            self.instances = Some(instances?);
+            self.instance_vao = Some(vao);
+        }
+        Ok(())
     }
-}
 
-fn init_attributes(env: &GlEnvironment, buffer: &WebGlBuffer) {
-    let stride = VERTEX_DATA_SIZE * BYTES_PER_FLOAT;
-    configure_attribute(
-        &env,
-        &buffer,
-        "position",
-        POSITION_DATA_SIZE as i32,
-        stride as i32,
-        0,
-    );
-    configure_attribute(
-        &env,
-        &buffer,
-        "color",
-        COLOR_DATA_SIZE as i32,
-        stride as i32,
-        (POSITION_DATA_SIZE * BYTES_PER_FLOAT) as i32,
-    );
-}
+    pub fn instances_mut(&mut self) -> Option<&mut Instances> {
+        self.instances
+            .as_mut()
+            .map(|instances| &mut instances.instances)
+    }
 
-fn create_buffer(env: &GlEnvironment) -> Result<WebGlBuffer, String> {
-    let buffer = env
-        .context
-        .create_buffer()
-        .ok_or("Failed to create buffer")?;
-    Ok(buffer)
+    /// Bounds of the shape in world space, covering all of its instances,
+    /// as of the last `prepare_for_render`.
+    pub fn world_bounds(&self) -> Aabb {
+        self.instances
+            .as_ref()
+            .and_then(|instances| instances.bounds())
+            .unwrap_or(self.mesh.bounds)
+            .transform(self.transform.get_matrix())
+    }
 }
 
-fn configure_attribute(
+/// New vertex array object reading `mesh`, to which instance attributes
+/// are then added.
+fn instance_vertex_array(
     env: &GlEnvironment,
-    buffer: &WebGlBuffer,
-    name: &str,
-    size: i32,
-    stride: i32,
-    offset: i32,
-) {
-    let attribute_location = env
-        .context
-        .get_attrib_location(&env.program.gl_program, name);
-    env.context.vertex_attrib_pointer_with_i32(
-        attribute_location as u32,
-        size,
-        WebGl2RenderingContext::FLOAT,
-        false,
-        stride,
-        offset,
-    );
-    env.context
-        .enable_vertex_attrib_array(attribute_location as u32);
+    mesh: &Mesh,
+) -> Result<GlResource<WebGlVertexArrayObject>, EngineError> {
+    let vao = env.resources.create::<WebGlVertexArrayObject>()?;
+    with_vao(&env.context, &vao, |_| {
+        mesh.bind_to_vertex_array(&env.context)
+    });
+    Ok(vao)
 }
 
 pub fn update_buffer(
     context: &WebGl2RenderingContext,
     buffer: &WebGlBuffer,
-    data: &Vec<f32>,
+    data: &[f32],
 ) {
     // Note that `Float32Array::view` is somewhat dangerous (hence the
     // `unsafe`!). This is creating a raw view into our module's
@@ -244,7 +237,7 @@ pub fn update_buffer(
 pub fn update_element_buffer(
     context: &WebGl2RenderingContext,
     buffer: &WebGlBuffer,
-    data: &Vec<u32>,
+    data: &[u32],
 ) {
     unsafe {
         let positions_array_buf_view = js_sys::Uint32Array::view(data);
@@ -265,7 +258,7 @@ pub fn with_buffer<F>(
     F: Fn(&WebGlBuffer),
 {
     context.bind_buffer(target, Some(buffer));
-    func(&buffer);
+    func(buffer);
     context.bind_buffer(target, None);
 }
 
@@ -276,7 +269,7 @@ pub fn with_vao<F>(
 ) where
     F: Fn(&WebGlVertexArrayObject),
 {
-    context.bind_vertex_array(Some(&vao));
-    func(&vao);
+    context.bind_vertex_array(Some(vao));
+    func(vao);
     context.bind_vertex_array(None);
 }
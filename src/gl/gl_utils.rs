@@ -1,100 +1,270 @@
 use std::collections::HashMap;
 
-use js_sys::Object;
-use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{
-    WebGl2RenderingContext, WebGlProgram, WebGlShader, WebGlUniformLocation,
+    HtmlCanvasElement, OffscreenCanvas, WebGl2RenderingContext,
+    WebGlContextAttributes, WebGlPowerPreference, WebGlProgram, WebGlShader,
+    WebGlUniformLocation,
 };
 
-static DEFAULT_VERTEX_SHADER: &str = r##"#version 300 es
-in vec3 position;
-in vec4 color;
-out vec4 vColor;
+use crate::deferred::{DEFERRED_EMISSION_PROGRAM, DEFERRED_LIGHT_PROGRAM};
+use crate::error::EngineError;
+use crate::gl::cubemap::EQUIRECT_TO_CUBE_PROGRAM;
+use crate::gl::gl_shape::ATTRIBUTE_LOCATIONS;
+use crate::gl::resources::{GlResource, Resources};
+use crate::gl::shaders::{
+    resolve_includes, BLINN_PHONG_FRAGMENT_SHADER,
+    BLOOM_COMBINE_FRAGMENT_SHADER, BLOOM_THRESHOLD_FRAGMENT_SHADER,
+    BLUR_FRAGMENT_SHADER, DEFERRED_EMISSION_FRAGMENT_SHADER,
+    DEFERRED_LIGHT_FRAGMENT_SHADER, DEFERRED_LIGHT_VERTEX_SHADER,
+    DEPTH_FRAGMENT_SHADER, DEPTH_VERTEX_SHADER,
+    EQUIRECT_TO_CUBE_FRAGMENT_SHADER, FULLSCREEN_VERTEX_SHADER,
+    FXAA_FRAGMENT_SHADER, LIT_VERTEX_SHADER, NORMAL_DEPTH_FRAGMENT_SHADER,
+    NORMAL_DEPTH_VERTEX_SHADER, OIT_COMPOSITE_FRAGMENT_SHADER,
+    PBR_FRAGMENT_SHADER, SKYBOX_FRAGMENT_SHADER, SKYBOX_VERTEX_SHADER,
+    SRGB_FRAGMENT_SHADER, SSAO_BLUR_FRAGMENT_SHADER,
+    SSAO_DEBUG_FRAGMENT_SHADER, SSAO_FRAGMENT_SHADER, TEXTURED_FRAGMENT_SHADER,
+    TONE_MAPPING_FRAGMENT_SHADER, UNLIT_FRAGMENT_SHADER, UNLIT_VERTEX_SHADER,
+    VIGNETTE_FRAGMENT_SHADER,
+};
+use crate::gl::texture::MAX_TEXTURE_MAX_ANISOTROPY_EXT;
+use crate::gl::uniform_buffer::bind_uniform_blocks;
+use crate::material::{
+    BLINN_PHONG_PROGRAM, PBR_PROGRAM, TEXTURED_PROGRAM, UNLIT_PROGRAM,
+};
+use crate::oit::OIT_COMPOSITE_PROGRAM;
+use crate::post_process::{
+    BLOOM_COMBINE_PROGRAM, BLOOM_THRESHOLD_PROGRAM, BLUR_PROGRAM, FXAA_PROGRAM,
+    SRGB_PROGRAM, TONE_MAPPING_PROGRAM, VIGNETTE_PROGRAM,
+};
+use crate::renderer::DEPTH_PROGRAM;
+use crate::skybox::SKYBOX_PROGRAM;
+use crate::ssao::{
+    NORMAL_DEPTH_PROGRAM, SSAO_BLUR_PROGRAM, SSAO_DEBUG_PROGRAM, SSAO_PROGRAM,
+};
 
-uniform mat4 modelMatrix;
-uniform mat4 viewMatrix;
-uniform mat4 projectionMatrix;
+/// Linked program, deleted on drop.
+pub struct Program {
+    pub gl_program: GlResource<WebGlProgram>,
+    pub uniform_locations: HashMap<String, WebGlUniformLocation>,
+}
 
-void main() {
-    vec4 worldPosition = projectionMatrix * viewMatrix * modelMatrix * vec4(position, 1.0);
-    gl_Position = worldPosition;
-    vColor = color;
+impl Program {
+    pub fn uniform_location(
+        &self,
+        name: &str,
+    ) -> Option<&WebGlUniformLocation> {
+        self.uniform_locations.get(name)
+    }
 }
-"##;
 
-static DEFAULT_FRAGMENT_SHADER: &str = r##"#version 300 es
-precision highp float;
-in vec4 vColor;
-out vec4 outColor;
+/// Compile-time switches of the material shaders, each one turned into a
+/// `#define`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ShaderFeatures {
+    /// Outputs weighted color and revealage for order-independent
+    /// transparency rather than a color.
+    pub weighted_blended_oit: bool,
+    /// Writes the surface to the deferred G-buffer rather than shading it.
+    pub deferred: bool,
+}
 
-void main() {
-    outColor = vColor;
-    //outColor = vec4(1.0, 0.0, 0.0, 1.0);
+impl ShaderFeatures {
+    pub fn defines(&self) -> Vec<&'static str> {
+        let mut defines = Vec::new();
+        if self.weighted_blended_oit {
+            defines.push("WEIGHTED_BLENDED_OIT");
+        }
+        if self.deferred {
+            defines.push("DEFERRED");
+        }
+        defines
+    }
 }
-"##;
 
-pub struct Program {
-    pub gl_program: WebGlProgram,
-    pub vert_shader: WebGlShader,
-    pub frag_shader: WebGlShader,
-    pub uniform_locations: HashMap<String, WebGlUniformLocation>,
+/// Names and vertex and fragment sources of the material programs, which
+/// can be compiled with any `ShaderFeatures`.
+pub fn material_programs() -> [(&'static str, &'static str, &'static str); 4] {
+    [
+        (UNLIT_PROGRAM, UNLIT_VERTEX_SHADER, UNLIT_FRAGMENT_SHADER),
+        (
+            BLINN_PHONG_PROGRAM,
+            LIT_VERTEX_SHADER,
+            BLINN_PHONG_FRAGMENT_SHADER,
+        ),
+        (PBR_PROGRAM, LIT_VERTEX_SHADER, PBR_FRAGMENT_SHADER),
+        (
+            TEXTURED_PROGRAM,
+            LIT_VERTEX_SHADER,
+            TEXTURED_FRAGMENT_SHADER,
+        ),
+    ]
 }
 
-pub fn create_default_program(
-    context: &WebGl2RenderingContext,
-) -> Result<Program, String> {
+/// Compiles the programs used by the built-in materials, keyed by
+/// `Material::program_name`, and by the built-in passes.
+pub fn create_default_programs(
+    resources: &Resources,
+) -> Result<HashMap<&'static str, Program>, EngineError> {
+    let mut programs = HashMap::new();
+    for (name, vert_source, frag_source) in material_programs() {
+        programs
+            .insert(name, create_program(resources, vert_source, frag_source)?);
+    }
+    programs.insert(
+        SKYBOX_PROGRAM,
+        create_program(
+            resources,
+            SKYBOX_VERTEX_SHADER,
+            SKYBOX_FRAGMENT_SHADER,
+        )?,
+    );
+    programs.insert(
+        EQUIRECT_TO_CUBE_PROGRAM,
+        create_program(
+            resources,
+            FULLSCREEN_VERTEX_SHADER,
+            EQUIRECT_TO_CUBE_FRAGMENT_SHADER,
+        )?,
+    );
+    programs.insert(
+        DEPTH_PROGRAM,
+        create_program(resources, DEPTH_VERTEX_SHADER, DEPTH_FRAGMENT_SHADER)?,
+    );
+    programs.insert(
+        DEFERRED_LIGHT_PROGRAM,
+        create_program(
+            resources,
+            DEFERRED_LIGHT_VERTEX_SHADER,
+            DEFERRED_LIGHT_FRAGMENT_SHADER,
+        )?,
+    );
+    programs.insert(
+        NORMAL_DEPTH_PROGRAM,
+        create_program(
+            resources,
+            NORMAL_DEPTH_VERTEX_SHADER,
+            NORMAL_DEPTH_FRAGMENT_SHADER,
+        )?,
+    );
+    for (name, frag_source) in [
+        (OIT_COMPOSITE_PROGRAM, OIT_COMPOSITE_FRAGMENT_SHADER),
+        (TONE_MAPPING_PROGRAM, TONE_MAPPING_FRAGMENT_SHADER),
+        (SRGB_PROGRAM, SRGB_FRAGMENT_SHADER),
+        (FXAA_PROGRAM, FXAA_FRAGMENT_SHADER),
+        (BLOOM_THRESHOLD_PROGRAM, BLOOM_THRESHOLD_FRAGMENT_SHADER),
+        (BLUR_PROGRAM, BLUR_FRAGMENT_SHADER),
+        (BLOOM_COMBINE_PROGRAM, BLOOM_COMBINE_FRAGMENT_SHADER),
+        (VIGNETTE_PROGRAM, VIGNETTE_FRAGMENT_SHADER),
+        (DEFERRED_EMISSION_PROGRAM, DEFERRED_EMISSION_FRAGMENT_SHADER),
+        (SSAO_PROGRAM, SSAO_FRAGMENT_SHADER),
+        (SSAO_BLUR_PROGRAM, SSAO_BLUR_FRAGMENT_SHADER),
+        (SSAO_DEBUG_PROGRAM, SSAO_DEBUG_FRAGMENT_SHADER),
+    ] {
+        programs.insert(
+            name,
+            create_program(resources, FULLSCREEN_VERTEX_SHADER, frag_source)?,
+        );
+    }
+    Ok(programs)
+}
+
+pub fn create_program(
+    resources: &Resources,
+    vert_source: &str,
+    frag_source: &str,
+) -> Result<Program, EngineError> {
+    create_program_with_defines(resources, vert_source, frag_source, &[])
+}
+
+/// Compiles a program with `#define`s inserted after the `#version`
+/// directive of both shaders.
+pub fn create_program_with_defines(
+    resources: &Resources,
+    vert_source: &str,
+    frag_source: &str,
+    defines: &[&str],
+) -> Result<Program, EngineError> {
+    let context = resources.context();
+    let vert_source = add_defines(&resolve_includes(vert_source)?, defines);
+    let frag_source = add_defines(&resolve_includes(frag_source)?, defines);
     let vert_shader = compile_shader(
-        &context,
+        context,
         WebGl2RenderingContext::VERTEX_SHADER,
-        DEFAULT_VERTEX_SHADER,
+        &vert_source,
     )?;
-
-    let frag_shader = compile_shader(
-        &context,
+    let program = compile_shader(
+        context,
         WebGl2RenderingContext::FRAGMENT_SHADER,
-        DEFAULT_FRAGMENT_SHADER,
-    )?;
-
-    let program = link_program(context, &vert_shader, &frag_shader)?;
-
-    let mut uniform_locations = HashMap::new();
-    uniform_locations.insert(
-        String::from("modelMatrix"),
-        context
-            .get_uniform_location(&program, "modelMatrix")
-            .unwrap(),
-    );
-
-    uniform_locations.insert(
-        String::from("viewMatrix"),
-        context
-            .get_uniform_location(&program, "viewMatrix")
-            .unwrap(),
-    );
-
-    uniform_locations.insert(
-        String::from("projectionMatrix"),
-        context
-            .get_uniform_location(&program, "projectionMatrix")
-            .unwrap(),
-    );
+        &frag_source,
+    )
+    .and_then(|frag_shader| {
+        let program = link_program(resources, &vert_shader, &frag_shader);
+        context.delete_shader(Some(&frag_shader));
+        program
+    });
+    // Linked programs keep their shaders until they are deleted.
+    context.delete_shader(Some(&vert_shader));
+    let program = program?;
+    bind_uniform_blocks(context, &program);
+    let uniform_locations = get_uniform_locations(context, &program);
 
     Ok(Program {
         gl_program: program,
-        vert_shader,
-        frag_shader,
         uniform_locations,
     })
 }
 
+fn add_defines(source: &str, defines: &[&str]) -> String {
+    let (version, rest) = source.split_once('\n').unwrap_or((source, ""));
+    let mut result = String::with_capacity(source.len() + defines.len() * 32);
+    result.push_str(version);
+    result.push('\n');
+    for define in defines {
+        result.push_str("#define ");
+        result.push_str(define);
+        result.push('\n');
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Looks up every active uniform of `program`. Arrays are registered under
+/// their base name (`lightColors` rather than `lightColors[0]`) so they can
+/// be uploaded in a single call.
+fn get_uniform_locations(
+    context: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+) -> HashMap<String, WebGlUniformLocation> {
+    let count = context
+        .get_program_parameter(program, WebGl2RenderingContext::ACTIVE_UNIFORMS)
+        .as_f64()
+        .unwrap_or(0.0) as u32;
+
+    let mut uniform_locations = HashMap::new();
+    for index in 0..count {
+        let info = match context.get_active_uniform(program, index) {
+            Some(info) => info,
+            None => continue,
+        };
+        let name = info.name();
+        let name = name.strip_suffix("[0]").unwrap_or(&name);
+        if let Some(location) = context.get_uniform_location(program, name) {
+            uniform_locations.insert(String::from(name), location);
+        }
+    }
+    uniform_locations
+}
+
 pub fn compile_shader(
     context: &WebGl2RenderingContext,
     shader_type: u32,
     source: &str,
-) -> Result<WebGlShader, String> {
-    let shader = context
-        .create_shader(shader_type)
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+) -> Result<WebGlShader, EngineError> {
+    let shader = context.create_shader(shader_type).ok_or_else(|| {
+        EngineError::Context(String::from("Unable to create shader object"))
+    })?;
     context.shader_source(&shader, source);
     context.compile_shader(&shader);
 
@@ -105,23 +275,29 @@ pub fn compile_shader(
     {
         Ok(shader)
     } else {
-        Err(context
+        let log = context
             .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("Unknown error creating shader")))
+            .unwrap_or_else(|| String::from("Unknown error creating shader"));
+        context.delete_shader(Some(&shader));
+        Err(EngineError::shader_compile(log, source))
     }
 }
 
 fn link_program(
-    context: &WebGl2RenderingContext,
+    resources: &Resources,
     vert_shader: &WebGlShader,
     frag_shader: &WebGlShader,
-) -> Result<WebGlProgram, String> {
-    let gl_program = context
-        .create_program()
-        .ok_or_else(|| String::from("Unable to create shader object"))?;
+) -> Result<GlResource<WebGlProgram>, EngineError> {
+    let context = resources.context();
+    let gl_program = resources.create::<WebGlProgram>()?;
 
     context.attach_shader(&gl_program, vert_shader);
     context.attach_shader(&gl_program, frag_shader);
+    // Attribute locations are fixed so that a vertex array object can be
+    // drawn with any program.
+    for (name, location) in ATTRIBUTE_LOCATIONS.iter() {
+        context.bind_attrib_location(&gl_program, *location, name);
+    }
     context.link_program(&gl_program);
 
     if context
@@ -131,22 +307,187 @@ fn link_program(
     {
         Ok(gl_program)
     } else {
-        Err(context
-            .get_program_info_log(&gl_program)
-            .unwrap_or_else(|| {
-                String::from("Unknown error creating program object")
-            }))
+        let log =
+            context
+                .get_program_info_log(&gl_program)
+                .unwrap_or_else(|| {
+                    String::from("Unknown error creating program object")
+                });
+        Err(EngineError::ProgramLink { log })
+    }
+}
+
+/// GPU the browser should pick on systems with several.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PowerPreference {
+    Default,
+    HighPerformance,
+    LowPower,
+}
+
+/// Attributes the WebGL context is created with.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ContextAttributes {
+    /// Multisampling of the canvas. Render targets have their own samples.
+    pub antialias: bool,
+    /// Whether the canvas has an alpha channel, composited with the page.
+    pub alpha: bool,
+    /// Keeps the canvas content after it is presented, to read it back.
+    pub preserve_drawing_buffer: bool,
+    pub power_preference: PowerPreference,
+}
+
+#[wasm_bindgen]
+impl ContextAttributes {
+    /// The defaults of the browser.
+    pub fn new() -> ContextAttributes {
+        ContextAttributes {
+            antialias: true,
+            alpha: true,
+            preserve_drawing_buffer: false,
+            power_preference: PowerPreference::Default,
+        }
+    }
+}
+
+impl Default for ContextAttributes {
+    fn default() -> Self {
+        ContextAttributes::new()
     }
 }
 
-pub fn get_context() -> Result<WebGl2RenderingContext, Object> {
-    let document = web_sys::window().unwrap().document().unwrap();
-    let canvas = document.get_element_by_id("canvas").unwrap();
-    let canvas: web_sys::HtmlCanvasElement =
-        canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+/// Canvas an environment renders to.
+pub enum CanvasTarget {
+    /// Id of a canvas element of the document.
+    Id(String),
+    Element(HtmlCanvasElement),
+    /// A canvas detached from the document, possibly in a worker.
+    Offscreen(OffscreenCanvas),
+}
 
-    canvas
-        .get_context("webgl2")?
-        .unwrap()
+impl CanvasTarget {
+    /// Reads a canvas element, its id or an `OffscreenCanvas` passed from
+    /// JavaScript.
+    pub fn from_js(value: &JsValue) -> Result<CanvasTarget, EngineError> {
+        if let Some(id) = value.as_string() {
+            Ok(CanvasTarget::Id(id))
+        } else if let Some(canvas) = value.dyn_ref::<HtmlCanvasElement>() {
+            Ok(CanvasTarget::Element(canvas.clone()))
+        } else if let Some(canvas) = value.dyn_ref::<OffscreenCanvas>() {
+            Ok(CanvasTarget::Offscreen(canvas.clone()))
+        } else {
+            Err(EngineError::Context(String::from(
+                "Expected a canvas element, its id or an OffscreenCanvas.",
+            )))
+        }
+    }
+}
+
+/// Creates a WebGL 2 context on `target`. Each environment needs its own
+/// canvas, since a canvas only has one context.
+pub fn get_context(
+    target: &CanvasTarget,
+    attributes: &ContextAttributes,
+) -> Result<WebGl2RenderingContext, EngineError> {
+    let options = WebGlContextAttributes::new();
+    options.set_antialias(attributes.antialias);
+    options.set_alpha(attributes.alpha);
+    options.set_preserve_drawing_buffer(attributes.preserve_drawing_buffer);
+    options.set_power_preference(match attributes.power_preference {
+        PowerPreference::Default => WebGlPowerPreference::Default,
+        PowerPreference::HighPerformance => {
+            WebGlPowerPreference::HighPerformance
+        }
+        PowerPreference::LowPower => WebGlPowerPreference::LowPower,
+    });
+    let context = match target {
+        CanvasTarget::Id(id) => canvas_by_id(id)?
+            .get_context_with_context_options("webgl2", &options),
+        CanvasTarget::Element(canvas) => {
+            canvas.get_context_with_context_options("webgl2", &options)
+        }
+        CanvasTarget::Offscreen(canvas) => {
+            canvas.get_context_with_context_options("webgl2", &options)
+        }
+    };
+    context
+        .map_err(|error| {
+            EngineError::Context(format!(
+                "Could not create a WebGL 2 context: {:?}",
+                error
+            ))
+        })?
+        .ok_or_else(|| {
+            EngineError::Context(String::from(
+                "WebGL 2 is not supported, or the canvas already has \
+                 another kind of context.",
+            ))
+        })?
         .dyn_into::<WebGl2RenderingContext>()
+        .map_err(|_| {
+            EngineError::Context(String::from(
+                "The webgl2 context is not a WebGl2RenderingContext.",
+            ))
+        })
+}
+
+fn canvas_by_id(id: &str) -> Result<HtmlCanvasElement, EngineError> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| {
+            EngineError::Context(String::from(
+                "There is no document to find the canvas in, pass an \
+                 OffscreenCanvas instead.",
+            ))
+        })?;
+    document
+        .get_element_by_id(id)
+        .ok_or_else(|| {
+            EngineError::Context(format!("No element has id {}.", id))
+        })?
+        .dyn_into::<HtmlCanvasElement>()
+        .map_err(|_| {
+            EngineError::Context(format!("Element {} is not a canvas.", id))
+        })
+}
+
+/// Enables anisotropic filtering if available and returns the maximum
+/// anisotropy supported, `1.0` meaning none.
+pub fn get_max_anisotropy(context: &WebGl2RenderingContext) -> f32 {
+    match context.get_extension("EXT_texture_filter_anisotropic") {
+        Ok(Some(_)) => context
+            .get_parameter(MAX_TEXTURE_MAX_ANISOTROPY_EXT)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .unwrap_or(1.0) as f32,
+        _ => 1.0,
+    }
+}
+
+/// Enables rendering to float color buffers if available.
+pub fn enable_color_buffer_float(context: &WebGl2RenderingContext) -> bool {
+    matches!(context.get_extension("EXT_color_buffer_float"), Ok(Some(_)))
+}
+
+/// Enables linear filtering of 32-bit float textures if available.
+pub fn enable_texture_float_linear(context: &WebGl2RenderingContext) -> bool {
+    matches!(
+        context.get_extension("OES_texture_float_linear"),
+        Ok(Some(_))
+    )
+}
+
+/// Returns the current viewport as (x, y, width, height).
+pub fn get_viewport(context: &WebGl2RenderingContext) -> [i32; 4] {
+    let mut viewport = [0; 4];
+    if let Ok(value) = context.get_parameter(WebGl2RenderingContext::VIEWPORT) {
+        js_sys::Int32Array::from(value).copy_to(&mut viewport);
+    }
+    viewport
+}
+
+pub fn set_viewport(context: &WebGl2RenderingContext, viewport: [i32; 4]) {
+    context.viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
 }
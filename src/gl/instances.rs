@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use nalgebra::Matrix4;
+use web_sys::{WebGl2RenderingContext, WebGlBuffer};
+
+use crate::error::EngineError;
+use crate::gl::gl_shape::{
+    INSTANCE_COLOR_LOCATION, INSTANCE_DATA_LOCATION, INSTANCE_MATRIX_LOCATION,
+};
+use crate::gl::resources::{GlResource, Resources};
+use crate::math::bounds::Aabb;
+use crate::math::matrix::mat_to_row_array;
+use crate::types::Color;
+
+static MATRIX_DATA_SIZE: usize = 16;
+static COLOR_DATA_SIZE: usize = 4;
+static CUSTOM_DATA_SIZE: usize = 4;
+static INSTANCE_DATA_SIZE: usize =
+    MATRIX_DATA_SIZE + COLOR_DATA_SIZE + CUSTOM_DATA_SIZE;
+static BYTES_PER_FLOAT: usize = 4;
+/// Instances the buffer of an instanced shape first holds.
+static INITIAL_CAPACITY: usize = 16;
+
+/// Per-instance values of an instanced shape.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Instance {
+    /// Transform applied before the one of the shape, in the row-vector
+    /// convention of `LinearTransform`. Normals are only correct for
+    /// uniform scales.
+    pub matrix: Matrix4<f32>,
+    /// Multiplied with the vertex colors.
+    pub color: Color,
+    /// Free values, available to shaders as `instanceData`.
+    pub data: [f32; 4],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Instance {
+            matrix: Matrix4::identity(),
+            color: Color::rgb(1.0, 1.0, 1.0),
+            data: [0.0; 4],
+        }
+    }
+}
+
+impl Instance {
+    fn pack(&self, out: &mut [f32]) {
+        // Each row of the row-vector matrix is a column of the shader's.
+        out[..MATRIX_DATA_SIZE]
+            .copy_from_slice(&mat_to_row_array(&self.matrix));
+        out[MATRIX_DATA_SIZE..MATRIX_DATA_SIZE + COLOR_DATA_SIZE]
+            .copy_from_slice(&self.color.to_array());
+        out[MATRIX_DATA_SIZE + COLOR_DATA_SIZE..].copy_from_slice(&self.data);
+    }
+}
+
+/// Identifies an instance, unaffected by the removal of others.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceId(pub u32);
+
+/// Instances packed contiguously in draw order. Removing one moves the last
+/// instance into its slot, so that only changed slots are uploaded.
+#[derive(Default)]
+pub struct Instances {
+    instances: Vec<Instance>,
+    /// Attributes of `instances`, `INSTANCE_DATA_SIZE` floats each.
+    data: Vec<f32>,
+    ids: Vec<InstanceId>,
+    slots: HashMap<InstanceId, usize>,
+    next_id: u32,
+    /// Slots changed since the last `take_dirty`.
+    dirty: Option<Range<usize>>,
+}
+
+impl Instances {
+    pub fn new() -> Instances {
+        Instances::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn add(&mut self, instance: Instance) -> InstanceId {
+        let id = InstanceId(self.next_id);
+        self.next_id += 1;
+        let slot = self.instances.len();
+        self.instances.push(instance);
+        self.data.resize(self.data.len() + INSTANCE_DATA_SIZE, 0.0);
+        self.ids.push(id);
+        self.slots.insert(id, slot);
+        self.write(slot);
+        id
+    }
+
+    /// Removes instance `id`, returning it if it existed.
+    pub fn remove(&mut self, id: InstanceId) -> Option<Instance> {
+        let slot = self.slots.remove(&id)?;
+        let last = self.instances.len() - 1;
+        let removed = self.instances.swap_remove(slot);
+        self.ids.swap_remove(slot);
+        self.data.truncate(last * INSTANCE_DATA_SIZE);
+        if slot != last {
+            self.slots.insert(self.ids[slot], slot);
+            self.write(slot);
+        } else {
+            // Nothing to upload, but the bounds change.
+            self.mark_dirty(last..last);
+        }
+        Some(removed)
+    }
+
+    pub fn get(&self, id: InstanceId) -> Option<&Instance> {
+        self.slots.get(&id).map(|slot| &self.instances[*slot])
+    }
+
+    /// Changes instance `id` with `update`, returning whether it exists.
+    pub fn update<F>(&mut self, id: InstanceId, update: F) -> bool
+    where
+        F: FnOnce(&mut Instance),
+    {
+        match self.slots.get(&id) {
+            Some(&slot) => {
+                update(&mut self.instances[slot]);
+                self.write(slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instance> {
+        self.instances.iter()
+    }
+
+    /// Packed attributes of every instance.
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    /// Range of slots changed since the last call, if any.
+    pub fn take_dirty(&mut self) -> Option<Range<usize>> {
+        self.dirty.take()
+    }
+
+    fn write(&mut self, slot: usize) {
+        let start = slot * INSTANCE_DATA_SIZE;
+        self.instances[slot]
+            .pack(&mut self.data[start..start + INSTANCE_DATA_SIZE]);
+        self.mark_dirty(slot..slot + 1);
+    }
+
+    fn mark_dirty(&mut self, slots: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => {
+                dirty.start.min(slots.start)..dirty.end.max(slots.end)
+            }
+            None => slots,
+        });
+    }
+}
+
+/// Instances of a shape and the buffer their attributes are read from.
+pub struct InstanceBuffer {
+    gl_buffer: GlResource<WebGlBuffer>,
+    /// Instances the GPU buffer can hold.
+    capacity: usize,
+    pub instances: Instances,
+    /// Union of the shape bounds transformed by every instance, as of the
+    /// last upload.
+    bounds: Option<Aabb>,
+}
+
+impl InstanceBuffer {
+    /// Creates an empty buffer and configures the instance attributes of
+    /// the bound vertex array object.
+    pub fn new(resources: &Resources) -> Result<InstanceBuffer, EngineError> {
+        let context = resources.context();
+        let mut gl_buffer = resources.create::<WebGlBuffer>()?;
+        context.bind_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            Some(&gl_buffer),
+        );
+        allocate(context, &mut gl_buffer, INITIAL_CAPACITY);
+        let stride = (INSTANCE_DATA_SIZE * BYTES_PER_FLOAT) as i32;
+        let attributes = [
+            (INSTANCE_MATRIX_LOCATION, 0),
+            (INSTANCE_MATRIX_LOCATION + 1, 4),
+            (INSTANCE_MATRIX_LOCATION + 2, 8),
+            (INSTANCE_MATRIX_LOCATION + 3, 12),
+            (INSTANCE_COLOR_LOCATION, MATRIX_DATA_SIZE),
+            (INSTANCE_DATA_LOCATION, MATRIX_DATA_SIZE + COLOR_DATA_SIZE),
+        ];
+        for (location, offset) in attributes.iter().copied() {
+            context.vertex_attrib_pointer_with_i32(
+                location,
+                4,
+                WebGl2RenderingContext::FLOAT,
+                false,
+                stride,
+                (offset * BYTES_PER_FLOAT) as i32,
+            );
+            context.vertex_attrib_divisor(location, 1);
+            context.enable_vertex_attrib_array(location);
+        }
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, None);
+        Ok(InstanceBuffer {
+            gl_buffer,
+            capacity: INITIAL_CAPACITY,
+            instances: Instances::new(),
+            bounds: None,
+        })
+    }
+
+    /// Replaces the buffer after the context was restored, configuring the
+    /// bound vertex array object, and uploads every instance again.
+    pub fn restore(
+        &mut self,
+        resources: &Resources,
+    ) -> Result<(), EngineError> {
+        let restored = InstanceBuffer::new(resources)?;
+        self.gl_buffer = restored.gl_buffer;
+        self.capacity = restored.capacity;
+        let len = self.instances.len();
+        self.instances.mark_dirty(0..len);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    /// Uploads the changed instances, growing the buffer when they no
+    /// longer fit.
+    pub fn upload(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        shape_bounds: &Aabb,
+    ) {
+        let dirty = match self.instances.take_dirty() {
+            Some(dirty) => dirty,
+            None => return,
+        };
+        context.bind_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            Some(&self.gl_buffer),
+        );
+        let data = self.instances.data();
+        let range = if self.len() > self.capacity {
+            self.capacity = self.len().max(self.capacity * 2);
+            allocate(context, &mut self.gl_buffer, self.capacity);
+            0..data.len()
+        } else {
+            dirty.start * INSTANCE_DATA_SIZE
+                ..(dirty.end * INSTANCE_DATA_SIZE).min(data.len())
+        };
+        if !range.is_empty() {
+            // See `gl_shape::update_buffer` about the safety of the view.
+            unsafe {
+                let view = js_sys::Float32Array::view(&data[range.clone()]);
+                context.buffer_sub_data_with_i32_and_array_buffer_view(
+                    WebGl2RenderingContext::ARRAY_BUFFER,
+                    (range.start * BYTES_PER_FLOAT) as i32,
+                    &view,
+                );
+            }
+        }
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, None);
+        self.bounds = self
+            .instances
+            .iter()
+            .map(|instance| shape_bounds.transform(&instance.matrix))
+            .reduce(|bounds, other| bounds.union(&other));
+    }
+}
+
+/// Reallocates `buffer`, which must be the bound array buffer, for
+/// `capacity` instances.
+fn allocate(
+    context: &WebGl2RenderingContext,
+    buffer: &mut GlResource<WebGlBuffer>,
+    capacity: usize,
+) {
+    let bytes = capacity * INSTANCE_DATA_SIZE * BYTES_PER_FLOAT;
+    context.buffer_data_with_i32(
+        WebGl2RenderingContext::ARRAY_BUFFER,
+        bytes as i32,
+        WebGl2RenderingContext::DYNAMIC_DRAW,
+    );
+    buffer.set_bytes(bytes);
+}
+
+/// Sets the values instance attributes read when disabled, so that shapes
+/// without instances are drawn once, untransformed and untinted.
+pub fn set_default_instance_attributes(context: &WebGl2RenderingContext) {
+    for column in 0..4 {
+        let mut values = [0.0; 4];
+        values[column] = 1.0;
+        context.vertex_attrib4fv_with_f32_array(
+            INSTANCE_MATRIX_LOCATION + column as u32,
+            &values,
+        );
+    }
+    context.vertex_attrib4f(INSTANCE_COLOR_LOCATION, 1.0, 1.0, 1.0, 1.0);
+    context.vertex_attrib4f(INSTANCE_DATA_LOCATION, 0.0, 0.0, 0.0, 0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32) -> Instance {
+        let mut matrix = Matrix4::identity();
+        matrix[(3, 0)] = x;
+        Instance {
+            matrix,
+            ..Instance::default()
+        }
+    }
+
+    #[test]
+    fn removal_moves_last_instance_into_slot() {
+        let mut instances = Instances::new();
+        let ids: Vec<_> = (0..4).map(|i| instances.add(at(i as f32))).collect();
+        assert_eq!(instances.take_dirty(), Some(0..4));
+
+        assert_eq!(instances.remove(ids[1]), Some(at(1.0)));
+        assert_eq!(instances.remove(ids[1]), None);
+        assert_eq!(instances.len(), 3);
+        // The last instance now fills slot 1.
+        assert_eq!(instances.take_dirty(), Some(1..2));
+        assert_eq!(instances.remove(ids[2]), Some(at(2.0)));
+        assert_eq!(instances.take_dirty(), Some(2..2));
+        assert_eq!(instances.data()[INSTANCE_DATA_SIZE + 12], 3.0);
+        assert_eq!(instances.get(ids[3]), Some(&at(3.0)));
+        assert_eq!(instances.data().len(), 2 * INSTANCE_DATA_SIZE);
+
+        assert!(instances.update(ids[3], |instance| instance.data[0] = 7.0));
+        assert_eq!(instances.take_dirty(), Some(1..2));
+        assert_eq!(instances.get(ids[3]).unwrap().data[0], 7.0);
+        assert!(!instances.update(ids[1], |_| {}));
+    }
+}
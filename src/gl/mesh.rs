@@ -0,0 +1,377 @@
+use nalgebra::Matrix4;
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlVertexArrayObject};
+
+use crate::environment::GlEnvironment;
+use crate::error::EngineError;
+use crate::gl::gl_shape::{
+    update_buffer, update_element_buffer, with_buffer, with_vao, ShapeProps,
+    COLOR_LOCATION, NORMAL_LOCATION, POSITION_LOCATION, UV_LOCATION,
+};
+use crate::gl::resources::GlResource;
+use crate::math::bounds::Aabb;
+use crate::math::matrix::transform_point;
+use crate::types::Vec3;
+
+static POSITION_DATA_SIZE: usize = 3;
+static COLOR_DATA_SIZE: usize = 4;
+static NORMAL_DATA_SIZE: usize = 3;
+static UV_DATA_SIZE: usize = 2;
+static VERTEX_DATA_SIZE: usize =
+    POSITION_DATA_SIZE + COLOR_DATA_SIZE + NORMAL_DATA_SIZE + UV_DATA_SIZE;
+static BYTES_PER_FLOAT: usize = 4;
+static BYTES_PER_INDEX: usize = 4;
+
+/// Vertices in GPU buffers, shared by the shapes drawing them through an
+/// `Rc`. The buffers are deleted with the last of these shapes.
+pub struct Mesh {
+    pub mode: u32,
+    pub vertices_count: usize,
+    /// Interleaved positions, colors, normals and uvs.
+    pub array_buffer: Vec<f32>,
+    pub element_array: Option<Vec<u32>>,
+    /// Bounds of the positions.
+    pub bounds: Aabb,
+    gl_array_buffer: GlResource<WebGlBuffer>,
+    gl_element_buffer: Option<GlResource<WebGlBuffer>>,
+    /// Reads the buffers, shared by the shapes drawing the mesh without
+    /// instances.
+    vao: GlResource<WebGlVertexArrayObject>,
+}
+
+impl Mesh {
+    /// Creates a mesh from separate attributes, which it interleaves.
+    pub fn new(props: ShapeProps) -> Result<Mesh, EngineError> {
+        let count = props.vertices_count;
+        let attributes = [
+            ("positions", props.positions, POSITION_DATA_SIZE),
+            ("colors", props.colors, COLOR_DATA_SIZE),
+            ("normals", props.normals, NORMAL_DATA_SIZE),
+            ("uvs", props.uvs, UV_DATA_SIZE),
+        ];
+        for (name, attribute, size) in &attributes {
+            if attribute.len() != size * count {
+                return Err(EngineError::InvalidMesh(format!(
+                    "{} size = {}, expected {}",
+                    name,
+                    attribute.len(),
+                    size * count
+                )));
+            }
+        }
+
+        let bounds = Aabb::from_positions(&attributes[0].1)
+            .unwrap_or_else(|| Aabb::new(Vec3::zero(), Vec3::zero()));
+        let mut array_buffer = vec![0.0; VERTEX_DATA_SIZE * count];
+        let mut offset = 0;
+        for (_, attribute, size) in &attributes {
+            for i in 0..count {
+                let start = i * VERTEX_DATA_SIZE + offset;
+                array_buffer[start..start + size]
+                    .copy_from_slice(&attribute[i * size..(i + 1) * size]);
+            }
+            offset += size;
+        }
+        Mesh::create(props.env, props.mode, array_buffer, props.indices, bounds)
+    }
+
+    /// Creates a mesh from vertices interleaved like `array_buffer`.
+    pub fn from_array_buffer(
+        env: &GlEnvironment,
+        mode: u32,
+        array_buffer: Vec<f32>,
+        indices: Option<Vec<u32>>,
+    ) -> Result<Mesh, EngineError> {
+        let bounds = Aabb::from_points(
+            array_buffer
+                .chunks_exact(VERTEX_DATA_SIZE)
+                .map(|vertex| Vec3::new(vertex[0], vertex[1], vertex[2])),
+        )
+        .unwrap_or_else(|| Aabb::new(Vec3::zero(), Vec3::zero()));
+        Mesh::create(env, mode, array_buffer, indices, bounds)
+    }
+
+    /// Uploads the retained vertices and indices to new buffers.
+    pub fn recreate(&self, env: &GlEnvironment) -> Result<Mesh, EngineError> {
+        Mesh::create(
+            env,
+            self.mode,
+            self.array_buffer.clone(),
+            self.element_array.clone(),
+            self.bounds,
+        )
+    }
+
+    /// Uploads the vertices and indices to new buffers.
+    fn create(
+        env: &GlEnvironment,
+        mode: u32,
+        array_buffer: Vec<f32>,
+        indices: Option<Vec<u32>>,
+        bounds: Aabb,
+    ) -> Result<Mesh, EngineError> {
+        let vertices_count = validate(&array_buffer, indices.as_deref())?;
+        let context = &env.context;
+        let mut gl_array_buffer = env.resources.create::<WebGlBuffer>()?;
+        with_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            context,
+            &gl_array_buffer,
+            |buffer| update_buffer(context, buffer, &array_buffer),
+        );
+        gl_array_buffer.set_bytes(array_buffer.len() * BYTES_PER_FLOAT);
+
+        let gl_element_buffer = match &indices {
+            Some(indices) => {
+                let mut buffer = env.resources.create::<WebGlBuffer>()?;
+                with_buffer(
+                    WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                    context,
+                    &buffer,
+                    |buffer| update_element_buffer(context, buffer, indices),
+                );
+                buffer.set_bytes(indices.len() * BYTES_PER_INDEX);
+                Some(buffer)
+            }
+            None => None,
+        };
+
+        let mesh = Mesh {
+            mode,
+            vertices_count,
+            array_buffer,
+            element_array: indices,
+            bounds,
+            gl_array_buffer,
+            gl_element_buffer,
+            vao: env.resources.create::<WebGlVertexArrayObject>()?,
+        };
+        with_vao(context, &mesh.vao, |_| mesh.bind_to_vertex_array(context));
+        Ok(mesh)
+    }
+
+    /// Vertex array object reading the mesh, for shapes without instances.
+    pub fn vertex_array(&self) -> &WebGlVertexArrayObject {
+        &self.vao
+    }
+
+    /// Reads the vertex attributes and indices of the bound vertex array
+    /// object from the buffers of the mesh.
+    pub fn bind_to_vertex_array(&self, context: &WebGl2RenderingContext) {
+        if let Some(element_buffer) = &self.gl_element_buffer {
+            context.bind_buffer(
+                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                Some(element_buffer),
+            );
+        }
+        with_buffer(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            context,
+            &self.gl_array_buffer,
+            |_buffer| init_attributes(context),
+        );
+    }
+
+    /// Appends the vertices transformed by `matrix`, in the row-vector
+    /// convention of `LinearTransform`, to `vertices` and the triangles to
+    /// `indices`.
+    pub fn append_transformed(
+        &self,
+        matrix: &Matrix4<f32>,
+        vertices: &mut Vec<f32>,
+        indices: &mut Vec<u32>,
+    ) {
+        append_transformed_vertices(
+            &self.array_buffer,
+            self.element_array.as_deref(),
+            matrix,
+            vertices,
+            indices,
+        );
+    }
+}
+
+/// Appends interleaved `array` vertices transformed by `matrix`, in the
+/// row-vector convention of `LinearTransform`, to `vertices`, and their
+/// `elements` or the vertices in order to `indices`.
+fn append_transformed_vertices(
+    array: &[f32],
+    elements: Option<&[u32]>,
+    matrix: &Matrix4<f32>,
+    vertices: &mut Vec<f32>,
+    indices: &mut Vec<u32>,
+) {
+    let first = (vertices.len() / VERTEX_DATA_SIZE) as u32;
+    let normal_offset = POSITION_DATA_SIZE + COLOR_DATA_SIZE;
+    for vertex in array.chunks_exact(VERTEX_DATA_SIZE) {
+        let start = vertices.len();
+        vertices.extend_from_slice(vertex);
+        let position =
+            transform_point(matrix, Vec3::new(vertex[0], vertex[1], vertex[2]));
+        vertices[start..start + POSITION_DATA_SIZE]
+            .copy_from_slice(&position.to_array());
+        let normal = &vertex[normal_offset..normal_offset + NORMAL_DATA_SIZE];
+        // Transforms only carry a uniform scale, so normals are rotated by
+        // the matrix and renormalized.
+        let rotated = |j| {
+            normal[0] * matrix[(0, j)]
+                + normal[1] * matrix[(1, j)]
+                + normal[2] * matrix[(2, j)]
+        };
+        let normal = Vec3::new(rotated(0), rotated(1), rotated(2)).normalize();
+        vertices
+            [start + normal_offset..start + normal_offset + NORMAL_DATA_SIZE]
+            .copy_from_slice(&normal.to_array());
+    }
+    match elements {
+        Some(elements) => {
+            indices.extend(elements.iter().map(|index| first + index))
+        }
+        None => {
+            let count = (array.len() / VERTEX_DATA_SIZE) as u32;
+            indices.extend(first..first + count)
+        }
+    }
+}
+
+fn init_attributes(context: &WebGl2RenderingContext) {
+    let stride = (VERTEX_DATA_SIZE * BYTES_PER_FLOAT) as i32;
+    configure_attribute(
+        context,
+        POSITION_LOCATION,
+        POSITION_DATA_SIZE as i32,
+        stride,
+        0,
+    );
+    configure_attribute(
+        context,
+        COLOR_LOCATION,
+        COLOR_DATA_SIZE as i32,
+        stride,
+        (POSITION_DATA_SIZE * BYTES_PER_FLOAT) as i32,
+    );
+    configure_attribute(
+        context,
+        NORMAL_LOCATION,
+        NORMAL_DATA_SIZE as i32,
+        stride,
+        ((POSITION_DATA_SIZE + COLOR_DATA_SIZE) * BYTES_PER_FLOAT) as i32,
+    );
+    configure_attribute(
+        context,
+        UV_LOCATION,
+        UV_DATA_SIZE as i32,
+        stride,
+        ((POSITION_DATA_SIZE + COLOR_DATA_SIZE + NORMAL_DATA_SIZE)
+            * BYTES_PER_FLOAT) as i32,
+    );
+}
+
+fn configure_attribute(
+    context: &WebGl2RenderingContext,
+    location: u32,
+    size: i32,
+    stride: i32,
+    offset: i32,
+) {
+    context.vertex_attrib_pointer_with_i32(
+        location,
+        size,
+        WebGl2RenderingContext::FLOAT,
+        false,
+        stride,
+        offset,
+    );
+    context.enable_vertex_attrib_array(location);
+}
+
+/// Checks that `array_buffer` holds whole vertices that `indices` only
+/// point to, and returns their count.
+fn validate(
+    array_buffer: &[f32],
+    indices: Option<&[u32]>,
+) -> Result<usize, EngineError> {
+    if !array_buffer.len().is_multiple_of(VERTEX_DATA_SIZE) {
+        return Err(EngineError::InvalidMesh(format!(
+            "array buffer size = {}, expected a multiple of {}",
+            array_buffer.len(),
+            VERTEX_DATA_SIZE
+        )));
+    }
+    let count = array_buffer.len() / VERTEX_DATA_SIZE;
+    let indices = indices.unwrap_or(&[]);
+    if count > i32::MAX as usize || indices.len() > i32::MAX as usize {
+        return Err(EngineError::InvalidMesh(String::from(
+            "Too many vertices or indices to draw.",
+        )));
+    }
+    if let Some(index) = indices.iter().find(|index| **index as usize >= count)
+    {
+        return Err(EngineError::InvalidMesh(format!(
+            "index {} is out of the {} vertices",
+            index, count
+        )));
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_partial_vertices_and_stray_indices() {
+        let vertices = vec![0.0; 3 * VERTEX_DATA_SIZE];
+        assert_eq!(validate(&vertices, Some(&[0, 1, 2])), Ok(3));
+        assert_eq!(validate(&vertices, None), Ok(3));
+        assert!(matches!(
+            validate(&vertices[1..], None),
+            Err(EngineError::InvalidMesh(_))
+        ));
+        assert!(matches!(
+            validate(&vertices, Some(&[0, 3])),
+            Err(EngineError::InvalidMesh(_))
+        ));
+    }
+
+    #[test]
+    fn appended_vertices_are_transformed_and_reindexed() {
+        // Position, color, normal and uv of two vertices.
+        let array = [
+            [1.0, 0.0, 0.0, 1.0, 0.5, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0, 0.5, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+        ]
+        .concat();
+        // Quarter turn around z then translation by (0, 0, 5).
+        let mut matrix = Matrix4::identity();
+        matrix[(0, 0)] = 0.0;
+        matrix[(0, 1)] = 1.0;
+        matrix[(1, 0)] = -1.0;
+        matrix[(1, 1)] = 0.0;
+        matrix[(3, 2)] = 5.0;
+        let mut vertices = vec![0.0; VERTEX_DATA_SIZE];
+        let mut indices = vec![0];
+
+        append_transformed_vertices(
+            &array,
+            Some(&[1, 0, 1]),
+            &matrix,
+            &mut vertices,
+            &mut indices,
+        );
+        assert_eq!(indices, [0, 2, 1, 2]);
+        let first = &vertices[VERTEX_DATA_SIZE..2 * VERTEX_DATA_SIZE];
+        assert_eq!(first[..3], [0.0, 1.0, 5.0]);
+        assert_eq!(first[3..7], array[3..7]);
+        assert_eq!(first[7..10], [0.0, 1.0, 0.0]);
+        assert_eq!(first[10..], array[10..12]);
+
+        append_transformed_vertices(
+            &array,
+            None,
+            &matrix,
+            &mut vertices,
+            &mut indices,
+        );
+        assert_eq!(indices[4..], [3, 4]);
+    }
+}
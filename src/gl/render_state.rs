@@ -0,0 +1,595 @@
+use web_sys::WebGl2RenderingContext;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DepthFunc {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+impl DepthFunc {
+    fn gl_func(self) -> u32 {
+        match self {
+            DepthFunc::Never => WebGl2RenderingContext::NEVER,
+            DepthFunc::Less => WebGl2RenderingContext::LESS,
+            DepthFunc::Equal => WebGl2RenderingContext::EQUAL,
+            DepthFunc::LessEqual => WebGl2RenderingContext::LEQUAL,
+            DepthFunc::Greater => WebGl2RenderingContext::GREATER,
+            DepthFunc::NotEqual => WebGl2RenderingContext::NOTEQUAL,
+            DepthFunc::GreaterEqual => WebGl2RenderingContext::GEQUAL,
+            DepthFunc::Always => WebGl2RenderingContext::ALWAYS,
+        }
+    }
+}
+
+/// Faces discarded by face culling.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CullFace {
+    Front,
+    Back,
+    FrontAndBack,
+}
+
+impl CullFace {
+    fn gl_mode(self) -> u32 {
+        match self {
+            CullFace::Front => WebGl2RenderingContext::FRONT,
+            CullFace::Back => WebGl2RenderingContext::BACK,
+            CullFace::FrontAndBack => WebGl2RenderingContext::FRONT_AND_BACK,
+        }
+    }
+}
+
+/// Winding of front faces, as seen on screen.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+impl FrontFace {
+    fn gl_mode(self) -> u32 {
+        match self {
+            FrontFace::CounterClockwise => WebGl2RenderingContext::CCW,
+            FrontFace::Clockwise => WebGl2RenderingContext::CW,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendEquation {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+impl BlendEquation {
+    fn gl_mode(self) -> u32 {
+        match self {
+            BlendEquation::Add => WebGl2RenderingContext::FUNC_ADD,
+            BlendEquation::Subtract => WebGl2RenderingContext::FUNC_SUBTRACT,
+            BlendEquation::ReverseSubtract => {
+                WebGl2RenderingContext::FUNC_REVERSE_SUBTRACT
+            }
+            BlendEquation::Min => WebGl2RenderingContext::MIN,
+            BlendEquation::Max => WebGl2RenderingContext::MAX,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
+    ConstantAlpha,
+    OneMinusConstantAlpha,
+    SrcAlphaSaturate,
+}
+
+impl BlendFactor {
+    fn gl_factor(self) -> u32 {
+        match self {
+            BlendFactor::Zero => WebGl2RenderingContext::ZERO,
+            BlendFactor::One => WebGl2RenderingContext::ONE,
+            BlendFactor::SrcColor => WebGl2RenderingContext::SRC_COLOR,
+            BlendFactor::OneMinusSrcColor => {
+                WebGl2RenderingContext::ONE_MINUS_SRC_COLOR
+            }
+            BlendFactor::DstColor => WebGl2RenderingContext::DST_COLOR,
+            BlendFactor::OneMinusDstColor => {
+                WebGl2RenderingContext::ONE_MINUS_DST_COLOR
+            }
+            BlendFactor::SrcAlpha => WebGl2RenderingContext::SRC_ALPHA,
+            BlendFactor::OneMinusSrcAlpha => {
+                WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA
+            }
+            BlendFactor::DstAlpha => WebGl2RenderingContext::DST_ALPHA,
+            BlendFactor::OneMinusDstAlpha => {
+                WebGl2RenderingContext::ONE_MINUS_DST_ALPHA
+            }
+            BlendFactor::ConstantColor => {
+                WebGl2RenderingContext::CONSTANT_COLOR
+            }
+            BlendFactor::OneMinusConstantColor => {
+                WebGl2RenderingContext::ONE_MINUS_CONSTANT_COLOR
+            }
+            BlendFactor::ConstantAlpha => {
+                WebGl2RenderingContext::CONSTANT_ALPHA
+            }
+            BlendFactor::OneMinusConstantAlpha => {
+                WebGl2RenderingContext::ONE_MINUS_CONSTANT_ALPHA
+            }
+            BlendFactor::SrcAlphaSaturate => {
+                WebGl2RenderingContext::SRC_ALPHA_SATURATE
+            }
+        }
+    }
+}
+
+/// Blend equations and factors, separate for the color and alpha channels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Blend {
+    pub color_equation: BlendEquation,
+    pub alpha_equation: BlendEquation,
+    pub src_color: BlendFactor,
+    pub dst_color: BlendFactor,
+    pub src_alpha: BlendFactor,
+    pub dst_alpha: BlendFactor,
+}
+
+impl Blend {
+    /// "Over" compositing of non-premultiplied colors.
+    pub fn alpha() -> Blend {
+        Blend {
+            color_equation: BlendEquation::Add,
+            alpha_equation: BlendEquation::Add,
+            src_color: BlendFactor::SrcAlpha,
+            dst_color: BlendFactor::OneMinusSrcAlpha,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::OneMinusSrcAlpha,
+        }
+    }
+
+    /// "Over" compositing of premultiplied colors.
+    pub fn premultiplied_alpha() -> Blend {
+        Blend {
+            src_color: BlendFactor::One,
+            ..Blend::alpha()
+        }
+    }
+
+    pub fn additive() -> Blend {
+        Blend {
+            color_equation: BlendEquation::Add,
+            alpha_equation: BlendEquation::Add,
+            src_color: BlendFactor::One,
+            dst_color: BlendFactor::One,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::One,
+        }
+    }
+}
+
+/// Depth offset of polygons, `factor * slope + units * r`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PolygonOffset {
+    pub factor: f32,
+    pub units: f32,
+}
+
+/// Rectangle in window coordinates, from the bottom-left corner.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Scissor {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Scissor {
+    /// Part of `self` inside `other`, empty when they don't overlap.
+    pub fn intersection(&self, other: &Scissor) -> Scissor {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let top = (self.y + self.height).min(other.y + other.height);
+        Scissor {
+            x,
+            y,
+            width: (right - x).max(0),
+            height: (top - y).max(0),
+        }
+    }
+}
+
+/// Fixed function state a draw call is made with. `None` disables the
+/// matching test or stage.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderState {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_func: DepthFunc,
+    pub cull_face: Option<CullFace>,
+    pub front_face: FrontFace,
+    pub blend: Option<Blend>,
+    /// Written red, green, blue and alpha channels.
+    pub color_mask: [bool; 4],
+    pub polygon_offset: Option<PolygonOffset>,
+    pub scissor: Option<Scissor>,
+}
+
+impl Default for RenderState {
+    /// Opaque geometry: depth tested and written, back faces culled.
+    fn default() -> Self {
+        RenderState {
+            depth_test: true,
+            depth_write: true,
+            depth_func: DepthFunc::Less,
+            cull_face: Some(CullFace::Back),
+            front_face: FrontFace::CounterClockwise,
+            blend: None,
+            color_mask: [true; 4],
+            polygon_offset: None,
+            scissor: None,
+        }
+    }
+}
+
+impl RenderState {
+    /// Full-screen passes, which ignore and keep the depth buffer.
+    pub fn full_screen() -> RenderState {
+        RenderState {
+            depth_test: false,
+            depth_write: false,
+            cull_face: None,
+            ..RenderState::default()
+        }
+    }
+
+    /// GL calls needed to switch from `previous` to `self`. Everything
+    /// `self` uses is set when `previous` is unknown.
+    pub fn diff(&self, previous: Option<&RenderState>) -> StateChanges {
+        StateChanges {
+            depth_test: previous.map(|p| p.depth_test) != Some(self.depth_test),
+            depth_write: previous.map(|p| p.depth_write)
+                != Some(self.depth_write),
+            depth_func: previous.map(|p| p.depth_func) != Some(self.depth_func),
+            cull_face_enabled: previous.map(|p| p.cull_face.is_some())
+                != Some(self.cull_face.is_some()),
+            cull_face: self.cull_face.is_some()
+                && previous.and_then(|p| p.cull_face) != self.cull_face,
+            front_face: previous.map(|p| p.front_face) != Some(self.front_face),
+            blend_enabled: previous.map(|p| p.blend.is_some())
+                != Some(self.blend.is_some()),
+            blend: self.blend.is_some()
+                && previous.and_then(|p| p.blend) != self.blend,
+            color_mask: previous.map(|p| p.color_mask) != Some(self.color_mask),
+            polygon_offset_enabled: previous
+                .map(|p| p.polygon_offset.is_some())
+                != Some(self.polygon_offset.is_some()),
+            polygon_offset: self.polygon_offset.is_some()
+                && previous.and_then(|p| p.polygon_offset)
+                    != self.polygon_offset,
+            scissor_enabled: previous.map(|p| p.scissor.is_some())
+                != Some(self.scissor.is_some()),
+            scissor: self.scissor.is_some()
+                && previous.and_then(|p| p.scissor) != self.scissor,
+        }
+    }
+}
+
+/// Which parts of the fixed function state `StateCache::apply` sets, one
+/// flag per GL call. `*_enabled` flags toggle the matching capability.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct StateChanges {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_func: bool,
+    pub cull_face_enabled: bool,
+    pub cull_face: bool,
+    pub front_face: bool,
+    pub blend_enabled: bool,
+    pub blend: bool,
+    pub color_mask: bool,
+    pub polygon_offset_enabled: bool,
+    pub polygon_offset: bool,
+    pub scissor_enabled: bool,
+    pub scissor: bool,
+}
+
+/// Applies render states, only issuing the GL calls for what differs from
+/// the last applied state.
+///
+/// Code changing the same state directly must call `invalidate`.
+#[derive(Default)]
+pub struct StateCache {
+    current: Option<RenderState>,
+    /// Scissor every applied state is confined to, see `set_clip`.
+    clip: Option<Scissor>,
+}
+
+impl StateCache {
+    pub fn new() -> StateCache {
+        StateCache {
+            current: None,
+            clip: None,
+        }
+    }
+
+    /// Confines the states applied from now on to `clip`, intersected with
+    /// their own scissor, e.g. to the viewport of a camera. Takes effect on
+    /// the next `apply`.
+    pub fn set_clip(&mut self, clip: Option<Scissor>) {
+        self.clip = clip;
+    }
+
+    /// Forgets the cached state, so that the next `apply` sets everything.
+    pub fn invalidate(&mut self) {
+        self.current = None;
+    }
+
+    /// `state` confined to the clip.
+    fn clipped(&self, state: &RenderState) -> RenderState {
+        RenderState {
+            scissor: match (state.scissor, self.clip) {
+                (Some(scissor), Some(clip)) => {
+                    Some(scissor.intersection(&clip))
+                }
+                (scissor, clip) => scissor.or(clip),
+            },
+            ..*state
+        }
+    }
+
+    /// What `apply` would set for `state`.
+    pub fn changes(&self, state: &RenderState) -> StateChanges {
+        self.clipped(state).diff(self.current.as_ref())
+    }
+
+    pub fn apply(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        state: &RenderState,
+    ) {
+        let state = self.clipped(state);
+        let changes = state.diff(self.current.as_ref());
+
+        if changes.depth_test {
+            set_capability(
+                context,
+                WebGl2RenderingContext::DEPTH_TEST,
+                state.depth_test,
+            );
+        }
+        if changes.depth_write {
+            context.depth_mask(state.depth_write);
+        }
+        if changes.depth_func {
+            context.depth_func(state.depth_func.gl_func());
+        }
+
+        if changes.cull_face_enabled {
+            set_capability(
+                context,
+                WebGl2RenderingContext::CULL_FACE,
+                state.cull_face.is_some(),
+            );
+        }
+        if let (true, Some(cull_face)) = (changes.cull_face, state.cull_face) {
+            context.cull_face(cull_face.gl_mode());
+        }
+        if changes.front_face {
+            context.front_face(state.front_face.gl_mode());
+        }
+
+        if changes.blend_enabled {
+            set_capability(
+                context,
+                WebGl2RenderingContext::BLEND,
+                state.blend.is_some(),
+            );
+        }
+        if let (true, Some(blend)) = (changes.blend, state.blend) {
+            context.blend_equation_separate(
+                blend.color_equation.gl_mode(),
+                blend.alpha_equation.gl_mode(),
+            );
+            context.blend_func_separate(
+                blend.src_color.gl_factor(),
+                blend.dst_color.gl_factor(),
+                blend.src_alpha.gl_factor(),
+                blend.dst_alpha.gl_factor(),
+            );
+        }
+
+        if changes.color_mask {
+            let [r, g, b, a] = state.color_mask;
+            context.color_mask(r, g, b, a);
+        }
+
+        if changes.polygon_offset_enabled {
+            set_capability(
+                context,
+                WebGl2RenderingContext::POLYGON_OFFSET_FILL,
+                state.polygon_offset.is_some(),
+            );
+        }
+        if let (true, Some(offset)) =
+            (changes.polygon_offset, state.polygon_offset)
+        {
+            context.polygon_offset(offset.factor, offset.units);
+        }
+
+        if changes.scissor_enabled {
+            set_capability(
+                context,
+                WebGl2RenderingContext::SCISSOR_TEST,
+                state.scissor.is_some(),
+            );
+        }
+        if let (true, Some(scissor)) = (changes.scissor, state.scissor) {
+            context.scissor(
+                scissor.x,
+                scissor.y,
+                scissor.width,
+                scissor.height,
+            );
+        }
+
+        self.current = Some(state);
+    }
+}
+
+fn set_capability(
+    context: &WebGl2RenderingContext,
+    capability: u32,
+    enabled: bool,
+) {
+    if enabled {
+        context.enable(capability);
+    } else {
+        context.disable(capability);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scissor_intersection() {
+        let a = Scissor {
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 50,
+        };
+        let b = Scissor {
+            x: 80,
+            y: 10,
+            width: 40,
+            height: 100,
+        };
+        assert_eq!(
+            a.intersection(&b),
+            Scissor {
+                x: 80,
+                y: 10,
+                width: 20,
+                height: 40,
+            }
+        );
+        let apart = Scissor { x: 200, ..b };
+        assert_eq!(a.intersection(&apart).width, 0);
+    }
+
+    #[test]
+    fn identical_state_changes_nothing() {
+        let state = RenderState::default();
+        assert_eq!(state.diff(Some(&state)), StateChanges::default());
+    }
+
+    #[test]
+    fn blend_change_only_touches_blend() {
+        let opaque = RenderState::default();
+        let blended = RenderState {
+            blend: Some(Blend::alpha()),
+            ..opaque
+        };
+        assert_eq!(
+            blended.diff(Some(&opaque)),
+            StateChanges {
+                blend_enabled: true,
+                blend: true,
+                ..StateChanges::default()
+            }
+        );
+        let additive = RenderState {
+            blend: Some(Blend::additive()),
+            ..opaque
+        };
+        assert_eq!(
+            additive.diff(Some(&blended)),
+            StateChanges {
+                blend: true,
+                ..StateChanges::default()
+            }
+        );
+        assert_eq!(
+            opaque.diff(Some(&blended)),
+            StateChanges {
+                blend_enabled: true,
+                ..StateChanges::default()
+            }
+        );
+    }
+
+    #[test]
+    fn invalidate_forces_full_apply() {
+        let state = RenderState {
+            blend: Some(Blend::alpha()),
+            ..RenderState::default()
+        };
+        let full = StateChanges {
+            depth_test: true,
+            depth_write: true,
+            depth_func: true,
+            cull_face_enabled: true,
+            cull_face: true,
+            front_face: true,
+            blend_enabled: true,
+            blend: true,
+            color_mask: true,
+            polygon_offset_enabled: true,
+            polygon_offset: false,
+            scissor_enabled: true,
+            scissor: false,
+        };
+        assert_eq!(state.diff(None), full);
+
+        let mut cache = StateCache::new();
+        assert_eq!(cache.changes(&state), full);
+        cache.current = Some(state);
+        assert_eq!(cache.changes(&state), StateChanges::default());
+        cache.invalidate();
+        assert_eq!(cache.changes(&state), full);
+    }
+
+    #[test]
+    fn clip_is_applied_as_scissor() {
+        let clip = Scissor {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10,
+        };
+        let mut cache = StateCache::new();
+        cache.current = Some(RenderState::default());
+        cache.set_clip(Some(clip));
+        assert_eq!(
+            cache.changes(&RenderState::default()),
+            StateChanges {
+                scissor_enabled: true,
+                scissor: true,
+                ..StateChanges::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,549 @@
+use std::rc::Rc;
+
+use web_sys::{WebGl2RenderingContext, WebGlFramebuffer, WebGlRenderbuffer};
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        render_state::RenderState,
+        resources::GlResource,
+        texture::{Filter, SamplerSettings, Texture2D, TextureFormat, Wrap},
+    },
+};
+
+/// Where the pixels of an attachment are stored.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AttachmentStorage {
+    /// A texture, which can be sampled once rendered.
+    Texture(SamplerSettings),
+    /// A renderbuffer, which can only be rendered to.
+    Renderbuffer,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AttachmentSettings {
+    pub format: TextureFormat,
+    pub storage: AttachmentStorage,
+}
+
+impl AttachmentSettings {
+    /// A texture attachment sampled without mipmaps, clamped to its edges.
+    pub fn texture(format: TextureFormat, filter: Filter) -> Self {
+        AttachmentSettings {
+            format,
+            storage: AttachmentStorage::Texture(SamplerSettings {
+                wrap_s: Wrap::ClampToEdge,
+                wrap_t: Wrap::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: None,
+                anisotropy: 1.0,
+            }),
+        }
+    }
+
+    pub fn renderbuffer(format: TextureFormat) -> Self {
+        AttachmentSettings {
+            format,
+            storage: AttachmentStorage::Renderbuffer,
+        }
+    }
+}
+
+/// Attachments of a render target. Color attachment `i` is written by
+/// fragment shader output `location = i`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderTargetSettings {
+    pub color: Vec<AttachmentSettings>,
+    /// Depth or depth-stencil attachment.
+    pub depth: Option<AttachmentSettings>,
+    /// Samples per pixel, clamped to `MAX_SAMPLES`. Above 1, rendering goes
+    /// to multisampled renderbuffers, copied to the attachments by
+    /// `RenderTarget::resolve`.
+    pub samples: u32,
+}
+
+impl Default for RenderTargetSettings {
+    /// A linearly filtered `Rgba8` color texture and a depth renderbuffer.
+    fn default() -> Self {
+        RenderTargetSettings {
+            color: vec![AttachmentSettings::texture(
+                TextureFormat::Rgba8,
+                Filter::Linear,
+            )],
+            depth: Some(AttachmentSettings::renderbuffer(
+                TextureFormat::Depth24,
+            )),
+            samples: 1,
+        }
+    }
+}
+
+pub enum Attachment {
+    Texture(Rc<Texture2D>),
+    Renderbuffer(GlResource<WebGlRenderbuffer>),
+}
+
+/// Multisampled renderbuffers matching the attachments of a target.
+struct Multisample {
+    framebuffer: GlResource<WebGlFramebuffer>,
+    color: Vec<GlResource<WebGlRenderbuffer>>,
+    depth: Option<GlResource<WebGlRenderbuffer>>,
+}
+
+/// Framebuffer object rendered to instead of the canvas, whose texture
+/// attachments can then be used by materials. Its objects are deleted on
+/// drop, except for the textures still used by materials.
+pub struct RenderTarget {
+    framebuffer: GlResource<WebGlFramebuffer>,
+    color: Vec<Attachment>,
+    /// Missing when multisampled into a depth renderbuffer, which then only
+    /// exists multisampled.
+    depth: Option<Attachment>,
+    /// Rendered to instead of `framebuffer` when multisampling.
+    multisample: Option<Multisample>,
+    settings: RenderTargetSettings,
+    pub width: u32,
+    pub height: u32,
+    /// Samples per pixel after clamping, 1 without multisampling.
+    pub samples: u32,
+}
+
+impl RenderTarget {
+    pub fn new(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+        settings: &RenderTargetSettings,
+    ) -> Result<RenderTarget, EngineError> {
+        let context = &env.context;
+        validate(env, settings)?;
+        if width == 0 || height == 0 {
+            return Err(EngineError::InvalidArgument(format!(
+                "Invalid render target size {}x{}.",
+                width, height
+            )));
+        }
+
+        let framebuffer = env.resources.create::<WebGlFramebuffer>()?;
+        let samples = settings.samples.clamp(1, max_samples(context));
+        let mut target = RenderTarget {
+            framebuffer,
+            color: Vec::with_capacity(settings.color.len()),
+            depth: None,
+            multisample: None,
+            settings: settings.clone(),
+            width,
+            height,
+            samples,
+        };
+        target.attach(env, settings)?;
+        if samples > 1 {
+            target.attach_multisample(env, settings)?;
+        }
+        Ok(target)
+    }
+
+    /// Creates the attachments of `settings` and attaches them.
+    fn attach(
+        &mut self,
+        env: &GlEnvironment,
+        settings: &RenderTargetSettings,
+    ) -> Result<(), EngineError> {
+        let context = &env.context;
+        for attachment in &settings.color {
+            self.color.push(create_attachment(
+                env,
+                self.width,
+                self.height,
+                attachment,
+            )?);
+        }
+        let resolved_depth = settings.depth.filter(|depth| {
+            self.samples == 1
+                || depth.storage != AttachmentStorage::Renderbuffer
+        });
+        if let Some(attachment) = &resolved_depth {
+            self.depth = Some(create_attachment(
+                env,
+                self.width,
+                self.height,
+                attachment,
+            )?);
+        }
+
+        if self.color.is_empty() && self.depth.is_none() {
+            // Multisampled depth only, nothing to resolve to.
+            return Ok(());
+        }
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&self.framebuffer),
+        );
+        for (i, attachment) in self.color.iter().enumerate() {
+            attach(
+                context,
+                WebGl2RenderingContext::COLOR_ATTACHMENT0 + i as u32,
+                attachment,
+            );
+        }
+        if let (Some(attachment), Some(depth)) = (&self.depth, &resolved_depth)
+        {
+            attach(context, depth_attachment_point(depth.format), attachment);
+        }
+        check_status(context)
+    }
+
+    /// Creates the multisampled renderbuffers rendered to and attaches them
+    /// to their own framebuffer.
+    fn attach_multisample(
+        &mut self,
+        env: &GlEnvironment,
+        settings: &RenderTargetSettings,
+    ) -> Result<(), EngineError> {
+        let context = &env.context;
+        let (width, height, samples) = (self.width, self.height, self.samples);
+        let framebuffer = env.resources.create::<WebGlFramebuffer>()?;
+        let multisample = self.multisample.insert(Multisample {
+            framebuffer,
+            color: Vec::with_capacity(settings.color.len()),
+            depth: None,
+        });
+        for attachment in &settings.color {
+            multisample.color.push(create_renderbuffer(
+                env,
+                width,
+                height,
+                samples,
+                attachment.format,
+            )?);
+        }
+        if let Some(depth) = &settings.depth {
+            multisample.depth = Some(create_renderbuffer(
+                env,
+                width,
+                height,
+                samples,
+                depth.format,
+            )?);
+        }
+
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&multisample.framebuffer),
+        );
+        for (i, renderbuffer) in multisample.color.iter().enumerate() {
+            attach_renderbuffer(
+                context,
+                WebGl2RenderingContext::COLOR_ATTACHMENT0 + i as u32,
+                renderbuffer,
+            );
+        }
+        if let (Some(renderbuffer), Some(depth)) =
+            (&multisample.depth, &settings.depth)
+        {
+            attach_renderbuffer(
+                context,
+                depth_attachment_point(depth.format),
+                renderbuffer,
+            );
+        }
+        check_status(context)
+    }
+
+    /// Settings the target was created with, before clamping.
+    pub fn settings(&self) -> &RenderTargetSettings {
+        &self.settings
+    }
+
+    pub fn color_texture(&self, index: usize) -> Option<&Rc<Texture2D>> {
+        match self.color.get(index) {
+            Some(Attachment::Texture(texture)) => Some(texture),
+            _ => None,
+        }
+    }
+
+    pub fn depth_texture(&self) -> Option<&Rc<Texture2D>> {
+        match &self.depth {
+            Some(Attachment::Texture(texture)) => Some(texture),
+            _ => None,
+        }
+    }
+
+    /// Texture attachments, which can't be sampled while rendering to the
+    /// target.
+    pub fn textures(&self) -> impl Iterator<Item = &Rc<Texture2D>> {
+        self.color
+            .iter()
+            .chain(&self.depth)
+            .filter_map(|attachment| match attachment {
+                Attachment::Texture(texture) => Some(texture),
+                Attachment::Renderbuffer(_) => None,
+            })
+    }
+
+    /// Renders to the target from now on, to all its color attachments and
+    /// over its whole size.
+    pub fn bind(&self, context: &WebGl2RenderingContext) {
+        let framebuffer = match &self.multisample {
+            Some(multisample) => &multisample.framebuffer,
+            None => &self.framebuffer,
+        };
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(framebuffer),
+        );
+        if !self.color.is_empty() {
+            let buffers = js_sys::Array::new();
+            for i in 0..self.color.len() as u32 {
+                buffers.push(
+                    &(WebGl2RenderingContext::COLOR_ATTACHMENT0 + i).into(),
+                );
+            }
+            context.draw_buffers(&buffers);
+        }
+        context.viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    /// Copies the multisampled rendering to the attachments, which must be
+    /// done before sampling them. Does nothing without multisampling.
+    pub fn resolve(&self, env: &GlEnvironment) {
+        let multisample = match &self.multisample {
+            Some(multisample) => multisample,
+            None => return,
+        };
+        let context = &env.context;
+        // Blits are clipped by the scissor test.
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::full_screen());
+        context.bind_framebuffer(
+            WebGl2RenderingContext::READ_FRAMEBUFFER,
+            Some(&multisample.framebuffer),
+        );
+        context.bind_framebuffer(
+            WebGl2RenderingContext::DRAW_FRAMEBUFFER,
+            Some(&self.framebuffer),
+        );
+        let (width, height) = (self.width as i32, self.height as i32);
+        let blit = |mask| {
+            context.blit_framebuffer(
+                0,
+                0,
+                width,
+                height,
+                0,
+                0,
+                width,
+                height,
+                mask,
+                WebGl2RenderingContext::NEAREST,
+            )
+        };
+        // Only one color buffer is read at a time, each is blitted to the
+        // matching draw buffer.
+        for i in 0..self.color.len() as u32 {
+            let attachment = WebGl2RenderingContext::COLOR_ATTACHMENT0 + i;
+            context.read_buffer(attachment);
+            let buffers = js_sys::Array::new();
+            for _ in 0..i {
+                buffers.push(&WebGl2RenderingContext::NONE.into());
+            }
+            buffers.push(&attachment.into());
+            context.draw_buffers(&buffers);
+            blit(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+        }
+        if let (Some(_), Some(depth)) = (&self.depth, &self.settings.depth) {
+            let mut mask = WebGl2RenderingContext::DEPTH_BUFFER_BIT;
+            if depth.format == TextureFormat::Depth24Stencil8 {
+                mask |= WebGl2RenderingContext::STENCIL_BUFFER_BIT;
+            }
+            blit(mask);
+        }
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+    }
+
+    /// Regenerates the mipmaps of the color textures sampled with mipmaps,
+    /// after rendering.
+    pub fn update_mipmaps(&self, context: &WebGl2RenderingContext) {
+        for attachment in &self.color {
+            if let Attachment::Texture(texture) = attachment {
+                if texture.sampler().uses_mipmaps() {
+                    texture.generate_mipmaps(context);
+                }
+            }
+        }
+    }
+}
+
+/// Binds `target`, or the canvas when `None`. The viewport is only set for
+/// render targets.
+pub fn bind_target(
+    context: &WebGl2RenderingContext,
+    target: Option<&RenderTarget>,
+) {
+    match target {
+        Some(target) => target.bind(context),
+        None => {
+            context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None)
+        }
+    }
+}
+
+fn validate(
+    env: &GlEnvironment,
+    settings: &RenderTargetSettings,
+) -> Result<(), EngineError> {
+    let max_attachments = env
+        .context
+        .get_parameter(WebGl2RenderingContext::MAX_COLOR_ATTACHMENTS)
+        .ok()
+        .and_then(|value| value.as_f64())
+        .unwrap_or(4.0) as usize;
+    if settings.color.len() > max_attachments {
+        return Err(EngineError::Unsupported(format!(
+            "{} color attachments, at most {} are supported.",
+            settings.color.len(),
+            max_attachments
+        )));
+    }
+    for attachment in &settings.color {
+        if attachment.format.is_depth() {
+            return Err(EngineError::InvalidArgument(format!(
+                "{:?} can't be a color attachment.",
+                attachment.format
+            )));
+        }
+        if attachment.format.is_float_color() && !env.color_buffer_float {
+            return Err(EngineError::Unsupported(format!(
+                "Rendering to {:?} is not supported.",
+                attachment.format
+            )));
+        }
+    }
+    if let Some(depth) = &settings.depth {
+        if !depth.format.is_depth() {
+            return Err(EngineError::InvalidArgument(format!(
+                "{:?} can't be a depth attachment.",
+                depth.format
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn max_samples(context: &WebGl2RenderingContext) -> u32 {
+    context
+        .get_parameter(WebGl2RenderingContext::MAX_SAMPLES)
+        .ok()
+        .and_then(|value| value.as_f64())
+        .unwrap_or(1.0) as u32
+}
+
+fn depth_attachment_point(format: TextureFormat) -> u32 {
+    if format == TextureFormat::Depth24Stencil8 {
+        WebGl2RenderingContext::DEPTH_STENCIL_ATTACHMENT
+    } else {
+        WebGl2RenderingContext::DEPTH_ATTACHMENT
+    }
+}
+
+/// Checks the completeness of the bound framebuffer, and unbinds it.
+fn check_status(context: &WebGl2RenderingContext) -> Result<(), EngineError> {
+    let target = WebGl2RenderingContext::FRAMEBUFFER;
+    let status = context.check_framebuffer_status(target);
+    context.bind_framebuffer(target, None);
+    if status != WebGl2RenderingContext::FRAMEBUFFER_COMPLETE {
+        return Err(EngineError::IncompleteFramebuffer(status));
+    }
+    Ok(())
+}
+
+fn create_attachment(
+    env: &GlEnvironment,
+    width: u32,
+    height: u32,
+    settings: &AttachmentSettings,
+) -> Result<Attachment, EngineError> {
+    match settings.storage {
+        AttachmentStorage::Texture(sampler) => {
+            let texture =
+                Texture2D::empty(env, width, height, settings.format, sampler)?;
+            Ok(Attachment::Texture(Rc::new(texture)))
+        }
+        AttachmentStorage::Renderbuffer => {
+            let renderbuffer =
+                create_renderbuffer(env, width, height, 1, settings.format)?;
+            Ok(Attachment::Renderbuffer(renderbuffer))
+        }
+    }
+}
+
+fn create_renderbuffer(
+    env: &GlEnvironment,
+    width: u32,
+    height: u32,
+    samples: u32,
+    format: TextureFormat,
+) -> Result<GlResource<WebGlRenderbuffer>, EngineError> {
+    let context = &env.context;
+    let target = WebGl2RenderingContext::RENDERBUFFER;
+    let mut renderbuffer = env.resources.create::<WebGlRenderbuffer>()?;
+    renderbuffer.set_bytes(
+        (width * height * samples) as usize * format.bytes_per_pixel(),
+    );
+    let (internal_format, _, _) = format.gl_formats();
+    context.bind_renderbuffer(target, Some(&renderbuffer));
+    if samples > 1 {
+        context.renderbuffer_storage_multisample(
+            target,
+            samples as i32,
+            internal_format as u32,
+            width as i32,
+            height as i32,
+        );
+    } else {
+        context.renderbuffer_storage(
+            target,
+            internal_format as u32,
+            width as i32,
+            height as i32,
+        );
+    }
+    context.bind_renderbuffer(target, None);
+    Ok(renderbuffer)
+}
+
+/// Attaches `attachment` to `point` of the bound framebuffer.
+fn attach(
+    context: &WebGl2RenderingContext,
+    point: u32,
+    attachment: &Attachment,
+) {
+    match attachment {
+        Attachment::Texture(texture) => context.framebuffer_texture_2d(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            point,
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&texture.gl_texture),
+            0,
+        ),
+        Attachment::Renderbuffer(renderbuffer) => {
+            attach_renderbuffer(context, point, renderbuffer)
+        }
+    }
+}
+
+fn attach_renderbuffer(
+    context: &WebGl2RenderingContext,
+    point: u32,
+    renderbuffer: &WebGlRenderbuffer,
+) {
+    context.framebuffer_renderbuffer(
+        WebGl2RenderingContext::FRAMEBUFFER,
+        point,
+        WebGl2RenderingContext::RENDERBUFFER,
+        Some(renderbuffer),
+    );
+}
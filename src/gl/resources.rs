@@ -0,0 +1,276 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::wasm_bindgen;
+use web_sys::{
+    WebGl2RenderingContext, WebGlBuffer, WebGlFramebuffer, WebGlProgram,
+    WebGlRenderbuffer, WebGlTexture, WebGlVertexArrayObject,
+};
+
+use crate::error::EngineError;
+
+/// Kinds of GPU objects tracked by `Resources`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    Buffer,
+    VertexArray,
+    Texture,
+    Renderbuffer,
+    Framebuffer,
+    Program,
+}
+
+static KIND_COUNT: usize = 6;
+
+/// GPU objects alive in a context, and the bytes of their storage.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ResourceStats {
+    pub buffers: u32,
+    pub buffer_bytes: u32,
+    pub vertex_arrays: u32,
+    pub textures: u32,
+    pub texture_bytes: u32,
+    pub renderbuffers: u32,
+    pub renderbuffer_bytes: u32,
+    pub framebuffers: u32,
+    pub programs: u32,
+}
+
+#[wasm_bindgen]
+impl ResourceStats {
+    pub fn total_count(&self) -> u32 {
+        self.buffers
+            + self.vertex_arrays
+            + self.textures
+            + self.renderbuffers
+            + self.framebuffers
+            + self.programs
+    }
+
+    /// Estimated GPU memory held by buffers, textures and renderbuffers.
+    pub fn total_bytes(&self) -> u32 {
+        self.buffer_bytes + self.texture_bytes + self.renderbuffer_bytes
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Usage {
+    count: usize,
+    bytes: usize,
+}
+
+/// Live object counts and bytes per kind.
+#[derive(Debug, Default)]
+pub struct ResourceRegistry {
+    usage: [Usage; KIND_COUNT],
+}
+
+impl ResourceRegistry {
+    pub fn created(&mut self, kind: ResourceKind, bytes: usize) {
+        let usage = &mut self.usage[kind as usize];
+        usage.count += 1;
+        usage.bytes += bytes;
+    }
+
+    pub fn resized(&mut self, kind: ResourceKind, from: usize, to: usize) {
+        let usage = &mut self.usage[kind as usize];
+        usage.bytes = usage.bytes - from + to;
+    }
+
+    pub fn deleted(&mut self, kind: ResourceKind, bytes: usize) {
+        let usage = &mut self.usage[kind as usize];
+        usage.count -= 1;
+        usage.bytes -= bytes;
+    }
+
+    pub fn stats(&self) -> ResourceStats {
+        let usage = |kind: ResourceKind| self.usage[kind as usize];
+        ResourceStats {
+            buffers: usage(ResourceKind::Buffer).count as u32,
+            buffer_bytes: usage(ResourceKind::Buffer).bytes as u32,
+            vertex_arrays: usage(ResourceKind::VertexArray).count as u32,
+            textures: usage(ResourceKind::Texture).count as u32,
+            texture_bytes: usage(ResourceKind::Texture).bytes as u32,
+            renderbuffers: usage(ResourceKind::Renderbuffer).count as u32,
+            renderbuffer_bytes: usage(ResourceKind::Renderbuffer).bytes as u32,
+            framebuffers: usage(ResourceKind::Framebuffer).count as u32,
+            programs: usage(ResourceKind::Program).count as u32,
+        }
+    }
+}
+
+/// WebGL object type created and deleted through `Resources`.
+pub trait GlObject: Sized {
+    const KIND: ResourceKind;
+
+    fn create(context: &WebGl2RenderingContext) -> Option<Self>;
+
+    fn delete(&self, context: &WebGl2RenderingContext);
+}
+
+macro_rules! gl_object {
+    ($type:ty, $kind:ident, $create:ident, $delete:ident) => {
+        impl GlObject for $type {
+            const KIND: ResourceKind = ResourceKind::$kind;
+
+            fn create(context: &WebGl2RenderingContext) -> Option<Self> {
+                context.$create()
+            }
+
+            fn delete(&self, context: &WebGl2RenderingContext) {
+                context.$delete(Some(self));
+            }
+        }
+    };
+}
+
+gl_object!(WebGlBuffer, Buffer, create_buffer, delete_buffer);
+gl_object!(
+    WebGlVertexArrayObject,
+    VertexArray,
+    create_vertex_array,
+    delete_vertex_array
+);
+gl_object!(WebGlTexture, Texture, create_texture, delete_texture);
+gl_object!(
+    WebGlRenderbuffer,
+    Renderbuffer,
+    create_renderbuffer,
+    delete_renderbuffer
+);
+gl_object!(
+    WebGlFramebuffer,
+    Framebuffer,
+    create_framebuffer,
+    delete_framebuffer
+);
+gl_object!(WebGlProgram, Program, create_program, delete_program);
+
+/// Creates the GPU objects of a context, tracking them in a registry shared
+/// by every clone.
+#[derive(Clone)]
+pub struct Resources {
+    context: WebGl2RenderingContext,
+    registry: Rc<RefCell<ResourceRegistry>>,
+    /// Incremented when the context is lost, which invalidates the objects
+    /// created before.
+    generation: Rc<Cell<u32>>,
+}
+
+impl Resources {
+    pub fn new(context: &WebGl2RenderingContext) -> Resources {
+        Resources {
+            context: context.clone(),
+            registry: Rc::new(RefCell::new(ResourceRegistry::default())),
+            generation: Rc::new(Cell::new(0)),
+        }
+    }
+
+    pub fn context(&self) -> &WebGl2RenderingContext {
+        &self.context
+    }
+
+    pub fn stats(&self) -> ResourceStats {
+        self.registry.borrow().stats()
+    }
+
+    /// Marks every object created so far as lost with the context. They are
+    /// then no longer deleted when dropped.
+    pub fn invalidate(&self) {
+        self.generation.set(self.generation.get() + 1);
+    }
+
+    /// Creates an object, deleted when the returned handle is dropped.
+    pub fn create<T: GlObject>(&self) -> Result<GlResource<T>, EngineError> {
+        let object =
+            T::create(&self.context).ok_or(EngineError::Resource(T::KIND))?;
+        self.registry.borrow_mut().created(T::KIND, 0);
+        Ok(GlResource {
+            object,
+            bytes: 0,
+            generation: self.generation.get(),
+            resources: self.clone(),
+        })
+    }
+}
+
+/// Owned WebGL object, deleted on drop.
+pub struct GlResource<T: GlObject> {
+    object: T,
+    /// Estimated size of the storage of the object.
+    bytes: usize,
+    /// Generation of `resources` the object was created in.
+    generation: u32,
+    resources: Resources,
+}
+
+impl<T: GlObject> GlResource<T> {
+    /// Records that the storage of the object now takes `bytes`.
+    pub fn set_bytes(&mut self, bytes: usize) {
+        self.resources.registry.borrow_mut().resized(
+            T::KIND,
+            self.bytes,
+            bytes,
+        );
+        self.bytes = bytes;
+    }
+}
+
+impl<T: GlObject> Deref for GlResource<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.object
+    }
+}
+
+impl<T: GlObject> Drop for GlResource<T> {
+    fn drop(&mut self) {
+        if self.generation == self.resources.generation.get() {
+            self.object.delete(&self.resources.context);
+        }
+        self.resources
+            .registry
+            .borrow_mut()
+            .deleted(T::KIND, self.bytes);
+    }
+}
+
+impl<T: GlObject + PartialEq> PartialEq for GlResource<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.object == other.object
+    }
+}
+
+impl<T: GlObject + fmt::Debug> fmt::Debug for GlResource<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.object.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_tracks_live_objects() {
+        let mut registry = ResourceRegistry::default();
+        registry.created(ResourceKind::Buffer, 0);
+        registry.resized(ResourceKind::Buffer, 0, 256);
+        registry.created(ResourceKind::Buffer, 64);
+        registry.created(ResourceKind::Texture, 1024);
+        registry.created(ResourceKind::Program, 0);
+        registry.resized(ResourceKind::Buffer, 256, 128);
+        registry.deleted(ResourceKind::Buffer, 64);
+
+        let stats = registry.stats();
+        assert_eq!(stats.buffers, 1);
+        assert_eq!(stats.buffer_bytes, 128);
+        assert_eq!(stats.textures, 1);
+        assert_eq!(stats.total_count(), 3);
+        assert_eq!(stats.total_bytes(), 1152);
+    }
+}
@@ -0,0 +1,1328 @@
+use crate::error::EngineError;
+
+pub static UNLIT_VERTEX_SHADER: &str = r##"#version 300 es
+in vec3 position;
+in vec4 color;
+// Per-instance attributes, read as the identity matrix, white and zeros by
+// shapes without instances.
+in mat4 instanceMatrix;
+in vec4 instanceColor;
+in vec4 instanceData;
+out vec4 vColor;
+out vec3 vWorldPosition;
+
+#include <camera>
+
+uniform mat4 modelMatrix;
+
+void main() {
+    vec4 worldPosition = modelMatrix * instanceMatrix * vec4(position, 1.0);
+    vWorldPosition = worldPosition.xyz;
+    gl_Position = projectionMatrix * viewMatrix * worldPosition;
+    vColor = color * instanceColor;
+}
+"##;
+
+pub static UNLIT_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#include <camera>
+#include <alpha>
+#include <g_buffer>
+#include <output>
+#include <fog>
+
+in vec4 vColor;
+in vec3 vWorldPosition;
+
+void main() {
+    writeColor(vec4(applyFog(vColor.rgb, vWorldPosition), outputAlpha(vColor.a)));
+}
+"##;
+
+pub static LIT_VERTEX_SHADER: &str = r##"#version 300 es
+in vec3 position;
+in vec4 color;
+in vec3 normal;
+in vec2 uv;
+// Per-instance attributes, read as the identity matrix, white and zeros by
+// shapes without instances.
+in mat4 instanceMatrix;
+in vec4 instanceColor;
+in vec4 instanceData;
+out vec4 vColor;
+out vec3 vWorldPosition;
+out vec3 vNormal;
+out vec2 vUv;
+
+#include <camera>
+
+uniform mat4 modelMatrix;
+
+void main() {
+    mat4 model = modelMatrix * instanceMatrix;
+    vec4 worldPosition = model * vec4(position, 1.0);
+    // Transforms only carry a uniform scale, so the model matrix can be used
+    // for normals as well.
+    vNormal = mat3(model) * normal;
+    vWorldPosition = worldPosition.xyz;
+    vColor = color * instanceColor;
+    vUv = uv;
+    gl_Position = projectionMatrix * viewMatrix * worldPosition;
+}
+"##;
+
+/// Alpha modes of `material::AlphaMode`, shared by every material fragment
+/// shader.
+pub static ALPHA_CHUNK: &str = r##"
+#define ALPHA_OPAQUE 0
+#define ALPHA_MASK 1
+#define ALPHA_BLEND 2
+
+uniform int alphaMode;
+uniform float alphaCutoff;
+
+// Returns the alpha to output for a fragment of opacity `alpha`, discarding
+// the fragment when it is masked out.
+float outputAlpha(float alpha) {
+    if (alphaMode == ALPHA_BLEND) {
+        return alpha;
+    }
+    if (alphaMode == ALPHA_MASK && alpha < alphaCutoff) {
+        discard;
+    }
+    return 1.0;
+}
+"##;
+
+/// Encoding of the deferred G-buffer, shared by the material shaders and the
+/// deferred lighting passes.
+pub static G_BUFFER_CHUNK: &str = r##"
+// Shading models, stored in the alpha of the normal attachment.
+#define SHADING_UNLIT 0
+#define SHADING_BLINN_PHONG 1
+#define SHADING_PBR 2
+
+// Blinn-Phong shininess is stored as a fraction of this maximum.
+#define MAX_SHININESS 256.0
+
+// World position of the surface at `depth` in `texel` of a G-buffer drawn
+// in `viewport`.
+vec3 gBufferPosition(mat4 inverseViewProjection, vec4 viewport, ivec2 texel, float depth) {
+    vec2 ndc = (vec2(texel) + 0.5 - viewport.xy) / viewport.zw * 2.0 - 1.0;
+    vec4 position = inverseViewProjection * vec4(ndc, depth * 2.0 - 1.0, 1.0);
+    return position.xyz / position.w;
+}
+"##;
+
+/// Fragment outputs of the material shaders. With `WEIGHTED_BLENDED_OIT`,
+/// the color is accumulated for order-independent transparency instead,
+/// and with `DEFERRED` the surface is written to the G-buffer. Needs the
+/// g_buffer chunk.
+pub static OUTPUT_CHUNK: &str = r##"
+#if defined(DEFERRED)
+// Albedo in RGB, 1 in A if the surface receives shadows.
+layout(location = 0) out vec4 outAlbedo;
+// World space normal in RGB, shading model in A.
+layout(location = 1) out vec4 outNormal;
+// Metallic and roughness, or specular color and shininess.
+layout(location = 2) out vec4 outMaterial;
+// Light leaving the surface regardless of the punctual lights.
+layout(location = 3) out vec4 outEmission;
+
+void writeSurface(
+    int shading,
+    vec3 albedo,
+    vec3 normal,
+    vec4 parameters,
+    vec3 emission,
+    bool shadowed
+) {
+    outAlbedo = vec4(albedo, shadowed ? 1.0 : 0.0);
+    outNormal = vec4(normal, float(shading));
+    outMaterial = parameters;
+    outEmission = vec4(emission, 1.0);
+}
+
+void writeColor(vec4 color) {
+    writeSurface(SHADING_UNLIT, vec3(0.0), vec3(0.0), vec4(0.0), color.rgb, false);
+}
+#elif defined(WEIGHTED_BLENDED_OIT)
+// Premultiplied weighted color in RGB, revealage in A.
+layout(location = 0) out vec4 outAccumulation;
+// Weighted alpha in R.
+layout(location = 1) out vec4 outWeight;
+
+// Weighted Blended Order-Independent Transparency, McGuire and Bavoil 2013,
+// weight function of equation 10.
+// https://jcgt.org/published/0002/02/09/
+void writeColor(vec4 color) {
+    float depth = 1.0 - gl_FragCoord.z * 0.9;
+    float weight = clamp(
+        pow(min(1.0, color.a * 10.0) + 0.01, 3.0) * 1e8 * depth * depth * depth,
+        1e-2,
+        3e3
+    );
+    outAccumulation = vec4(color.rgb * color.a * weight, color.a);
+    outWeight = vec4(color.a * weight);
+}
+#else
+out vec4 outColor;
+
+void writeColor(vec4 color) {
+    outColor = color;
+}
+#endif
+"##;
+
+/// Per-frame camera values, shared by every program through a uniform
+/// buffer.
+pub static CAMERA_CHUNK: &str = r##"
+layout(std140) uniform Camera {
+    mat4 viewMatrix;
+    mat4 projectionMatrix;
+    mat4 viewProjectionMatrix;
+    mat4 inverseViewProjectionMatrix;
+    vec3 cameraPosition;
+    // Seconds since the renderer was created.
+    float time;
+    // Pixels the camera draws into: x, y, width and height. Intermediate
+    // targets are the size of the final one and drawn in the same pixels.
+    vec4 viewport;
+};
+"##;
+
+/// Lights uniform block and helpers shared by every lit fragment shader.
+pub static LIGHTS_CHUNK: &str = r##"
+#define MAX_LIGHTS 8
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT 1
+#define SPOT_LIGHT 2
+
+layout(std140) uniform Lights {
+    vec3 ambientLight;
+    int lightCount;
+    int lightTypes[MAX_LIGHTS];
+    vec3 lightPositions[MAX_LIGHTS];
+    vec3 lightDirections[MAX_LIGHTS];
+    // Color premultiplied by intensity.
+    vec3 lightColors[MAX_LIGHTS];
+    float lightRanges[MAX_LIGHTS];
+    // Cosines of the inner and outer cone angles.
+    vec2 lightCones[MAX_LIGHTS];
+};
+
+// https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_lights_punctual
+float rangeAttenuation(float distance, float range) {
+    float inverseSquare = 1.0 / max(distance * distance, 0.0001);
+    if (range <= 0.0) {
+        return inverseSquare;
+    }
+    return clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0) * inverseSquare;
+}
+
+float spotAttenuation(vec3 pointToLight, vec3 spotDirection, vec2 cones) {
+    float cd = dot(normalize(spotDirection), -pointToLight);
+    return smoothstep(cones.y, cones.x, cd);
+}
+
+// Returns the radiance of light `i` reaching `worldPosition` and sets `l` to
+// the normalized direction towards the light.
+vec3 lightRadiance(int i, vec3 worldPosition, out vec3 l) {
+    if (lightTypes[i] == DIRECTIONAL_LIGHT) {
+        l = -normalize(lightDirections[i]);
+        return lightColors[i];
+    }
+    vec3 pointToLight = lightPositions[i] - worldPosition;
+    float distance = length(pointToLight);
+    l = pointToLight / distance;
+    float attenuation = rangeAttenuation(distance, lightRanges[i]);
+    if (lightTypes[i] == SPOT_LIGHT) {
+        attenuation *= spotAttenuation(l, lightDirections[i], lightCones[i]);
+    }
+    return lightColors[i] * attenuation;
+}
+"##;
+
+/// Reflections of a cube map, shared by lit fragment shaders.
+pub static ENVIRONMENT_CHUNK: &str = r##"
+uniform samplerCube environmentMap;
+uniform bool hasEnvironmentMap;
+uniform float environmentMapMaxLod;
+
+// Samples the environment in `direction`, blurrier as `roughness` grows.
+vec3 sampleEnvironment(vec3 direction, float roughness) {
+    return textureLod(environmentMap, direction, roughness * environmentMapMaxLod).rgb;
+}
+"##;
+
+/// Screen-space ambient occlusion, shared by lit fragment shaders. Needs the
+/// alpha chunk.
+pub static AMBIENT_OCCLUSION_CHUNK: &str = r##"
+uniform sampler2D ambientOcclusionMap;
+uniform bool hasAmbientOcclusionMap;
+
+// Fraction of the ambient light reaching the fragment. Blended surfaces are
+// not in the map, which only holds the opaque ones.
+float ambientOcclusion() {
+    if (!hasAmbientOcclusionMap || alphaMode == ALPHA_BLEND) {
+        return 1.0;
+    }
+    vec2 uv = gl_FragCoord.xy / vec2(textureSize(ambientOcclusionMap, 0));
+    return texture(ambientOcclusionMap, uv).r;
+}
+"##;
+
+/// Shadow map sampling, shared by lit fragment shaders. Needs the camera
+/// and lights chunks.
+pub static SHADOWS_CHUNK: &str = r##"
+#define MAX_SHADOWED_LIGHTS 2
+#define MAX_CASCADES 4
+
+// Depth of every cascade of every shadowed light, at layer
+// `shadow * MAX_CASCADES + cascade`.
+uniform highp sampler2DArrayShadow shadowMap;
+uniform bool receiveShadows;
+
+// Shadow of each light, -1 for lights without shadow map.
+uniform int lightShadows[MAX_LIGHTS];
+uniform mat4 shadowMatrices[MAX_SHADOWED_LIGHTS * MAX_CASCADES];
+// Farthest view depth covered by each cascade.
+uniform vec4 shadowCascadeSplits[MAX_SHADOWED_LIGHTS];
+// Bias, normal bias, PCF radius in texels and cascade count.
+uniform vec4 shadowParameters[MAX_SHADOWED_LIGHTS];
+
+// Returns how much of light `i` reaches `worldPosition`, from 0 (fully in
+// shadow) to 1.
+float lightShadow(int i, vec3 worldPosition, vec3 normal) {
+    int shadow = lightShadows[i];
+    if (!receiveShadows || shadow < 0) {
+        return 1.0;
+    }
+    vec4 parameters = shadowParameters[shadow];
+    vec4 splits = shadowCascadeSplits[shadow];
+    int cascadeCount = int(parameters.w);
+    float viewDepth = -(viewMatrix * vec4(worldPosition, 1.0)).z;
+    int cascade = 0;
+    while (cascade < cascadeCount && viewDepth > splits[cascade]) {
+        ++cascade;
+    }
+    if (cascade == cascadeCount) {
+        return 1.0;
+    }
+
+    int layer = shadow * MAX_CASCADES + cascade;
+    vec4 lightSpace = shadowMatrices[layer]
+        * vec4(worldPosition + normal * parameters.y, 1.0);
+    vec3 coords = lightSpace.xyz / lightSpace.w * 0.5 + 0.5;
+    if (any(lessThan(coords, vec3(0.0))) || any(greaterThan(coords, vec3(1.0)))) {
+        return 1.0;
+    }
+
+    // Percentage-closer filtering over a square kernel, each tap being
+    // bilinearly filtered by the comparison sampler.
+    vec2 texelSize = 1.0 / vec2(textureSize(shadowMap, 0).xy);
+    int radius = int(parameters.z);
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; ++x) {
+        for (int y = -radius; y <= radius; ++y) {
+            vec2 uv = coords.xy + vec2(x, y) * texelSize;
+            lit += texture(shadowMap, vec4(uv, float(layer), coords.z - parameters.x));
+        }
+    }
+    float taps = float((2 * radius + 1) * (2 * radius + 1));
+    return lit / taps;
+}
+"##;
+
+/// Distance and height fog, shared by every material and the skybox. Needs
+/// the camera chunk.
+pub static FOG_CHUNK: &str = r##"
+#define FOG_NONE 0
+#define FOG_LINEAR 1
+#define FOG_EXPONENTIAL 2
+#define FOG_EXPONENTIAL_SQUARED 3
+
+uniform int fogMode;
+uniform vec3 fogColor;
+// Start and end of linear fog, or density of exponential fog in x.
+uniform vec2 fogParameters;
+uniform bool hasHeightFog;
+// Height, density at that height and falloff of the height fog.
+uniform vec3 heightFog;
+// Distance the skybox is fogged at.
+uniform float fogSkyDistance;
+
+// Fraction of the light leaving `worldPosition` that is replaced by fog
+// before reaching the camera.
+float fogAmount(vec3 worldPosition) {
+    float viewDistance = length(worldPosition - cameraPosition);
+    float visibility = 1.0;
+    if (fogMode == FOG_LINEAR) {
+        float range = max(fogParameters.y - fogParameters.x, 1e-4);
+        visibility = 1.0 - clamp((viewDistance - fogParameters.x) / range, 0.0, 1.0);
+    } else if (fogMode == FOG_EXPONENTIAL) {
+        visibility = exp(-fogParameters.x * viewDistance);
+    } else if (fogMode == FOG_EXPONENTIAL_SQUARED) {
+        float x = fogParameters.x * viewDistance;
+        visibility = exp(-x * x);
+    }
+    if (hasHeightFog) {
+        // Density integrated along the view ray, the density decreasing
+        // exponentially with height.
+        float falloff = max(heightFog.z, 1e-4);
+        float rise = (worldPosition.y - cameraPosition.y) * falloff;
+        float integral = abs(rise) > 1e-4 ? (1.0 - exp(-rise)) / rise : 1.0;
+        float density = heightFog.y * exp(-falloff * (cameraPosition.y - heightFog.x));
+        visibility *= exp(-density * viewDistance * integral);
+    }
+    return 1.0 - visibility;
+}
+
+vec3 applyFog(vec3 color, vec3 worldPosition) {
+#ifdef DEFERRED
+    // Deferred surfaces are fogged once shaded.
+    return color;
+#else
+    return mix(color, fogColor, fogAmount(worldPosition));
+#endif
+}
+"##;
+
+/// Metallic-roughness BRDF, see `math::brdf` for the reference
+/// implementation.
+pub static BRDF_CHUNK: &str = r##"
+#define PI 3.14159265359
+#define DIELECTRIC_F0 0.04
+
+float distributionGgx(float nDotH, float roughness) {
+    float alpha = roughness * roughness;
+    float alphaSq = alpha * alpha;
+    float f = nDotH * nDotH * (alphaSq - 1.0) + 1.0;
+    return alphaSq / (PI * f * f);
+}
+
+float visibilitySmithGgx(float nDotL, float nDotV, float roughness) {
+    float alpha = roughness * roughness;
+    float alphaSq = alpha * alpha;
+    float ggxV = nDotL * sqrt(nDotV * nDotV * (1.0 - alphaSq) + alphaSq);
+    float ggxL = nDotV * sqrt(nDotL * nDotL * (1.0 - alphaSq) + alphaSq);
+    float ggx = ggxV + ggxL;
+    return ggx > 0.0 ? 0.5 / ggx : 0.0;
+}
+
+vec3 fresnelSchlick(vec3 f0, float vDotH) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - vDotH, 0.0, 1.0), 5.0);
+}
+
+// Fresnel averaged over the specular lobe, for image based lighting.
+// https://seblagarde.wordpress.com/2011/08/17/hello-world/
+vec3 fresnelSchlickRoughness(vec3 f0, float nDotV, float roughness) {
+    vec3 f90 = max(vec3(1.0 - roughness), f0);
+    return f0 + (f90 - f0) * pow(1.0 - nDotV, 5.0);
+}
+
+// Diffuse and specular reflectance towards `v` of light coming from `l`.
+vec3 brdf(vec3 n, vec3 v, vec3 l, vec3 diffuseColor, vec3 f0, float roughness) {
+    vec3 h = normalize(l + v);
+    float nDotL = clamp(dot(n, l), 0.0, 1.0);
+    float nDotV = clamp(dot(n, v), 0.0001, 1.0);
+    float nDotH = clamp(dot(n, h), 0.0, 1.0);
+    float vDotH = clamp(dot(v, h), 0.0, 1.0);
+
+    vec3 fresnel = fresnelSchlick(f0, vDotH);
+    vec3 diffuse = (1.0 - fresnel) * diffuseColor / PI;
+    vec3 specular = fresnel * distributionGgx(nDotH, roughness)
+        * visibilitySmithGgx(nDotL, nDotV, roughness);
+    return diffuse + specular;
+}
+"##;
+
+pub static BLINN_PHONG_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#include <camera>
+#include <alpha>
+#include <g_buffer>
+#include <output>
+#include <lights>
+#include <shadows>
+#include <environment>
+#include <ambient_occlusion>
+#include <fog>
+
+struct BlinnPhongMaterial {
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+    float shininess;
+    float reflectivity;
+};
+
+uniform BlinnPhongMaterial material;
+
+in vec4 vColor;
+in vec3 vWorldPosition;
+in vec3 vNormal;
+
+void main() {
+    vec3 n = normalize(vNormal);
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    vec3 v = normalize(cameraPosition - vWorldPosition);
+    vec3 albedo = vColor.rgb;
+
+    vec3 ambient = ambientLight * material.ambient * albedo * ambientOcclusion();
+    float reflectivity = hasEnvironmentMap ? material.reflectivity : 0.0;
+    vec3 reflected = hasEnvironmentMap
+        ? sampleEnvironment(reflect(-v, n), 0.0) * material.specular
+        : vec3(0.0);
+
+#ifdef DEFERRED
+    // Reflections replace part of the lit color, so the light reflected by
+    // the surface is scaled down to match.
+    float lit = 1.0 - reflectivity;
+    outputAlpha(vColor.a);
+    writeSurface(
+        SHADING_BLINN_PHONG,
+        material.diffuse * albedo * lit,
+        n,
+        vec4(material.specular * lit, material.shininess / MAX_SHININESS),
+        mix(ambient, reflected, reflectivity),
+        receiveShadows
+    );
+#else
+    vec3 color = ambient;
+    for (int i = 0; i < MAX_LIGHTS; ++i) {
+        if (i >= lightCount) {
+            break;
+        }
+        vec3 l;
+        vec3 radiance = lightRadiance(i, vWorldPosition, l)
+            * lightShadow(i, vWorldPosition, n);
+        float nDotL = max(dot(n, l), 0.0);
+        vec3 h = normalize(l + v);
+        float specular = nDotL > 0.0
+            ? pow(max(dot(n, h), 0.0), material.shininess)
+            : 0.0;
+        color += radiance * (material.diffuse * albedo * nDotL
+            + material.specular * specular);
+    }
+
+    color = mix(color, reflected, reflectivity);
+
+    writeColor(vec4(applyFog(color, vWorldPosition), outputAlpha(vColor.a)));
+#endif
+}
+"##;
+
+/// Metallic-roughness shading.
+pub static PBR_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#include <camera>
+#include <alpha>
+#include <g_buffer>
+#include <output>
+#include <lights>
+#include <shadows>
+#include <environment>
+#include <ambient_occlusion>
+#include <brdf>
+#include <fog>
+
+struct PbrMaterial {
+    vec4 baseColor;
+    float metallic;
+    float roughness;
+    float occlusionStrength;
+    vec3 emissive;
+    float normalScale;
+    bool hasBaseColorTexture;
+    bool hasMetallicRoughnessTexture;
+    bool hasOcclusionTexture;
+    bool hasEmissiveTexture;
+    bool hasNormalTexture;
+};
+
+uniform PbrMaterial material;
+
+uniform sampler2D baseColorTexture;
+// Roughness in G, metallic in B as in glTF.
+uniform sampler2D metallicRoughnessTexture;
+uniform sampler2D occlusionTexture;
+uniform sampler2D emissiveTexture;
+uniform sampler2D normalTexture;
+
+in vec4 vColor;
+in vec3 vWorldPosition;
+in vec3 vNormal;
+in vec2 vUv;
+
+// Perturbs `n` with the normal map, building the tangent frame from screen
+// space derivatives so meshes don't need a tangent attribute.
+// http://www.thetenthplanet.de/archives/1180
+vec3 perturbNormal(vec3 n, vec3 position, vec2 uv) {
+    vec3 dp1 = dFdx(position);
+    vec3 dp2 = dFdy(position);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+    vec3 dp2perp = cross(dp2, n);
+    vec3 dp1perp = cross(n, dp1);
+    vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+    float invmax = inversesqrt(max(dot(t, t), dot(b, b)));
+    mat3 tbn = mat3(t * invmax, b * invmax, n);
+
+    vec3 mapped = texture(normalTexture, uv).xyz * 2.0 - 1.0;
+    mapped.xy *= material.normalScale;
+    return normalize(tbn * mapped);
+}
+
+void main() {
+    vec3 n = normalize(vNormal);
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    vec3 geometricNormal = n;
+    if (material.hasNormalTexture) {
+        n = perturbNormal(n, vWorldPosition, vUv);
+    }
+    vec3 v = normalize(cameraPosition - vWorldPosition);
+    float nDotV = clamp(dot(n, v), 0.0001, 1.0);
+
+    vec4 baseColor = material.baseColor * vColor;
+    if (material.hasBaseColorTexture) {
+        baseColor *= texture(baseColorTexture, vUv);
+    }
+    float metallic = material.metallic;
+    float roughness = material.roughness;
+    if (material.hasMetallicRoughnessTexture) {
+        vec4 metallicRoughness = texture(metallicRoughnessTexture, vUv);
+        roughness *= metallicRoughness.g;
+        metallic *= metallicRoughness.b;
+    }
+    metallic = clamp(metallic, 0.0, 1.0);
+    roughness = clamp(roughness, 0.04, 1.0);
+    float occlusion = material.hasOcclusionTexture
+        ? texture(occlusionTexture, vUv).r
+        : 1.0;
+    vec3 emissive = material.emissive;
+    if (material.hasEmissiveTexture) {
+        emissive *= texture(emissiveTexture, vUv).rgb;
+    }
+
+    vec3 diffuseColor = baseColor.rgb * (1.0 - metallic);
+    vec3 f0 = mix(vec3(DIELECTRIC_F0), baseColor.rgb, metallic);
+
+    vec3 ambient = ambientLight * diffuseColor;
+    if (hasEnvironmentMap) {
+        ambient += sampleEnvironment(reflect(-v, n), roughness)
+            * fresnelSchlickRoughness(f0, nDotV, roughness);
+    } else {
+        ambient += ambientLight * f0 * (1.0 - roughness);
+    }
+    ambient *= ambientOcclusion();
+    vec3 emission = mix(ambient, ambient * occlusion, material.occlusionStrength)
+        + emissive;
+
+#ifdef DEFERRED
+    outputAlpha(baseColor.a);
+    writeSurface(
+        SHADING_PBR,
+        baseColor.rgb,
+        n,
+        vec4(metallic, roughness, 0.0, 0.0),
+        emission,
+        receiveShadows
+    );
+#else
+    vec3 color = emission;
+    for (int i = 0; i < MAX_LIGHTS; ++i) {
+        if (i >= lightCount) {
+            break;
+        }
+        vec3 l;
+        vec3 radiance = lightRadiance(i, vWorldPosition, l);
+        float nDotL = clamp(dot(n, l), 0.0, 1.0);
+        if (nDotL <= 0.0) {
+            continue;
+        }
+        radiance *= lightShadow(i, vWorldPosition, geometricNormal);
+        color += radiance * nDotL
+            * brdf(n, v, l, diffuseColor, f0, roughness);
+    }
+
+    writeColor(vec4(applyFog(color, vWorldPosition), outputAlpha(baseColor.a)));
+#endif
+}
+"##;
+
+/// Unlit shading sampling `colorTexture` with the shape's UVs.
+pub static TEXTURED_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#include <camera>
+#include <alpha>
+#include <g_buffer>
+#include <output>
+#include <fog>
+
+uniform vec4 tint;
+uniform sampler2D colorTexture;
+
+in vec4 vColor;
+in vec3 vWorldPosition;
+in vec2 vUv;
+
+void main() {
+    vec4 color = texture(colorTexture, vUv) * tint * vColor;
+    writeColor(vec4(applyFog(color.rgb, vWorldPosition), outputAlpha(color.a)));
+}
+"##;
+
+/// Draws a triangle covering the screen, without any vertex attribute.
+pub static FULLSCREEN_VERTEX_SHADER: &str = r##"#version 300 es
+out vec2 vUv;
+
+void main() {
+    vec2 position = vec2(
+        gl_VertexID == 1 ? 3.0 : -1.0,
+        gl_VertexID == 2 ? 3.0 : -1.0
+    );
+    vUv = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+"##;
+
+/// Renders face `face` of a cube map from an equirectangular texture.
+pub static EQUIRECT_TO_CUBE_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#define PI 3.14159265359
+
+uniform sampler2D equirectangularMap;
+uniform int face;
+
+in vec2 vUv;
+out vec4 outColor;
+
+// Direction of a texel of a face, following the cube map conventions of the
+// OpenGL ES specification.
+vec3 faceDirection(vec2 uv) {
+    vec2 c = uv * 2.0 - 1.0;
+    if (face == 0) return vec3(1.0, -c.y, -c.x);
+    if (face == 1) return vec3(-1.0, -c.y, c.x);
+    if (face == 2) return vec3(c.x, 1.0, c.y);
+    if (face == 3) return vec3(c.x, -1.0, -c.y);
+    if (face == 4) return vec3(c.x, -c.y, 1.0);
+    return vec3(-c.x, -c.y, -1.0);
+}
+
+void main() {
+    vec3 d = normalize(faceDirection(vUv));
+    vec2 uv = vec2(
+        atan(d.z, d.x) / (2.0 * PI) + 0.5,
+        0.5 - asin(clamp(d.y, -1.0, 1.0)) / PI
+    );
+    // Explicit LOD, derivatives are discontinuous where atan wraps around.
+    outColor = textureLod(equirectangularMap, uv, 0.0);
+}
+"##;
+
+/// Composites weighted blended transparency over the opaque image.
+pub static OIT_COMPOSITE_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D accumulation;
+uniform sampler2D weight;
+
+out vec4 outColor;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 accumulated = texelFetch(accumulation, texel, 0);
+    float revealage = accumulated.a;
+    if (revealage >= 1.0) {
+        discard;
+    }
+    float totalWeight = max(texelFetch(weight, texel, 0).r, 1e-5);
+    outColor = vec4(accumulated.rgb / totalWeight, 1.0 - revealage);
+}
+"##;
+
+/// Scales the HDR color by `exposure` and maps it to [0, 1].
+pub static TONE_MAPPING_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#define REINHARD 0
+#define ACES 1
+
+uniform sampler2D source;
+uniform float exposure;
+uniform int toneMapping;
+
+in vec2 vUv;
+out vec4 outColor;
+
+// Fit of the ACES filmic curve by Krzysztof Narkowicz.
+// https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec4 color = texture(source, vUv);
+    vec3 exposed = color.rgb * exposure;
+    vec3 mapped = toneMapping == ACES ? aces(exposed) : exposed / (1.0 + exposed);
+    outColor = vec4(mapped, color.a);
+}
+"##;
+
+/// Encodes linear colors to sRGB.
+pub static SRGB_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D source;
+
+in vec2 vUv;
+out vec4 outColor;
+
+vec3 linearToSrgb(vec3 color) {
+    vec3 c = clamp(color, 0.0, 1.0);
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+void main() {
+    vec4 color = texture(source, vUv);
+    outColor = vec4(linearToSrgb(color.rgb), color.a);
+}
+"##;
+
+/// Fast approximate anti-aliasing, the reduced quality variant of FXAA by
+/// Timothy Lottes. Expects a [0, 1] perceptual input.
+pub static FXAA_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#define REDUCE_MIN (1.0 / 128.0)
+#define REDUCE_MUL (1.0 / 8.0)
+#define SPAN_MAX 8.0
+
+uniform sampler2D source;
+uniform vec2 texelSize;
+
+in vec2 vUv;
+out vec4 outColor;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+    vec4 center = texture(source, vUv);
+    float lumaNW = luma(texture(source, vUv + vec2(-1.0, -1.0) * texelSize).rgb);
+    float lumaNE = luma(texture(source, vUv + vec2(1.0, -1.0) * texelSize).rgb);
+    float lumaSW = luma(texture(source, vUv + vec2(-1.0, 1.0) * texelSize).rgb);
+    float lumaSE = luma(texture(source, vUv + vec2(1.0, 1.0) * texelSize).rgb);
+    float lumaM = luma(center.rgb);
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    // Blur along the edge, perpendicular to the luma gradient.
+    vec2 direction = vec2(
+        (lumaSW + lumaSE) - (lumaNW + lumaNE),
+        (lumaNW + lumaSW) - (lumaNE + lumaSE)
+    );
+    float reduce = max(
+        (lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * REDUCE_MUL,
+        REDUCE_MIN
+    );
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, -SPAN_MAX, SPAN_MAX) * texelSize;
+
+    vec3 colorA = 0.5 * (
+        texture(source, vUv + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(source, vUv + direction * (2.0 / 3.0 - 0.5)).rgb
+    );
+    vec3 colorB = colorA * 0.5 + 0.25 * (
+        texture(source, vUv - direction * 0.5).rgb +
+        texture(source, vUv + direction * 0.5).rgb
+    );
+    float lumaB = luma(colorB);
+    // The wider blur crossed another edge, keep the narrow one.
+    bool outside = lumaB < lumaMin || lumaB > lumaMax;
+    outColor = vec4(outside ? colorA : colorB, center.a);
+}
+"##;
+
+/// Keeps the part of the colors brighter than `threshold`.
+pub static BLOOM_THRESHOLD_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D source;
+uniform float threshold;
+
+in vec2 vUv;
+out vec4 outColor;
+
+void main() {
+    vec3 color = texture(source, vUv).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    float contribution = max(brightness - threshold, 0.0) / max(brightness, 1e-5);
+    outColor = vec4(color * contribution, 1.0);
+}
+"##;
+
+/// One direction of a separable 9-tap Gaussian blur, sampled between texels
+/// to only take 5 samples.
+pub static BLUR_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D source;
+// Offset between two texels along the blur direction, in UVs.
+uniform vec2 direction;
+
+in vec2 vUv;
+out vec4 outColor;
+
+const float offsets[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float weights[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    vec3 color = texture(source, vUv).rgb * weights[0];
+    for (int i = 1; i < 3; i++) {
+        color += texture(source, vUv + direction * offsets[i]).rgb * weights[i];
+        color += texture(source, vUv - direction * offsets[i]).rgb * weights[i];
+    }
+    outColor = vec4(color, 1.0);
+}
+"##;
+
+/// Adds the blurred bright parts over the image.
+pub static BLOOM_COMBINE_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D source;
+uniform sampler2D bloom;
+uniform float intensity;
+
+in vec2 vUv;
+out vec4 outColor;
+
+void main() {
+    vec4 color = texture(source, vUv);
+    outColor = vec4(color.rgb + texture(bloom, vUv).rgb * intensity, color.a);
+}
+"##;
+
+/// Darkens the corners of the image.
+pub static VIGNETTE_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D source;
+uniform float intensity;
+uniform float smoothness;
+
+in vec2 vUv;
+out vec4 outColor;
+
+void main() {
+    vec4 color = texture(source, vUv);
+    // 0 at the center, 1 in the corners.
+    float radius = length(vUv - 0.5) * 1.41421356;
+    float amount = smoothstep(1.0 - smoothness, 1.0, radius);
+    outColor = vec4(color.rgb * (1.0 - intensity * amount), color.a);
+}
+"##;
+
+/// Copies the depth and emission of the G-buffer, leaving the background
+/// untouched.
+pub static DEFERRED_EMISSION_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#include <camera>
+#include <g_buffer>
+#include <fog>
+
+uniform sampler2D emissionBuffer;
+uniform sampler2D depthBuffer;
+
+out vec4 outColor;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(depthBuffer, texel, 0).r;
+    if (depth >= 1.0) {
+        discard;
+    }
+    gl_FragDepth = depth;
+    vec3 worldPosition = gBufferPosition(
+        inverseViewProjectionMatrix,
+        viewport,
+        texel,
+        depth
+    );
+    vec3 emission = texelFetch(emissionBuffer, texel, 0).rgb;
+    outColor = vec4(applyFog(emission, worldPosition), 1.0);
+}
+"##;
+
+/// Covers the range of a deferred light: the cube bounding the sphere of
+/// radius `volumeRadius`, or the whole screen without `lightVolume`.
+pub static DEFERRED_LIGHT_VERTEX_SHADER: &str = r##"#version 300 es
+#include <camera>
+
+uniform bool lightVolume;
+uniform vec3 volumeCenter;
+uniform float volumeRadius;
+
+// Corners of the faces, counter-clockwise seen from outside, with the x, y
+// and z of each corner in bits 0, 1 and 2.
+const int cubeCorners[36] = int[36](
+    0, 4, 6, 0, 6, 2,
+    1, 3, 7, 1, 7, 5,
+    0, 1, 5, 0, 5, 4,
+    2, 6, 7, 2, 7, 3,
+    0, 2, 3, 0, 3, 1,
+    4, 5, 7, 4, 7, 6
+);
+
+void main() {
+    if (!lightVolume) {
+        vec2 position = vec2(
+            gl_VertexID == 1 ? 3.0 : -1.0,
+            gl_VertexID == 2 ? 3.0 : -1.0
+        );
+        gl_Position = vec4(position, 0.0, 1.0);
+        return;
+    }
+    int corner = cubeCorners[gl_VertexID];
+    vec3 offset = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) * 2.0 - 1.0;
+    vec4 worldPosition = vec4(volumeCenter + offset * volumeRadius, 1.0);
+    gl_Position = projectionMatrix * viewMatrix * worldPosition;
+    // Keeps back faces beyond the far plane from being clipped.
+    gl_Position.z = min(gl_Position.z, gl_Position.w);
+}
+"##;
+
+/// Adds the light in the first slot of the light uniforms to the surfaces
+/// of the G-buffer.
+pub static DEFERRED_LIGHT_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#include <camera>
+#include <g_buffer>
+#include <lights>
+#include <shadows>
+#include <brdf>
+#include <fog>
+
+uniform sampler2D albedoBuffer;
+uniform sampler2D normalBuffer;
+uniform sampler2D materialBuffer;
+uniform sampler2D depthBuffer;
+
+out vec4 outColor;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(depthBuffer, texel, 0).r;
+    vec4 normal = texelFetch(normalBuffer, texel, 0);
+    int shading = int(normal.a + 0.5);
+    if (depth >= 1.0 || shading == SHADING_UNLIT) {
+        discard;
+    }
+    vec4 albedo = texelFetch(albedoBuffer, texel, 0);
+    vec4 parameters = texelFetch(materialBuffer, texel, 0);
+
+    vec3 worldPosition = gBufferPosition(
+        inverseViewProjectionMatrix,
+        viewport,
+        texel,
+        depth
+    );
+    vec3 n = normalize(normal.xyz);
+    vec3 v = normalize(cameraPosition - worldPosition);
+
+    vec3 l;
+    vec3 radiance = lightRadiance(0, worldPosition, l);
+    float nDotL = clamp(dot(n, l), 0.0, 1.0);
+    if (nDotL <= 0.0) {
+        discard;
+    }
+    if (albedo.a > 0.5) {
+        radiance *= lightShadow(0, worldPosition, n);
+    }
+
+    vec3 color;
+    if (shading == SHADING_BLINN_PHONG) {
+        vec3 h = normalize(l + v);
+        float specular = pow(max(dot(n, h), 0.0), parameters.a * MAX_SHININESS);
+        color = radiance * (albedo.rgb * nDotL + parameters.rgb * specular);
+    } else {
+        float metallic = parameters.r;
+        vec3 diffuseColor = albedo.rgb * (1.0 - metallic);
+        vec3 f0 = mix(vec3(DIELECTRIC_F0), albedo.rgb, metallic);
+        color = radiance * nDotL
+            * brdf(n, v, l, diffuseColor, f0, parameters.g);
+    }
+    // Fog replaces part of the light, as applied to the emission.
+    outColor = vec4(color * (1.0 - fogAmount(worldPosition)), 0.0);
+}
+"##;
+
+/// Writes view space normals and view depths, for screen-space effects.
+pub static NORMAL_DEPTH_VERTEX_SHADER: &str = r##"#version 300 es
+in vec3 position;
+in vec3 normal;
+in mat4 instanceMatrix;
+out vec3 vNormal;
+out float vDepth;
+
+#include <camera>
+
+uniform mat4 modelMatrix;
+
+void main() {
+    mat4 model = modelMatrix * instanceMatrix;
+    vec4 viewPosition = viewMatrix * model * vec4(position, 1.0);
+    // Transforms only carry a uniform scale, so the matrices can be used for
+    // normals as well.
+    vNormal = mat3(viewMatrix) * mat3(model) * normal;
+    vDepth = -viewPosition.z / viewPosition.w;
+    gl_Position = projectionMatrix * viewPosition;
+}
+"##;
+
+pub static NORMAL_DEPTH_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+in vec3 vNormal;
+in float vDepth;
+out vec4 outNormalDepth;
+
+void main() {
+    // Shapes without normals face the camera.
+    vec3 n = length(vNormal) > 0.0 ? normalize(vNormal) : vec3(0.0, 0.0, 1.0);
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    outNormalDepth = vec4(n, vDepth);
+}
+"##;
+
+/// Screen-space ambient occlusion, sampling a hemisphere kernel around each
+/// pixel, randomly rotated by a tiled noise texture.
+pub static SSAO_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#define MAX_SAMPLES 64
+
+#include <camera>
+
+// View space normal in RGB, view depth in A, 0 where nothing was drawn.
+uniform sampler2D normalDepth;
+uniform sampler2D noise;
+uniform vec3 kernel[MAX_SAMPLES];
+uniform int sampleCount;
+uniform float radius;
+uniform float bias;
+uniform float intensity;
+
+in vec2 vUv;
+out vec4 outColor;
+
+// Coordinates in the targets of `uv` in the viewport.
+vec2 targetUv(vec2 uv) {
+    return (viewport.xy + uv * viewport.zw) / vec2(textureSize(normalDepth, 0));
+}
+
+// View space position of the surface seen at `uv` in the viewport, `depth`
+// away.
+vec3 viewPosition(vec2 uv, float depth) {
+    vec2 ndc = uv * 2.0 - 1.0;
+    return vec3(
+        depth * (ndc.x + projectionMatrix[2][0]) / projectionMatrix[0][0],
+        depth * (ndc.y + projectionMatrix[2][1]) / projectionMatrix[1][1],
+        -depth
+    );
+}
+
+void main() {
+    vec4 center = texture(normalDepth, targetUv(vUv));
+    if (center.a <= 0.0) {
+        outColor = vec4(1.0);
+        return;
+    }
+    vec3 origin = viewPosition(vUv, center.a);
+    vec3 normal = normalize(center.rgb);
+
+    vec3 randomVector = texture(noise, gl_FragCoord.xy / vec2(textureSize(noise, 0))).xyz;
+    vec3 tangent = normalize(randomVector - normal * dot(randomVector, normal));
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float occlusion = 0.0;
+    for (int i = 0; i < MAX_SAMPLES; ++i) {
+        if (i >= sampleCount) {
+            break;
+        }
+        vec3 samplePosition = origin + tbn * kernel[i] * radius;
+        vec4 clip = projectionMatrix * vec4(samplePosition, 1.0);
+        // Clamped like the edges of a target, the rest of it may belong to
+        // another camera.
+        vec2 sampleUv = clamp(clip.xy / clip.w * 0.5 + 0.5, 0.0, 1.0);
+        float sceneDepth = texture(normalDepth, targetUv(sampleUv)).a;
+        if (sceneDepth <= 0.0) {
+            continue;
+        }
+        // Occluders much farther than the radius are unrelated surfaces.
+        float range = smoothstep(0.0, 1.0, radius / abs(center.a - sceneDepth));
+        occlusion += (sceneDepth <= -samplePosition.z - bias ? 1.0 : 0.0) * range;
+    }
+    float visibility = 1.0 - intensity * occlusion / float(sampleCount);
+    outColor = vec4(vec3(clamp(visibility, 0.0, 1.0)), 1.0);
+}
+"##;
+
+/// Blurs the ambient occlusion over the 4x4 tile of the noise texture,
+/// without blurring across depth discontinuities.
+pub static SSAO_BLUR_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D ambientOcclusion;
+uniform sampler2D normalDepth;
+uniform vec2 texelSize;
+
+in vec2 vUv;
+out vec4 outColor;
+
+void main() {
+    vec2 center = gl_FragCoord.xy * texelSize;
+    float depth = texture(normalDepth, center).a;
+    float sum = 0.0;
+    float weights = 0.0;
+    for (int x = -2; x < 2; ++x) {
+        for (int y = -2; y < 2; ++y) {
+            vec2 uv = center + vec2(float(x), float(y)) * texelSize;
+            float sampleDepth = texture(normalDepth, uv).a;
+            float difference = abs(depth - sampleDepth) / max(depth, 1e-3);
+            float weight = exp(-difference * 50.0);
+            sum += texture(ambientOcclusion, uv).r * weight;
+            weights += weight;
+        }
+    }
+    outColor = vec4(vec3(sum / max(weights, 1e-5)), 1.0);
+}
+"##;
+
+/// Shows the ambient occlusion in grayscale.
+pub static SSAO_DEBUG_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+uniform sampler2D ambientOcclusion;
+
+in vec2 vUv;
+out vec4 outColor;
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / vec2(textureSize(ambientOcclusion, 0));
+    outColor = vec4(vec3(texture(ambientOcclusion, uv).r), 1.0);
+}
+"##;
+
+pub static SKYBOX_VERTEX_SHADER: &str = r##"#version 300 es
+in vec3 position;
+out vec3 vDirection;
+
+#include <camera>
+
+void main() {
+    vDirection = position;
+    // Only the rotation of the camera is applied, so the sky never gets
+    // closer. Setting z to w puts it on the far plane.
+    vec4 clipPosition = projectionMatrix * mat4(mat3(viewMatrix)) * vec4(position, 1.0);
+    gl_Position = clipPosition.xyww;
+}
+"##;
+
+pub static SKYBOX_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+#include <camera>
+#include <fog>
+
+uniform samplerCube skybox;
+uniform float intensity;
+
+in vec3 vDirection;
+out vec4 outColor;
+
+void main() {
+    vec3 direction = normalize(vDirection);
+    vec3 color = texture(skybox, direction).rgb * intensity;
+    vec3 skyPosition = cameraPosition + direction * fogSkyDistance;
+    outColor = vec4(applyFog(color, skyPosition), 1.0);
+}
+"##;
+
+/// Only renders depth, e.g. into shadow maps.
+pub static DEPTH_VERTEX_SHADER: &str = r##"#version 300 es
+in vec3 position;
+in mat4 instanceMatrix;
+
+uniform mat4 modelMatrix;
+uniform mat4 viewProjectionMatrix;
+
+void main() {
+    gl_Position =
+        viewProjectionMatrix * modelMatrix * instanceMatrix * vec4(position, 1.0);
+}
+"##;
+
+pub static DEPTH_FRAGMENT_SHADER: &str = r##"#version 300 es
+precision highp float;
+
+void main() {}
+"##;
+
+static CHUNKS: [(&str, &str); 10] = [
+    ("camera", CAMERA_CHUNK),
+    ("alpha", ALPHA_CHUNK),
+    ("g_buffer", G_BUFFER_CHUNK),
+    ("output", OUTPUT_CHUNK),
+    ("lights", LIGHTS_CHUNK),
+    ("shadows", SHADOWS_CHUNK),
+    ("environment", ENVIRONMENT_CHUNK),
+    ("ambient_occlusion", AMBIENT_OCCLUSION_CHUNK),
+    ("brdf", BRDF_CHUNK),
+    ("fog", FOG_CHUNK),
+];
+
+/// Replaces `#include <name>` lines with the matching shader chunk.
+pub fn resolve_includes(source: &str) -> Result<String, EngineError> {
+    let mut resolved = String::with_capacity(source.len());
+    for (index, line) in source.lines().enumerate() {
+        let include = line
+            .trim()
+            .strip_prefix("#include <")
+            .and_then(|rest| rest.strip_suffix('>'));
+        match include {
+            Some(name) => {
+                let chunk = CHUNKS
+                    .iter()
+                    .find(|(chunk_name, _)| *chunk_name == name)
+                    .map(|(_, chunk)| chunk)
+                    .ok_or_else(|| EngineError::ShaderCompile {
+                        log: format!("Unknown shader chunk <{}>.", name),
+                        line: Some(index as u32 + 1),
+                        source_line: Some(String::from(line.trim())),
+                    })?;
+                resolved.push_str(chunk);
+            }
+            None => resolved.push_str(line),
+        }
+        resolved.push('\n');
+    }
+    Ok(resolved)
+}
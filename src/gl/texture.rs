@@ -0,0 +1,670 @@
+use wasm_bindgen::JsValue;
+use web_sys::{HtmlImageElement, WebGl2RenderingContext, WebGlTexture};
+
+use crate::environment::GlEnvironment;
+use crate::error::EngineError;
+use crate::gl::resources::GlResource;
+#[cfg(feature = "image-decoding")]
+use crate::image::DecodedImage;
+
+/// `EXT_texture_filter_anisotropic` enums, not exposed by web-sys.
+pub const TEXTURE_MAX_ANISOTROPY_EXT: u32 = 0x84FE;
+pub const MAX_TEXTURE_MAX_ANISOTROPY_EXT: u32 = 0x84FF;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextureFormat {
+    Rgba8,
+    /// 8-bit RGBA in the sRGB color space, decoded to linear when sampled.
+    Srgb8Alpha8,
+    Rgba16F,
+    /// Not filterable unless `OES_texture_float_linear` is available.
+    Rgba32F,
+    /// Single channel half float, only written by rendering.
+    R16F,
+    Depth24,
+    Depth32F,
+    Depth24Stencil8,
+}
+
+impl TextureFormat {
+    /// Returns the (internal format, format, type) triple of `texImage2D`.
+    pub fn gl_formats(&self) -> (i32, u32, u32) {
+        match self {
+            TextureFormat::Rgba8 => (
+                WebGl2RenderingContext::RGBA8 as i32,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+            ),
+            TextureFormat::Srgb8Alpha8 => (
+                WebGl2RenderingContext::SRGB8_ALPHA8 as i32,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+            ),
+            TextureFormat::Rgba16F => (
+                WebGl2RenderingContext::RGBA16F as i32,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::FLOAT,
+            ),
+            TextureFormat::Rgba32F => (
+                WebGl2RenderingContext::RGBA32F as i32,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::FLOAT,
+            ),
+            TextureFormat::R16F => (
+                WebGl2RenderingContext::R16F as i32,
+                WebGl2RenderingContext::RED,
+                WebGl2RenderingContext::FLOAT,
+            ),
+            TextureFormat::Depth24 => (
+                WebGl2RenderingContext::DEPTH_COMPONENT24 as i32,
+                WebGl2RenderingContext::DEPTH_COMPONENT,
+                WebGl2RenderingContext::UNSIGNED_INT,
+            ),
+            TextureFormat::Depth32F => (
+                WebGl2RenderingContext::DEPTH_COMPONENT32F as i32,
+                WebGl2RenderingContext::DEPTH_COMPONENT,
+                WebGl2RenderingContext::FLOAT,
+            ),
+            TextureFormat::Depth24Stencil8 => (
+                WebGl2RenderingContext::DEPTH24_STENCIL8 as i32,
+                WebGl2RenderingContext::DEPTH_STENCIL,
+                WebGl2RenderingContext::UNSIGNED_INT_24_8,
+            ),
+        }
+    }
+
+    /// Whether rendering to the format needs `EXT_color_buffer_float`.
+    pub fn is_float_color(&self) -> bool {
+        matches!(
+            self,
+            TextureFormat::Rgba16F
+                | TextureFormat::Rgba32F
+                | TextureFormat::R16F
+        )
+    }
+
+    /// Bytes a pixel takes on the GPU, assuming no padding.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            TextureFormat::R16F => 2,
+            TextureFormat::Rgba8
+            | TextureFormat::Srgb8Alpha8
+            | TextureFormat::Depth24
+            | TextureFormat::Depth32F
+            | TextureFormat::Depth24Stencil8 => 4,
+            TextureFormat::Rgba16F => 8,
+            TextureFormat::Rgba32F => 16,
+        }
+    }
+
+    /// Whether `generateMipmap` accepts the format, which must be both
+    /// color renderable and filterable.
+    pub fn can_generate_mipmaps(
+        &self,
+        color_buffer_float: bool,
+        texture_float_linear: bool,
+    ) -> bool {
+        match self {
+            TextureFormat::Rgba8 | TextureFormat::Srgb8Alpha8 => true,
+            TextureFormat::Rgba16F | TextureFormat::R16F => color_buffer_float,
+            TextureFormat::Rgba32F => {
+                color_buffer_float && texture_float_linear
+            }
+            TextureFormat::Depth24
+            | TextureFormat::Depth32F
+            | TextureFormat::Depth24Stencil8 => false,
+        }
+    }
+
+    pub fn is_depth(&self) -> bool {
+        matches!(
+            self,
+            TextureFormat::Depth24
+                | TextureFormat::Depth32F
+                | TextureFormat::Depth24Stencil8
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat,
+}
+
+impl Wrap {
+    fn to_gl(self) -> i32 {
+        (match self {
+            Wrap::Repeat => WebGl2RenderingContext::REPEAT,
+            Wrap::ClampToEdge => WebGl2RenderingContext::CLAMP_TO_EDGE,
+            Wrap::MirroredRepeat => WebGl2RenderingContext::MIRRORED_REPEAT,
+        }) as i32
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SamplerSettings {
+    pub wrap_s: Wrap,
+    pub wrap_t: Wrap,
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    /// Filter between mipmap levels, `None` disables mipmapping.
+    pub mipmap_filter: Option<Filter>,
+    /// Maximum anisotropy, clamped to what the device supports. `1.0`
+    /// disables anisotropic filtering.
+    pub anisotropy: f32,
+}
+
+impl SamplerSettings {
+    pub fn uses_mipmaps(&self) -> bool {
+        self.mipmap_filter.is_some()
+    }
+
+    fn gl_min_filter(&self) -> i32 {
+        (match (self.min_filter, self.mipmap_filter) {
+            (Filter::Nearest, None) => WebGl2RenderingContext::NEAREST,
+            (Filter::Linear, None) => WebGl2RenderingContext::LINEAR,
+            (Filter::Nearest, Some(Filter::Nearest)) => {
+                WebGl2RenderingContext::NEAREST_MIPMAP_NEAREST
+            }
+            (Filter::Nearest, Some(Filter::Linear)) => {
+                WebGl2RenderingContext::NEAREST_MIPMAP_LINEAR
+            }
+            (Filter::Linear, Some(Filter::Nearest)) => {
+                WebGl2RenderingContext::LINEAR_MIPMAP_NEAREST
+            }
+            (Filter::Linear, Some(Filter::Linear)) => {
+                WebGl2RenderingContext::LINEAR_MIPMAP_LINEAR
+            }
+        }) as i32
+    }
+
+    fn gl_mag_filter(&self) -> i32 {
+        (match self.mag_filter {
+            Filter::Nearest => WebGl2RenderingContext::NEAREST,
+            Filter::Linear => WebGl2RenderingContext::LINEAR,
+        }) as i32
+    }
+}
+
+/// `sampler` without mipmapping when mipmaps of `format` can't be
+/// generated, so that the texture stays complete.
+pub fn supported_sampler(
+    env: &GlEnvironment,
+    format: TextureFormat,
+    sampler: SamplerSettings,
+) -> SamplerSettings {
+    if format
+        .can_generate_mipmaps(env.color_buffer_float, env.texture_float_linear)
+    {
+        sampler
+    } else {
+        SamplerSettings {
+            mipmap_filter: None,
+            ..sampler
+        }
+    }
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        SamplerSettings {
+            wrap_s: Wrap::Repeat,
+            wrap_t: Wrap::Repeat,
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_filter: Some(Filter::Linear),
+            anisotropy: 1.0,
+        }
+    }
+}
+
+/// Pixels a texture can be created from.
+pub enum TextureSource<'a> {
+    Rgba8(&'a [u8]),
+    RgbaF32(&'a [f32]),
+    Image(&'a HtmlImageElement),
+    /// Undefined pixels, for textures that are rendered to.
+    Empty,
+}
+
+/// Owned copy of a `TextureSource`, kept to recreate a texture after the
+/// context is lost.
+#[derive(Clone, Debug)]
+pub enum Pixels {
+    Rgba8(Vec<u8>),
+    RgbaF32(Vec<f32>),
+    Image(HtmlImageElement),
+    Empty,
+}
+
+impl Pixels {
+    pub fn retain(source: &TextureSource) -> Pixels {
+        match source {
+            TextureSource::Rgba8(data) => Pixels::Rgba8(data.to_vec()),
+            TextureSource::RgbaF32(data) => Pixels::RgbaF32(data.to_vec()),
+            TextureSource::Image(image) => Pixels::Image((*image).clone()),
+            TextureSource::Empty => Pixels::Empty,
+        }
+    }
+
+    pub fn source(&self) -> TextureSource<'_> {
+        match self {
+            Pixels::Rgba8(data) => TextureSource::Rgba8(data),
+            Pixels::RgbaF32(data) => TextureSource::RgbaF32(data),
+            Pixels::Image(image) => TextureSource::Image(image),
+            Pixels::Empty => TextureSource::Empty,
+        }
+    }
+}
+
+/// Textures compare by identity, so that comparing materials never compares
+/// pixels.
+#[derive(Debug)]
+pub struct Texture2D {
+    pub gl_texture: GlResource<WebGlTexture>,
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    sampler: SamplerSettings,
+    pixels: Pixels,
+}
+
+impl PartialEq for Texture2D {
+    fn eq(&self, other: &Texture2D) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Texture2D {
+    /// Creates a texture from tightly packed 8-bit RGBA pixels, first row
+    /// first. `format` must be `Rgba8` or `Srgb8Alpha8`.
+    pub fn from_rgba8(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        data: &[u8],
+        sampler: SamplerSettings,
+    ) -> Result<Texture2D, EngineError> {
+        Texture2D::new(
+            env,
+            width,
+            height,
+            format,
+            TextureSource::Rgba8(data),
+            sampler,
+        )
+    }
+
+    /// Creates a texture from tightly packed float RGBA pixels. `format`
+    /// must be `Rgba16F` or `Rgba32F`.
+    pub fn from_rgba_f32(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        data: &[f32],
+        sampler: SamplerSettings,
+    ) -> Result<Texture2D, EngineError> {
+        Texture2D::new(
+            env,
+            width,
+            height,
+            format,
+            TextureSource::RgbaF32(data),
+            sampler,
+        )
+    }
+
+    /// Creates a texture from a decoded PNG or JPEG, in the format matching
+    /// its sRGB tag.
+    #[cfg(feature = "image-decoding")]
+    pub fn from_decoded_image(
+        env: &GlEnvironment,
+        image: &DecodedImage,
+        sampler: SamplerSettings,
+    ) -> Result<Texture2D, EngineError> {
+        Texture2D::from_rgba8(
+            env,
+            image.width,
+            image.height,
+            image.texture_format(),
+            &image.pixels,
+            sampler,
+        )
+    }
+
+    /// Creates a texture with undefined pixels, to be rendered to.
+    pub fn empty(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        sampler: SamplerSettings,
+    ) -> Result<Texture2D, EngineError> {
+        Texture2D::new(
+            env,
+            width,
+            height,
+            format,
+            TextureSource::Empty,
+            sampler,
+        )
+    }
+
+    /// Creates a texture from a loaded image element.
+    pub fn from_image(
+        env: &GlEnvironment,
+        image: &HtmlImageElement,
+        format: TextureFormat,
+        sampler: SamplerSettings,
+    ) -> Result<Texture2D, EngineError> {
+        if !image.complete() || image.natural_width() == 0 {
+            return Err(EngineError::InvalidTexture(String::from(
+                "Image is not loaded.",
+            )));
+        }
+        Texture2D::new(
+            env,
+            image.natural_width(),
+            image.natural_height(),
+            format,
+            TextureSource::Image(image),
+            sampler,
+        )
+    }
+
+    pub fn new(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        source: TextureSource,
+        sampler: SamplerSettings,
+    ) -> Result<Texture2D, EngineError> {
+        let context = &env.context;
+        let sampler = supported_sampler(env, format, sampler);
+        let pixels = Pixels::retain(&source);
+        let mut gl_texture = env.resources.create::<WebGlTexture>()?;
+        context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&gl_texture),
+        );
+        let uploaded = upload(
+            context,
+            WebGl2RenderingContext::TEXTURE_2D,
+            width,
+            height,
+            format,
+            source,
+        );
+        context.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+        uploaded?;
+        gl_texture.set_bytes(texture_bytes(
+            width,
+            height,
+            format,
+            sampler.uses_mipmaps(),
+        ));
+
+        let mut texture = Texture2D {
+            gl_texture,
+            width,
+            height,
+            format,
+            sampler,
+            pixels,
+        };
+        texture.set_sampler(env, sampler);
+        if sampler.uses_mipmaps() {
+            texture.generate_mipmaps(context);
+        }
+        Ok(texture)
+    }
+
+    /// Uploads the retained pixels to a new texture of the same size,
+    /// format and sampler.
+    pub fn recreate(
+        &self,
+        env: &GlEnvironment,
+    ) -> Result<Texture2D, EngineError> {
+        Texture2D::new(
+            env,
+            self.width,
+            self.height,
+            self.format,
+            self.pixels.source(),
+            self.sampler,
+        )
+    }
+
+    pub fn sampler(&self) -> SamplerSettings {
+        self.sampler
+    }
+
+    pub fn pixels(&self) -> &Pixels {
+        &self.pixels
+    }
+
+    pub fn set_sampler(
+        &mut self,
+        env: &GlEnvironment,
+        sampler: SamplerSettings,
+    ) {
+        apply_sampler(
+            env,
+            WebGl2RenderingContext::TEXTURE_2D,
+            &self.gl_texture,
+            sampler,
+        );
+        self.sampler = sampler;
+    }
+
+    pub fn generate_mipmaps(&self, context: &WebGl2RenderingContext) {
+        let target = WebGl2RenderingContext::TEXTURE_2D;
+        context.bind_texture(target, Some(&self.gl_texture));
+        context.generate_mipmap(target);
+        context.bind_texture(target, None);
+    }
+
+    /// Binds the texture to texture unit `unit`.
+    pub fn bind(&self, context: &WebGl2RenderingContext, unit: u32) {
+        context.active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
+        context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&self.gl_texture),
+        );
+    }
+}
+
+/// Estimated GPU memory of a `width` by `height` texture, with its mipmap
+/// chain when `mipmapped`.
+pub fn texture_bytes(
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    mipmapped: bool,
+) -> usize {
+    let (mut width, mut height) = (width as usize, height as usize);
+    let mut pixels = width * height;
+    while mipmapped && (width > 1 || height > 1) {
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+        pixels += width * height;
+    }
+    pixels * format.bytes_per_pixel()
+}
+
+/// Sets the sampler parameters of `texture`, a texture of type `target`.
+pub fn apply_sampler(
+    env: &GlEnvironment,
+    target: u32,
+    texture: &WebGlTexture,
+    sampler: SamplerSettings,
+) {
+    let context = &env.context;
+    context.bind_texture(target, Some(texture));
+    context.tex_parameteri(
+        target,
+        WebGl2RenderingContext::TEXTURE_WRAP_S,
+        sampler.wrap_s.to_gl(),
+    );
+    context.tex_parameteri(
+        target,
+        WebGl2RenderingContext::TEXTURE_WRAP_T,
+        sampler.wrap_t.to_gl(),
+    );
+    context.tex_parameteri(
+        target,
+        WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+        sampler.gl_min_filter(),
+    );
+    context.tex_parameteri(
+        target,
+        WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+        sampler.gl_mag_filter(),
+    );
+    if env.max_anisotropy > 1.0 {
+        context.tex_parameterf(
+            target,
+            TEXTURE_MAX_ANISOTROPY_EXT,
+            sampler.anisotropy.clamp(1.0, env.max_anisotropy),
+        );
+    }
+    context.bind_texture(target, None);
+}
+
+/// Uploads level 0 of the texture bound to `target`, which is either
+/// `TEXTURE_2D` or a cube map face.
+pub fn upload(
+    context: &WebGl2RenderingContext,
+    target: u32,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    source: TextureSource,
+) -> Result<(), EngineError> {
+    let (internal_format, gl_format, gl_type) = format.gl_formats();
+    let expected_len = width as usize * height as usize * 4;
+    let result: Result<(), JsValue> = match source {
+        TextureSource::Rgba8(data) => {
+            if gl_type != WebGl2RenderingContext::UNSIGNED_BYTE {
+                return Err(EngineError::InvalidTexture(format!(
+                    "{:?} can't be uploaded from 8-bit RGBA pixels.",
+                    format
+                )));
+            }
+            if data.len() != expected_len {
+                return Err(EngineError::InvalidTexture(format!(
+                    "pixels size = {}, expected {}",
+                    data.len(),
+                    expected_len
+                )));
+            }
+            context.pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 1);
+            let result = context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                target,
+                0,
+                internal_format,
+                width as i32,
+                height as i32,
+                0,
+                gl_format,
+                gl_type,
+                Some(data),
+            );
+            // Back to the default, other uploads expect 4 byte rows.
+            context.pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 4);
+            result
+        }
+        TextureSource::RgbaF32(data) => {
+            if !matches!(format, TextureFormat::Rgba16F | TextureFormat::Rgba32F)
+            {
+                return Err(EngineError::InvalidTexture(format!(
+                    "{:?} can't be uploaded from float RGBA pixels.",
+                    format
+                )));
+            }
+            if data.len() != expected_len {
+                return Err(EngineError::InvalidTexture(format!(
+                    "pixels size = {}, expected {}",
+                    data.len(),
+                    expected_len
+                )));
+            }
+            // See `update_buffer` about the safety of `Float32Array::view`.
+            unsafe {
+                let view = js_sys::Float32Array::view(data);
+                context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+                    target,
+                    0,
+                    internal_format,
+                    width as i32,
+                    height as i32,
+                    0,
+                    gl_format,
+                    gl_type,
+                    Some(&view),
+                )
+            }
+        }
+        TextureSource::Image(image) => context
+            .tex_image_2d_with_u32_and_u32_and_html_image_element(
+                target,
+                0,
+                internal_format,
+                gl_format,
+                gl_type,
+                image,
+            ),
+        TextureSource::Empty => context
+            .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                target,
+                0,
+                internal_format,
+                width as i32,
+                height as i32,
+                0,
+                gl_format,
+                gl_type,
+                None,
+            ),
+    };
+    result.map_err(|error| {
+        EngineError::InvalidTexture(format!(
+            "Failed to upload texture: {:?}",
+            error
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_bytes_include_mipmaps() {
+        assert_eq!(texture_bytes(4, 2, TextureFormat::Rgba8, false), 32);
+        // 4x2, 2x1 and 1x1 levels.
+        assert_eq!(texture_bytes(4, 2, TextureFormat::Rgba16F, true), 88);
+        assert_eq!(texture_bytes(1, 1, TextureFormat::R16F, true), 2);
+    }
+
+    #[test]
+    fn float_mipmaps_need_extensions() {
+        assert!(TextureFormat::Srgb8Alpha8.can_generate_mipmaps(false, false));
+        assert!(!TextureFormat::Rgba16F.can_generate_mipmaps(false, true));
+        assert!(TextureFormat::Rgba16F.can_generate_mipmaps(true, false));
+        assert!(!TextureFormat::Rgba32F.can_generate_mipmaps(true, false));
+        assert!(TextureFormat::Rgba32F.can_generate_mipmaps(true, true));
+        assert!(!TextureFormat::Depth24.can_generate_mipmaps(true, true));
+    }
+}
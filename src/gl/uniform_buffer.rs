@@ -0,0 +1,194 @@
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram};
+
+use crate::error::EngineError;
+use crate::gl::resources::{GlResource, Resources};
+
+/// Binding points of the uniform blocks shared by every program.
+pub const CAMERA_BINDING: u32 = 0;
+pub const LIGHTS_BINDING: u32 = 1;
+
+/// Uniform blocks bound to their binding point in every program declaring
+/// them, see the camera and lights shader chunks.
+pub static UNIFORM_BLOCK_BINDINGS: [(&str, u32); 2] =
+    [("Camera", CAMERA_BINDING), ("Lights", LIGHTS_BINDING)];
+
+/// Block data laid out with the std140 rules: scalars are aligned to 4
+/// bytes, vec2 to 8, vec3 and vec4 to 16, and array elements and matrix
+/// columns start on 16 bytes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Std140 {
+    bytes: Vec<u8>,
+}
+
+impl Std140 {
+    pub fn new() -> Std140 {
+        Std140::default()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn float(&mut self, value: f32) -> &mut Std140 {
+        self.push(4, &[value.to_bits()])
+    }
+
+    pub fn int(&mut self, value: i32) -> &mut Std140 {
+        self.push(4, &[value as u32])
+    }
+
+    pub fn vec2(&mut self, value: [f32; 2]) -> &mut Std140 {
+        self.push(8, &value.map(f32::to_bits))
+    }
+
+    pub fn vec3(&mut self, value: [f32; 3]) -> &mut Std140 {
+        self.push(16, &value.map(f32::to_bits))
+    }
+
+    pub fn vec4(&mut self, value: [f32; 4]) -> &mut Std140 {
+        self.push(16, &value.map(f32::to_bits))
+    }
+
+    /// Writes a matrix given column by column.
+    pub fn mat4(&mut self, columns: &[f32; 16]) -> &mut Std140 {
+        for column in columns.chunks_exact(4) {
+            self.vec4([column[0], column[1], column[2], column[3]]);
+        }
+        self
+    }
+
+    /// Writes an array of `len` elements, each written by `element` with
+    /// its index.
+    pub fn array<F>(&mut self, len: usize, mut element: F) -> &mut Std140
+    where
+        F: FnMut(&mut Std140, usize),
+    {
+        for i in 0..len {
+            self.align(16);
+            element(self, i);
+        }
+        self.align(16);
+        self
+    }
+
+    fn push(&mut self, alignment: usize, words: &[u32]) -> &mut Std140 {
+        self.align(alignment);
+        for word in words {
+            self.bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        self
+    }
+
+    fn align(&mut self, alignment: usize) {
+        let len = self.bytes.len().div_ceil(alignment) * alignment;
+        self.bytes.resize(len, 0);
+    }
+}
+
+/// Buffer backing a uniform block.
+pub struct UniformBuffer {
+    gl_buffer: GlResource<WebGlBuffer>,
+}
+
+impl UniformBuffer {
+    pub fn new(resources: &Resources) -> Result<UniformBuffer, EngineError> {
+        Ok(UniformBuffer {
+            gl_buffer: resources.create()?,
+        })
+    }
+
+    /// Replaces the content of the buffer with `data` and binds it to
+    /// `binding`.
+    pub fn upload(
+        &mut self,
+        context: &WebGl2RenderingContext,
+        data: &Std140,
+        binding: u32,
+    ) {
+        context.bind_buffer(
+            WebGl2RenderingContext::UNIFORM_BUFFER,
+            Some(&self.gl_buffer),
+        );
+        context.buffer_data_with_u8_array(
+            WebGl2RenderingContext::UNIFORM_BUFFER,
+            data.bytes(),
+            WebGl2RenderingContext::DYNAMIC_DRAW,
+        );
+        context.bind_buffer(WebGl2RenderingContext::UNIFORM_BUFFER, None);
+        self.gl_buffer.set_bytes(data.bytes().len());
+        self.bind(context, binding);
+    }
+
+    pub fn bind(&self, context: &WebGl2RenderingContext, binding: u32) {
+        context.bind_buffer_base(
+            WebGl2RenderingContext::UNIFORM_BUFFER,
+            binding,
+            Some(&self.gl_buffer),
+        );
+    }
+}
+
+/// Points the uniform blocks `program` declares at their binding point.
+pub fn bind_uniform_blocks(
+    context: &WebGl2RenderingContext,
+    program: &WebGlProgram,
+) {
+    for (name, binding) in UNIFORM_BLOCK_BINDINGS.iter() {
+        let index = context.get_uniform_block_index(program, name);
+        if index != WebGl2RenderingContext::INVALID_INDEX {
+            context.uniform_block_binding(program, index, *binding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(data: &Std140) -> Vec<f32> {
+        data.bytes()
+            .chunks_exact(4)
+            .map(|word| {
+                f32::from_le_bytes([word[0], word[1], word[2], word[3]])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn members_are_aligned() {
+        let mut data = Std140::new();
+        data.float(1.0)
+            .vec3([2.0, 3.0, 4.0])
+            .float(5.0)
+            .vec2([6.0, 7.0]);
+        assert_eq!(
+            floats(&data),
+            [1.0, 0.0, 0.0, 0.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]
+        );
+    }
+
+    #[test]
+    fn array_elements_and_matrix_columns_take_16_bytes() {
+        let mut data = Std140::new();
+        data.int(3)
+            .array(2, |data, i| {
+                data.float(i as f32 + 1.0);
+            })
+            .vec2([8.0, 9.0]);
+        assert_eq!(data.bytes().len(), 56);
+        assert_eq!(data.bytes()[..4], 3i32.to_le_bytes());
+        assert_eq!(
+            floats(&data)[4..],
+            [1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 8.0, 9.0]
+        );
+
+        let mut data = Std140::new();
+        let mut columns = [0.0; 16];
+        for (i, value) in columns.iter_mut().enumerate() {
+            *value = i as f32;
+        }
+        data.vec3([0.0; 3]).mat4(&columns);
+        assert_eq!(data.bytes().len(), 80);
+        assert_eq!(floats(&data)[4..8], [0.0, 1.0, 2.0, 3.0]);
+    }
+}
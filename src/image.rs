@@ -0,0 +1,406 @@
+//! PNG and JPEG decoding into the RGBA8 pixels `Texture2D` uploads.
+//!
+//! Only compiled with the `image-decoding` feature.
+
+use png::{BitDepth, ColorType, Transformations};
+use zune_jpeg::{
+    zune_core::{colorspace::ColorSpace, options::DecoderOptions},
+    JpegDecoder,
+};
+
+use crate::error::EngineError;
+use crate::gl::texture::TextureFormat;
+
+static PNG_SIGNATURE: [u8; 8] =
+    [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+static JPEG_SIGNATURE: [u8; 3] = [0xFF, 0xD8, 0xFF];
+
+/// Largest width or height decoded, the largest texture size WebGL
+/// implementations support.
+const MAX_DIMENSION: usize = 16384;
+/// Largest decoded image, in bytes of RGBA8 pixels.
+const MAX_RGBA_BYTES: usize = MAX_DIMENSION * MAX_DIMENSION * 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DecodeOptions {
+    /// Multiplies the color channels by alpha.
+    pub premultiply_alpha: bool,
+    /// Stores the last row first, as expected by UVs with a bottom-left
+    /// origin.
+    pub flip_y: bool,
+    /// Tags the pixels as sRGB encoded, so that they are uploaded as
+    /// `TextureFormat::Srgb8Alpha8` and linearized when sampled. Color
+    /// textures are usually sRGB, data textures (normals, roughness...)
+    /// are not.
+    pub srgb: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            premultiply_alpha: false,
+            flip_y: false,
+            srgb: true,
+        }
+    }
+}
+
+/// Tightly packed RGBA8 pixels, first row first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    pub srgb: bool,
+}
+
+impl DecodedImage {
+    /// Texture format matching the sRGB tag of the image.
+    pub fn texture_format(&self) -> TextureFormat {
+        if self.srgb {
+            TextureFormat::Srgb8Alpha8
+        } else {
+            TextureFormat::Rgba8
+        }
+    }
+}
+
+/// Decodes PNG or JPEG bytes, detecting the format from its signature.
+pub fn decode(
+    bytes: &[u8],
+    options: DecodeOptions,
+) -> Result<DecodedImage, EngineError> {
+    if bytes.starts_with(&PNG_SIGNATURE) {
+        decode_png(bytes, options)
+    } else if bytes.starts_with(&JPEG_SIGNATURE) {
+        decode_jpeg(bytes, options)
+    } else {
+        Err(EngineError::Image(String::from(
+            "Unsupported image format, expected PNG or JPEG.",
+        )))
+    }
+}
+
+/// Decodes a PNG of any color type and bit depth. Palettes and `tRNS`
+/// transparency are expanded and 16-bit channels reduced to 8 bits.
+pub fn decode_png(
+    bytes: &[u8],
+    options: DecodeOptions,
+) -> Result<DecodedImage, EngineError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(
+        Transformations::EXPAND | Transformations::STRIP_16,
+    );
+    let mut reader = decoder.read_info().map_err(|error| {
+        EngineError::Image(format!("Invalid PNG: {}", error))
+    })?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer).map_err(|error| {
+        EngineError::Image(format!("Invalid PNG: {}", error))
+    })?;
+    if frame.bit_depth != BitDepth::Eight {
+        return Err(EngineError::Image(format!(
+            "Unexpected PNG bit depth {:?}",
+            frame.bit_depth
+        )));
+    }
+
+    let channels = match frame.color_type {
+        ColorType::Grayscale => 1,
+        ColorType::GrayscaleAlpha => 2,
+        ColorType::Rgb => 3,
+        ColorType::Rgba => 4,
+        ColorType::Indexed => {
+            return Err(EngineError::Image(String::from(
+                "PNG palette was not expanded.",
+            )));
+        }
+    };
+    let mut pixels = Vec::with_capacity(rgba_size(frame.width, frame.height)?);
+    for row in buffer.chunks(frame.line_size).take(frame.height as usize) {
+        for pixel in row[..frame.width as usize * channels].chunks(channels) {
+            pixels.extend_from_slice(&rgba(pixel));
+        }
+    }
+
+    Ok(finish(frame.width, frame.height, pixels, options))
+}
+
+/// Decodes a baseline or progressive JPEG.
+pub fn decode_jpeg(
+    bytes: &[u8],
+    options: DecodeOptions,
+) -> Result<DecodedImage, EngineError> {
+    let decoder_options = DecoderOptions::default()
+        .jpeg_set_out_colorspace(ColorSpace::RGBA)
+        .set_max_width(MAX_DIMENSION)
+        .set_max_height(MAX_DIMENSION);
+    let mut decoder = JpegDecoder::new_with_options(bytes, decoder_options);
+    let mut pixels = decoder.decode().map_err(|error| {
+        EngineError::Image(format!("Invalid JPEG: {:?}", error))
+    })?;
+    let info = decoder.info().ok_or_else(|| {
+        EngineError::Image(String::from(
+            "Invalid JPEG: missing image information",
+        ))
+    })?;
+    // Grayscale JPEGs are decoded to luma whatever the requested color
+    // space.
+    let colorspace = decoder.get_output_colorspace();
+    match colorspace {
+        Some(ColorSpace::RGBA) => {}
+        Some(
+            colorspace @ (ColorSpace::Luma
+            | ColorSpace::LumaA
+            | ColorSpace::RGB),
+        ) => {
+            let channels = colorspace.num_components();
+            pixels = pixels.chunks(channels).flat_map(rgba).collect();
+        }
+        _ => {
+            return Err(EngineError::Image(format!(
+                "Unexpected JPEG output color space {:?}",
+                colorspace
+            )));
+        }
+    }
+
+    Ok(finish(
+        info.width as u32,
+        info.height as u32,
+        pixels,
+        options,
+    ))
+}
+
+/// Expands a pixel of 1 to 4 channels to RGBA.
+fn rgba(pixel: &[u8]) -> [u8; 4] {
+    match pixel {
+        [l] => [*l, *l, *l, 255],
+        [l, a] => [*l, *l, *l, *a],
+        [r, g, b] => [*r, *g, *b, 255],
+        [r, g, b, a] => [*r, *g, *b, *a],
+        _ => unreachable!(),
+    }
+}
+
+/// Bytes of `width` by `height` RGBA8 pixels, rejecting images larger than
+/// `MAX_RGBA_BYTES`.
+fn rgba_size(width: u32, height: u32) -> Result<usize, EngineError> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .filter(|size| *size <= MAX_RGBA_BYTES)
+        .ok_or_else(|| {
+            EngineError::Image(format!(
+                "Image of {}x{} pixels is too large.",
+                width, height
+            ))
+        })
+}
+
+fn finish(
+    width: u32,
+    height: u32,
+    mut pixels: Vec<u8>,
+    options: DecodeOptions,
+) -> DecodedImage {
+    if options.premultiply_alpha {
+        premultiply_alpha(&mut pixels);
+    }
+    if options.flip_y {
+        flip_y(&mut pixels, width as usize * 4);
+    }
+    DecodedImage {
+        width,
+        height,
+        pixels,
+        srgb: options.srgb,
+    }
+}
+
+fn premultiply_alpha(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_mut(4) {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+fn flip_y(pixels: &mut [u8], row_size: usize) {
+    let rows = pixels.len() / row_size;
+    for row in 0..rows / 2 {
+        let (top, bottom) = pixels.split_at_mut((rows - row - 1) * row_size);
+        top[row * row_size..(row + 1) * row_size]
+            .swap_with_slice(&mut bottom[..row_size]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_png(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        palette: Option<(&[u8], &[u8])>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, width, height);
+            encoder.set_color(color_type);
+            encoder.set_depth(bit_depth);
+            if let Some((palette, transparency)) = palette {
+                encoder.set_palette(palette.to_vec());
+                encoder.set_trns(transparency.to_vec());
+            }
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(data).unwrap();
+        }
+        bytes
+    }
+
+    /// Encodes a 16x8 grayscale JPEG of two flat blocks, 192 then 64, with
+    /// hand written Huffman tables: DC categories 7 and 8 coded `0` and
+    /// `10`, and the AC end of block coded `0`.
+    fn encode_gray_jpeg(progressive: bool) -> Vec<u8> {
+        fn segment(bytes: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+            bytes.extend_from_slice(&[0xFF, marker]);
+            bytes.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+            bytes.extend_from_slice(payload);
+        }
+        let scan =
+            |spectral: [u8; 2]| [1, 1, 0x00, spectral[0], spectral[1], 0x00];
+
+        let mut bytes = vec![0xFF, 0xD8];
+        // A DC quantizer of 8 makes the quantized DC the level shifted
+        // sample value.
+        let mut quantization = vec![0x00, 8];
+        quantization.extend_from_slice(&[1; 63]);
+        segment(&mut bytes, 0xDB, &quantization);
+        let frame = [8, 0, 8, 0, 16, 1, 1, 0x11, 0];
+        segment(&mut bytes, if progressive { 0xC2 } else { 0xC0 }, &frame);
+        let mut dc_table = vec![0x00, 1, 1];
+        dc_table.extend_from_slice(&[0; 14]);
+        dc_table.extend_from_slice(&[7, 8]);
+        segment(&mut bytes, 0xC4, &dc_table);
+        let mut ac_table = vec![0x10, 1];
+        ac_table.extend_from_slice(&[0; 15]);
+        ac_table.push(0x00);
+        segment(&mut bytes, 0xC4, &ac_table);
+
+        // Block DCs 64 = `0 1000000` and 64 - 128 = `10 01111111`, each
+        // followed by an end of block `0` in a baseline scan. Padded with
+        // ones.
+        if progressive {
+            segment(&mut bytes, 0xDA, &scan([0, 0]));
+            bytes.extend_from_slice(&[0b0100_0000, 0b1001_1111, 0xFF, 0x00]);
+            segment(&mut bytes, 0xDA, &scan([1, 63]));
+            bytes.push(0b0011_1111);
+        } else {
+            segment(&mut bytes, 0xDA, &scan([0, 63]));
+            bytes.extend_from_slice(&[0b0100_0000, 0b0100_1111, 0b1110_1111]);
+        }
+        bytes.extend_from_slice(&[0xFF, 0xD9]);
+        bytes
+    }
+
+    fn assert_gray_blocks(image: &DecodedImage) {
+        assert_eq!((image.width, image.height), (16, 8));
+        assert_eq!(image.pixels.len(), 16 * 8 * 4);
+        for (index, pixel) in image.pixels.chunks(4).enumerate() {
+            let expected = if index % 16 < 8 { 192 } else { 64 };
+            assert_eq!(pixel, [expected, expected, expected, 255]);
+        }
+    }
+
+    #[test]
+    fn decodes_baseline_jpeg() {
+        let bytes = encode_gray_jpeg(false);
+        let image = decode(&bytes, DecodeOptions::default()).unwrap();
+        assert_gray_blocks(&image);
+    }
+
+    #[test]
+    fn decodes_progressive_jpeg() {
+        let bytes = encode_gray_jpeg(true);
+        let image = decode(&bytes, DecodeOptions::default()).unwrap();
+        assert_gray_blocks(&image);
+    }
+
+    #[test]
+    fn rejects_oversized_images() {
+        assert_eq!(rgba_size(2, 3).unwrap(), 24);
+        assert!(rgba_size(16385, 16384).is_err());
+        assert!(rgba_size(u32::MAX, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn decodes_rgba_png() {
+        let data = [255, 0, 0, 255, 0, 255, 0, 128];
+        let bytes =
+            encode_png(2, 1, ColorType::Rgba, BitDepth::Eight, None, &data);
+        let image = decode(&bytes, DecodeOptions::default()).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, data);
+        assert_eq!(image.texture_format(), TextureFormat::Srgb8Alpha8);
+    }
+
+    #[test]
+    fn expands_palette_with_transparency() {
+        let palette = [10, 20, 30, 40, 50, 60];
+        let transparency = [255, 0];
+        // Two 1-bit indices, packed in the high bits of a single byte.
+        let bytes = encode_png(
+            2,
+            1,
+            ColorType::Indexed,
+            BitDepth::One,
+            Some((&palette, &transparency)),
+            &[0b0100_0000],
+        );
+        let image = decode(&bytes, DecodeOptions::default()).unwrap();
+        assert_eq!(image.pixels, [10, 20, 30, 255, 40, 50, 60, 0]);
+    }
+
+    #[test]
+    fn strips_16_bit_grayscale() {
+        let bytes = encode_png(
+            1,
+            1,
+            ColorType::Grayscale,
+            BitDepth::Sixteen,
+            None,
+            &[0xAB, 0xCD],
+        );
+        let image = decode(&bytes, DecodeOptions::default()).unwrap();
+        assert_eq!(image.pixels, [0xAB, 0xAB, 0xAB, 255]);
+    }
+
+    #[test]
+    fn applies_options() {
+        let data = [255, 255, 255, 128, 0, 0, 0, 255];
+        let bytes =
+            encode_png(1, 2, ColorType::Rgba, BitDepth::Eight, None, &data);
+        let options = DecodeOptions {
+            premultiply_alpha: true,
+            flip_y: true,
+            srgb: false,
+        };
+        let image = decode(&bytes, options).unwrap();
+        assert_eq!(image.pixels, [0, 0, 0, 255, 128, 128, 128, 128]);
+        assert_eq!(image.texture_format(), TextureFormat::Rgba8);
+    }
+
+    #[test]
+    fn rejects_unknown_and_truncated_data() {
+        assert!(decode(b"GIF89a", DecodeOptions::default()).is_err());
+        assert!(decode(&PNG_SIGNATURE, DecodeOptions::default()).is_err());
+        assert!(decode(&[0xFF, 0xD8, 0xFF, 0xE0], DecodeOptions::default())
+            .is_err());
+    }
+}
@@ -1,27 +1,56 @@
 #![allow(unused_variables)]
 
 use environment::GlEnvironment;
-use gl::gl_shape::GlShape;
 use renderer::Renderer;
+use scene::Scene;
 use wasm_bindgen::prelude::wasm_bindgen;
 
 #[macro_use]
 pub mod utils;
 
 pub mod camera;
+mod context_loss;
+mod deferred;
 mod environment;
+pub mod error;
+mod fog;
 mod gl {
+    pub mod cubemap;
     pub mod gl_shape;
     pub mod gl_utils;
+    pub mod instances;
+    pub mod mesh;
+    pub mod render_state;
+    pub mod render_target;
+    pub mod resources;
+    pub mod shaders;
+    pub mod texture;
+    pub mod uniform_buffer;
 }
-mod math {
+#[cfg(feature = "image-decoding")]
+mod image;
+pub mod lights;
+pub mod math {
+    pub mod bounds;
+    /// Reference of the BRDF shader chunk, only used to test it natively.
+    #[cfg(test)]
+    pub mod brdf;
     pub mod matrix;
     pub mod quaternion;
     pub mod utils;
 }
 pub mod linear_transform;
+mod material;
+mod oit;
+mod post_process;
+mod render_queue;
 mod renderer;
-mod shapes {
+mod scene;
+mod shadows;
+mod skybox;
+mod ssao;
+mod static_batch;
+pub mod shapes {
     pub mod cuboid;
     pub mod triangle;
 }
@@ -30,7 +59,7 @@ pub mod types;
 #[wasm_bindgen]
 pub struct Environment {
     gl_environment: GlEnvironment,
-    shapes: Vec<GlShape>,
+    scene: Scene,
     tick_count: u32,
     renderer: Renderer,
 }
@@ -0,0 +1,235 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    math::utils::degree_to_radian,
+    shadows::MAX_CASCADES,
+    types::{Color, Vec3},
+};
+
+/// Maximum number of lights the default shaders can evaluate per fragment.
+/// Extra lights in the scene are ignored.
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    Directional = 0,
+    Point = 1,
+    Spot = 2,
+}
+
+/// How a light samples its shadow map.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightShadow {
+    /// Depth offset subtracted before comparing with the shadow map, in
+    /// normalized light depth.
+    pub bias: f32,
+    /// Offset of the sampled position along the surface normal, in world
+    /// units.
+    pub normal_bias: f32,
+    /// PCF kernel radius in texels, `0` for a single filtered sample.
+    pub pcf_radius: u32,
+    /// Number of cascades of a directional light, up to `MAX_CASCADES`.
+    pub cascade_count: u32,
+}
+
+impl Default for LightShadow {
+    fn default() -> Self {
+        LightShadow {
+            bias: 0.002,
+            normal_bias: 0.02,
+            pcf_radius: 1,
+            cascade_count: 3,
+        }
+    }
+}
+
+/// A punctual light source, modelled after `KHR_lights_punctual`.
+///
+/// A `range` of `0.0` means the light is not attenuated by a cut-off
+/// distance. Cone angles are in degrees and measured from the spot axis.
+/// Directional and spot lights can cast shadows, point lights cannot.
+#[derive(Copy, Clone, Debug)]
+#[wasm_bindgen]
+pub struct Light {
+    kind: LightKind,
+    color: Color,
+    intensity: f32,
+    range: f32,
+    inner_cone_angle: f32,
+    outer_cone_angle: f32,
+    position: Vec3,
+    direction: Vec3,
+    casts_shadows: bool,
+    shadow: LightShadow,
+}
+
+#[wasm_bindgen]
+impl Light {
+    pub fn directional(
+        dx: f32,
+        dy: f32,
+        dz: f32,
+        r: f32,
+        g: f32,
+        b: f32,
+        intensity: f32,
+    ) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            color: Color::rgb(r, g, b),
+            intensity,
+            range: 0.0,
+            inner_cone_angle: 0.0,
+            outer_cone_angle: 0.0,
+            position: Vec3::zero(),
+            direction: Vec3::new(dx, dy, dz).normalize(),
+            casts_shadows: false,
+            shadow: LightShadow::default(),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn point(
+        x: f32,
+        y: f32,
+        z: f32,
+        r: f32,
+        g: f32,
+        b: f32,
+        intensity: f32,
+        range: f32,
+    ) -> Light {
+        Light {
+            kind: LightKind::Point,
+            color: Color::rgb(r, g, b),
+            intensity,
+            range,
+            inner_cone_angle: 0.0,
+            outer_cone_angle: 0.0,
+            position: Vec3::new(x, y, z),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+            casts_shadows: false,
+            shadow: LightShadow::default(),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn spot(
+        x: f32,
+        y: f32,
+        z: f32,
+        dx: f32,
+        dy: f32,
+        dz: f32,
+        r: f32,
+        g: f32,
+        b: f32,
+        intensity: f32,
+        range: f32,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    ) -> Light {
+        Light {
+            kind: LightKind::Spot,
+            color: Color::rgb(r, g, b),
+            intensity,
+            range,
+            inner_cone_angle,
+            outer_cone_angle,
+            position: Vec3::new(x, y, z),
+            direction: Vec3::new(dx, dy, dz).normalize(),
+            casts_shadows: false,
+            shadow: LightShadow::default(),
+        }
+    }
+
+    pub fn set_color(&mut self, r: f32, g: f32, b: f32) {
+        self.color = Color::rgb(r, g, b);
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+
+    pub fn set_range(&mut self, range: f32) {
+        self.range = range;
+    }
+
+    pub fn set_cone_angles(&mut self, inner: f32, outer: f32) {
+        self.inner_cone_angle = inner;
+        self.outer_cone_angle = outer;
+    }
+
+    pub fn set_position(&mut self, x: f32, y: f32, z: f32) {
+        self.position = Vec3::new(x, y, z);
+    }
+
+    pub fn set_direction(&mut self, dx: f32, dy: f32, dz: f32) {
+        self.direction = Vec3::new(dx, dy, dz).normalize();
+    }
+
+    pub fn set_casts_shadows(&mut self, casts_shadows: bool) {
+        self.casts_shadows = casts_shadows;
+    }
+
+    pub fn set_shadow_bias(&mut self, bias: f32, normal_bias: f32) {
+        self.shadow.bias = bias;
+        self.shadow.normal_bias = normal_bias;
+    }
+
+    pub fn set_shadow_pcf_radius(&mut self, radius: u32) {
+        self.shadow.pcf_radius = radius;
+    }
+
+    pub fn set_shadow_cascade_count(&mut self, count: u32) {
+        self.shadow.cascade_count = count.clamp(1, MAX_CASCADES as u32);
+    }
+}
+
+impl Light {
+    pub fn kind(&self) -> LightKind {
+        self.kind
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn range(&self) -> f32 {
+        self.range
+    }
+
+    /// Cosines of the inner and outer cone angles, as consumed by the
+    /// shaders.
+    pub fn cone_cosines(&self) -> (f32, f32) {
+        (
+            degree_to_radian(self.inner_cone_angle).cos(),
+            degree_to_radian(self.outer_cone_angle).cos(),
+        )
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    pub fn outer_cone_angle(&self) -> f32 {
+        self.outer_cone_angle
+    }
+
+    /// Whether the light has a shadow map. Always false for point lights.
+    pub fn casts_shadows(&self) -> bool {
+        self.casts_shadows && self.kind != LightKind::Point
+    }
+
+    pub fn shadow(&self) -> LightShadow {
+        self.shadow
+    }
+}
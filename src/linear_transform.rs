@@ -49,6 +49,10 @@ impl LinearTransform {
         self.needs_update = true;
     }
 
+    pub fn get_translation(&self) -> Vec3 {
+        self.translation
+    }
+
     pub fn get_matrix(&self) -> &Matrix4<f32> {
         &self.matrix
     }
@@ -67,3 +71,9 @@ impl LinearTransform {
         }
     }
 }
+
+impl Default for LinearTransform {
+    fn default() -> Self {
+        LinearTransform::new()
+    }
+}
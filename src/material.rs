@@ -0,0 +1,611 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::{
+    gl::{
+        cubemap::TextureCube,
+        gl_utils::Program,
+        render_state::{Blend, RenderState},
+        texture::{Texture2D, TextureFormat},
+    },
+    types::Color,
+};
+
+pub static UNLIT_PROGRAM: &str = "unlit";
+pub static BLINN_PHONG_PROGRAM: &str = "blinn_phong";
+pub static PBR_PROGRAM: &str = "pbr";
+pub static TEXTURED_PROGRAM: &str = "textured";
+
+/// Texture units of the material samplers, distinct within a program so
+/// that samplers of different types never alias. The color unit holds the
+/// color of `Textured` or the base color of `Pbr`.
+pub const COLOR_TEXTURE_UNIT: u32 = 0;
+pub const METALLIC_ROUGHNESS_TEXTURE_UNIT: u32 = 1;
+pub const OCCLUSION_TEXTURE_UNIT: u32 = 2;
+pub const EMISSIVE_TEXTURE_UNIT: u32 = 3;
+pub const NORMAL_TEXTURE_UNIT: u32 = 4;
+pub const ENVIRONMENT_TEXTURE_UNIT: u32 = 5;
+
+/// Parameters of the Blinn-Phong reflection model. Colors are multiplied
+/// with the vertex colors of the shape.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlinnPhong {
+    pub ambient: Color,
+    pub diffuse: Color,
+    pub specular: Color,
+    pub shininess: f32,
+    /// Cube map reflected by the surface, tinted by `specular`. Overrides
+    /// the skybox of the scene, which is reflected when `None`.
+    pub environment_map: Option<Rc<TextureCube>>,
+    /// How much of the reflection replaces the lit color, from 0 to 1.
+    pub reflectivity: f32,
+}
+
+impl Default for BlinnPhong {
+    fn default() -> Self {
+        BlinnPhong {
+            ambient: Color::rgb(1.0, 1.0, 1.0),
+            diffuse: Color::rgb(1.0, 1.0, 1.0),
+            specular: Color::rgb(0.5, 0.5, 0.5),
+            shininess: 32.0,
+            environment_map: None,
+            reflectivity: 0.0,
+        }
+    }
+}
+
+/// glTF metallic-roughness parameters. Factors are multiplied with the
+/// matching texture when there is one, and `base_color` with the vertex
+/// colors of the shape.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pbr {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion_strength: f32,
+    pub emissive: Color,
+    pub normal_scale: f32,
+    pub base_color_texture: Option<Rc<Texture2D>>,
+    /// Roughness is read from the green channel, metallic from the blue one.
+    pub metallic_roughness_texture: Option<Rc<Texture2D>>,
+    pub occlusion_texture: Option<Rc<Texture2D>>,
+    pub emissive_texture: Option<Rc<Texture2D>>,
+    pub normal_texture: Option<Rc<Texture2D>>,
+    /// Cube map used for image based ambient lighting and reflections.
+    /// Overrides the skybox of the scene, which is used when `None`.
+    pub environment_map: Option<Rc<TextureCube>>,
+}
+
+impl Default for Pbr {
+    fn default() -> Self {
+        Pbr {
+            base_color: Color::rgb(1.0, 1.0, 1.0),
+            metallic: 1.0,
+            roughness: 1.0,
+            occlusion_strength: 1.0,
+            emissive: Color::rgb(0.0, 0.0, 0.0),
+            normal_scale: 1.0,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+            normal_texture: None,
+            environment_map: None,
+        }
+    }
+}
+
+impl Pbr {
+    /// Untextured parameters, with `metallic` and `roughness` clamped to
+    /// [0, 1].
+    pub fn with_factors(
+        base_color: Color,
+        metallic: f32,
+        roughness: f32,
+    ) -> Pbr {
+        Pbr {
+            base_color,
+            metallic: metallic.clamp(0.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+            ..Pbr::default()
+        }
+    }
+
+    pub fn texture_mut(
+        &mut self,
+        slot: PbrTexture,
+    ) -> &mut Option<Rc<Texture2D>> {
+        match slot {
+            PbrTexture::BaseColor => &mut self.base_color_texture,
+            PbrTexture::MetallicRoughness => {
+                &mut self.metallic_roughness_texture
+            }
+            PbrTexture::Occlusion => &mut self.occlusion_texture,
+            PbrTexture::Emissive => &mut self.emissive_texture,
+            PbrTexture::Normal => &mut self.normal_texture,
+        }
+    }
+}
+
+/// Texture slots of the metallic-roughness material.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PbrTexture {
+    BaseColor,
+    MetallicRoughness,
+    Occlusion,
+    Emissive,
+    Normal,
+}
+
+impl PbrTexture {
+    /// Slot of name `"base_color"`, `"metallic_roughness"`, `"occlusion"`,
+    /// `"emissive"` or `"normal"`.
+    pub fn from_name(name: &str) -> Option<PbrTexture> {
+        match name {
+            "base_color" => Some(PbrTexture::BaseColor),
+            "metallic_roughness" => Some(PbrTexture::MetallicRoughness),
+            "occlusion" => Some(PbrTexture::Occlusion),
+            "emissive" => Some(PbrTexture::Emissive),
+            "normal" => Some(PbrTexture::Normal),
+            _ => None,
+        }
+    }
+
+    /// Whether the slot holds colors, stored in sRGB, rather than data.
+    pub fn is_srgb(self) -> bool {
+        matches!(self, PbrTexture::BaseColor | PbrTexture::Emissive)
+    }
+
+    pub fn format(self) -> TextureFormat {
+        if self.is_srgb() {
+            TextureFormat::Srgb8Alpha8
+        } else {
+            TextureFormat::Rgba8
+        }
+    }
+}
+
+/// Unlit texturing, `tint` and the vertex colors are multiplied with the
+/// texture color.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Textured {
+    pub texture: Rc<Texture2D>,
+    pub tint: Color,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shading {
+    /// Outputs the vertex colors as is.
+    Unlit,
+    /// Per-fragment Blinn-Phong lighting.
+    BlinnPhong(BlinnPhong),
+    /// Cook-Torrance GGX lighting with metallic-roughness parameters.
+    Pbr(Pbr),
+    /// Outputs a texture sampled with the shape's UVs.
+    Textured(Textured),
+}
+
+/// How the alpha of the shaded color is used.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AlphaMode {
+    /// Alpha is ignored, the surface is fully opaque.
+    Opaque,
+    /// Fragments with an alpha below the cutoff are discarded, the others
+    /// are opaque.
+    Mask(f32),
+    /// The surface is blended over what is behind it.
+    Blend,
+}
+
+impl AlphaMode {
+    /// Value of the `alphaMode` shader uniform.
+    pub fn shader_value(self) -> i32 {
+        match self {
+            AlphaMode::Opaque => 0,
+            AlphaMode::Mask(_) => 1,
+            AlphaMode::Blend => 2,
+        }
+    }
+
+    pub fn cutoff(self) -> f32 {
+        match self {
+            AlphaMode::Mask(cutoff) => cutoff,
+            _ => 0.0,
+        }
+    }
+
+    pub fn is_transparent(self) -> bool {
+        self == AlphaMode::Blend
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub shading: Shading,
+    pub alpha_mode: AlphaMode,
+    /// Blends with weighted blended order-independent transparency rather
+    /// than sorting, when the alpha mode is `Blend`.
+    pub order_independent: bool,
+    /// Fixed function state the material is drawn with. Transparent draws
+    /// additionally blend and don't write depth, see `render_state_for`.
+    pub render_state: RenderState,
+}
+
+impl Material {
+    fn new(shading: Shading) -> Material {
+        Material {
+            shading,
+            alpha_mode: AlphaMode::Opaque,
+            order_independent: false,
+            render_state: RenderState::default(),
+        }
+    }
+
+    pub fn unlit() -> Material {
+        Material::new(Shading::Unlit)
+    }
+
+    pub fn blinn_phong(params: BlinnPhong) -> Material {
+        Material::new(Shading::BlinnPhong(params))
+    }
+
+    pub fn pbr(params: Pbr) -> Material {
+        Material::new(Shading::Pbr(params))
+    }
+
+    pub fn textured(texture: Rc<Texture2D>) -> Material {
+        Material::new(Shading::Textured(Textured {
+            texture,
+            tint: Color::rgb(1.0, 1.0, 1.0),
+        }))
+    }
+
+    /// State to draw the material with in `alpha_mode`, which may differ
+    /// from the material's own when overridden by a shape.
+    pub fn render_state_for(&self, alpha_mode: AlphaMode) -> RenderState {
+        let mut state = self.render_state;
+        if alpha_mode.is_transparent() {
+            state.blend = state.blend.or_else(|| Some(Blend::alpha()));
+            state.depth_write = false;
+        }
+        state
+    }
+
+    /// Name of the program this material is drawn with.
+    pub fn program_name(&self) -> &'static str {
+        match self.shading {
+            Shading::Unlit => UNLIT_PROGRAM,
+            Shading::BlinnPhong(_) => BLINN_PHONG_PROGRAM,
+            Shading::Pbr(_) => PBR_PROGRAM,
+            Shading::Textured(_) => TEXTURED_PROGRAM,
+        }
+    }
+
+    /// Whether the material samples `texture`.
+    pub fn uses_texture(&self, texture: &Rc<Texture2D>) -> bool {
+        let textures = match &self.shading {
+            Shading::Unlit | Shading::BlinnPhong(_) => vec![],
+            Shading::Pbr(params) => vec![
+                &params.base_color_texture,
+                &params.metallic_roughness_texture,
+                &params.occlusion_texture,
+                &params.emissive_texture,
+                &params.normal_texture,
+            ],
+            Shading::Textured(params) => {
+                return Rc::ptr_eq(&params.texture, texture)
+            }
+        };
+        textures
+            .into_iter()
+            .flatten()
+            .any(|used| Rc::ptr_eq(used, texture))
+    }
+
+    /// Textures the material samples, to replace them with their
+    /// recreated versions when the context is restored.
+    pub fn textures_mut(&mut self) -> Vec<&mut Rc<Texture2D>> {
+        match &mut self.shading {
+            Shading::Unlit | Shading::BlinnPhong(_) => vec![],
+            Shading::Pbr(params) => vec![
+                &mut params.base_color_texture,
+                &mut params.metallic_roughness_texture,
+                &mut params.occlusion_texture,
+                &mut params.emissive_texture,
+                &mut params.normal_texture,
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            Shading::Textured(params) => vec![&mut params.texture],
+        }
+    }
+
+    pub fn environment_map_mut(&mut self) -> Option<&mut Rc<TextureCube>> {
+        match &mut self.shading {
+            Shading::BlinnPhong(params) => params.environment_map.as_mut(),
+            Shading::Pbr(params) => params.environment_map.as_mut(),
+            Shading::Unlit | Shading::Textured(_) => None,
+        }
+    }
+
+    /// Hash of what `apply` uploads, equal for equal materials. Textures
+    /// are hashed by identity.
+    pub fn parameters_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(&self.shading).hash(&mut hasher);
+        match &self.shading {
+            Shading::Unlit => {}
+            Shading::BlinnPhong(params) => {
+                hash_floats(&mut hasher, &params.ambient.to_array());
+                hash_floats(&mut hasher, &params.diffuse.to_array());
+                hash_floats(&mut hasher, &params.specular.to_array());
+                hash_floats(
+                    &mut hasher,
+                    &[params.shininess, params.reflectivity],
+                );
+                params
+                    .environment_map
+                    .as_ref()
+                    .map(Rc::as_ptr)
+                    .hash(&mut hasher);
+            }
+            Shading::Pbr(params) => {
+                hash_floats(&mut hasher, &params.base_color.to_array());
+                hash_floats(&mut hasher, &params.emissive.to_array());
+                hash_floats(
+                    &mut hasher,
+                    &[
+                        params.metallic,
+                        params.roughness,
+                        params.occlusion_strength,
+                        params.normal_scale,
+                    ],
+                );
+                for texture in [
+                    &params.base_color_texture,
+                    &params.metallic_roughness_texture,
+                    &params.occlusion_texture,
+                    &params.emissive_texture,
+                    &params.normal_texture,
+                ] {
+                    texture.as_ref().map(Rc::as_ptr).hash(&mut hasher);
+                }
+                params
+                    .environment_map
+                    .as_ref()
+                    .map(Rc::as_ptr)
+                    .hash(&mut hasher);
+            }
+            Shading::Textured(params) => {
+                hash_floats(&mut hasher, &params.tint.to_array());
+                Rc::as_ptr(&params.texture).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Texture most of the material's appearance comes from, if any.
+    pub fn main_texture(&self) -> Option<&Rc<Texture2D>> {
+        match &self.shading {
+            Shading::Unlit | Shading::BlinnPhong(_) => None,
+            Shading::Pbr(params) => params.base_color_texture.as_ref(),
+            Shading::Textured(params) => Some(&params.texture),
+        }
+    }
+
+    /// Uploads the material parameters. `program` must be the one returned
+    /// by `program_name` and be in use. `scene_environment_map` is
+    /// reflected by lit materials without an environment map of their own.
+    pub fn apply(
+        &self,
+        context: &WebGl2RenderingContext,
+        program: &Program,
+        scene_environment_map: Option<&TextureCube>,
+    ) {
+        match &self.shading {
+            Shading::Unlit => {}
+            Shading::BlinnPhong(params) => {
+                context.uniform3fv_with_f32_array(
+                    program.uniform_location("material.ambient"),
+                    &params.ambient.to_rgb_array(),
+                );
+                context.uniform3fv_with_f32_array(
+                    program.uniform_location("material.diffuse"),
+                    &params.diffuse.to_rgb_array(),
+                );
+                context.uniform3fv_with_f32_array(
+                    program.uniform_location("material.specular"),
+                    &params.specular.to_rgb_array(),
+                );
+                context.uniform1f(
+                    program.uniform_location("material.shininess"),
+                    params.shininess,
+                );
+                context.uniform1f(
+                    program.uniform_location("material.reflectivity"),
+                    params.reflectivity,
+                );
+                bind_environment_map(
+                    context,
+                    program,
+                    params.environment_map.as_deref().or(scene_environment_map),
+                );
+            }
+            Shading::Pbr(params) => {
+                context.uniform4fv_with_f32_array(
+                    program.uniform_location("material.baseColor"),
+                    &params.base_color.to_array(),
+                );
+                context.uniform1f(
+                    program.uniform_location("material.metallic"),
+                    params.metallic,
+                );
+                context.uniform1f(
+                    program.uniform_location("material.roughness"),
+                    params.roughness,
+                );
+                context.uniform1f(
+                    program.uniform_location("material.occlusionStrength"),
+                    params.occlusion_strength,
+                );
+                context.uniform3fv_with_f32_array(
+                    program.uniform_location("material.emissive"),
+                    &params.emissive.to_rgb_array(),
+                );
+                context.uniform1f(
+                    program.uniform_location("material.normalScale"),
+                    params.normal_scale,
+                );
+                let maps = [
+                    (
+                        "baseColorTexture",
+                        "material.hasBaseColorTexture",
+                        COLOR_TEXTURE_UNIT,
+                        params.base_color_texture.as_deref(),
+                    ),
+                    (
+                        "metallicRoughnessTexture",
+                        "material.hasMetallicRoughnessTexture",
+                        METALLIC_ROUGHNESS_TEXTURE_UNIT,
+                        params.metallic_roughness_texture.as_deref(),
+                    ),
+                    (
+                        "occlusionTexture",
+                        "material.hasOcclusionTexture",
+                        OCCLUSION_TEXTURE_UNIT,
+                        params.occlusion_texture.as_deref(),
+                    ),
+                    (
+                        "emissiveTexture",
+                        "material.hasEmissiveTexture",
+                        EMISSIVE_TEXTURE_UNIT,
+                        params.emissive_texture.as_deref(),
+                    ),
+                    (
+                        "normalTexture",
+                        "material.hasNormalTexture",
+                        NORMAL_TEXTURE_UNIT,
+                        params.normal_texture.as_deref(),
+                    ),
+                ];
+                for (sampler, flag, unit, texture) in maps {
+                    bind_texture(context, program, sampler, unit, texture);
+                    context.uniform1i(
+                        program.uniform_location(flag),
+                        texture.is_some() as i32,
+                    );
+                }
+                bind_environment_map(
+                    context,
+                    program,
+                    params.environment_map.as_deref().or(scene_environment_map),
+                );
+            }
+            Shading::Textured(params) => {
+                context.uniform4fv_with_f32_array(
+                    program.uniform_location("tint"),
+                    &params.tint.to_array(),
+                );
+                bind_texture(
+                    context,
+                    program,
+                    "colorTexture",
+                    COLOR_TEXTURE_UNIT,
+                    Some(&params.texture),
+                );
+            }
+        }
+    }
+}
+
+fn hash_floats<H: Hasher>(hasher: &mut H, values: &[f32]) {
+    for value in values {
+        value.to_bits().hash(hasher);
+    }
+}
+
+/// Points `sampler` at `unit` and binds `texture` to it, if any.
+fn bind_texture(
+    context: &WebGl2RenderingContext,
+    program: &Program,
+    sampler: &str,
+    unit: u32,
+    texture: Option<&Texture2D>,
+) {
+    context.uniform1i(program.uniform_location(sampler), unit as i32);
+    if let Some(texture) = texture {
+        texture.bind(context, unit);
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::unlit()
+    }
+}
+
+fn bind_environment_map(
+    context: &WebGl2RenderingContext,
+    program: &Program,
+    environment_map: Option<&TextureCube>,
+) {
+    context.uniform1i(
+        program.uniform_location("environmentMap"),
+        ENVIRONMENT_TEXTURE_UNIT as i32,
+    );
+    context.uniform1i(
+        program.uniform_location("hasEnvironmentMap"),
+        environment_map.is_some() as i32,
+    );
+    if let Some(environment_map) = environment_map {
+        environment_map.bind(context, ENVIRONMENT_TEXTURE_UNIT);
+        context.uniform1f(
+            program.uniform_location("environmentMapMaxLod"),
+            (environment_map.mip_levels() - 1) as f32,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pbr_material_from_factors() {
+        let base_color = Color::rgb(0.8, 0.1, 0.1);
+        let material = Material::pbr(Pbr::with_factors(base_color, 1.5, -0.2));
+        assert_eq!(material.program_name(), PBR_PROGRAM);
+        match &material.shading {
+            Shading::Pbr(params) => {
+                assert_eq!(params.base_color, base_color);
+                assert_eq!(params.metallic, 1.0);
+                assert_eq!(params.roughness, 0.0);
+                assert!(params.base_color_texture.is_none());
+            }
+            shading => panic!("Unexpected shading {:?}", shading),
+        }
+    }
+
+    #[test]
+    fn pbr_texture_slots() {
+        let names = [
+            "base_color",
+            "metallic_roughness",
+            "occlusion",
+            "emissive",
+            "normal",
+        ];
+        let slots: Vec<PbrTexture> = names
+            .iter()
+            .map(|name| PbrTexture::from_name(name).unwrap())
+            .collect();
+        assert_eq!(slots[1], PbrTexture::MetallicRoughness);
+        assert_eq!(PbrTexture::from_name("albedo"), None);
+        assert_eq!(PbrTexture::BaseColor.format(), TextureFormat::Srgb8Alpha8);
+        assert_eq!(PbrTexture::Normal.format(), TextureFormat::Rgba8);
+    }
+}
@@ -0,0 +1,113 @@
+use nalgebra::Matrix4;
+
+use crate::{math::matrix::transform_point, types::Vec3};
+
+/// Axis-aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// Smallest box containing `points`, `None` if there are none.
+    pub fn from_points<I>(points: I) -> Option<Aabb>
+    where
+        I: IntoIterator<Item = Vec3>,
+    {
+        points.into_iter().fold(None, |bounds, point| {
+            let point_bounds = Aabb::new(point, point);
+            Some(match bounds {
+                Some(bounds) => bounds.union(&point_bounds),
+                None => point_bounds,
+            })
+        })
+    }
+
+    /// Bounds of tightly packed xyz positions.
+    pub fn from_positions(positions: &[f32]) -> Option<Aabb> {
+        Aabb::from_points(
+            positions.chunks_exact(3).map(|position| {
+                Vec3::new(position[0], position[1], position[2])
+            }),
+        )
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Vec3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Vec3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    pub fn center(&self) -> Vec3 {
+        Vec3::new(
+            (self.min.x + self.max.x) / 2.0,
+            (self.min.y + self.max.y) / 2.0,
+            (self.min.z + self.max.z) / 2.0,
+        )
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// Bounds of this box once transformed by `matrix`, in the row-vector
+    /// convention of `LinearTransform`.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Aabb {
+        Aabb::from_points(
+            self.corners()
+                .iter()
+                .map(|corner| transform_point(matrix, *corner)),
+        )
+        .unwrap_or(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_positions() {
+        let bounds =
+            Aabb::from_positions(&[1.0, -2.0, 3.0, -1.0, 4.0, 0.5]).unwrap();
+        assert_eq!(bounds.min, Vec3::new(-1.0, -2.0, 0.5));
+        assert_eq!(bounds.max, Vec3::new(1.0, 4.0, 3.0));
+        assert_eq!(Aabb::from_positions(&[]), None);
+    }
+
+    #[test]
+    fn transforms_with_row_vector_matrices() {
+        let mut matrix = Matrix4::identity() * 2.0;
+        matrix[(3, 3)] = 1.0;
+        matrix[(3, 0)] = 10.0;
+        let bounds =
+            Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0))
+                .transform(&matrix);
+        assert_eq!(bounds.min, Vec3::new(8.0, -2.0, -2.0));
+        assert_eq!(bounds.max, Vec3::new(12.0, 2.0, 2.0));
+    }
+}
@@ -0,0 +1,126 @@
+//! Metallic-roughness BRDF, following the glTF 2.0 specification:
+//! https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#appendix-b-brdf-implementation
+//!
+//! This mirrors the GLSL in `gl::shaders::PBR_FRAGMENT_SHADER` so the shading
+//! math can be checked natively.
+
+use std::f32::consts::PI;
+
+/// Reflectance at normal incidence of dielectrics.
+pub const DIELECTRIC_F0: f32 = 0.04;
+
+/// Trowbridge-Reitz/GGX normal distribution function.
+pub fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha_sq = alpha * alpha;
+    let f = n_dot_h * n_dot_h * (alpha_sq - 1.0) + 1.0;
+    alpha_sq / (PI * f * f)
+}
+
+/// Height-correlated Smith visibility term. It already includes the
+/// `1 / (4 * n_dot_l * n_dot_v)` denominator of the Cook-Torrance BRDF.
+pub fn visibility_smith_ggx(n_dot_l: f32, n_dot_v: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha_sq = alpha * alpha;
+    let ggx_v =
+        n_dot_l * (n_dot_v * n_dot_v * (1.0 - alpha_sq) + alpha_sq).sqrt();
+    let ggx_l =
+        n_dot_v * (n_dot_l * n_dot_l * (1.0 - alpha_sq) + alpha_sq).sqrt();
+    let ggx = ggx_v + ggx_l;
+    if ggx > 0.0 {
+        0.5 / ggx
+    } else {
+        0.0
+    }
+}
+
+/// Schlick's approximation of the Fresnel term.
+pub fn fresnel_schlick(f0: [f32; 3], v_dot_h: f32) -> [f32; 3] {
+    let factor = (1.0 - v_dot_h).clamp(0.0, 1.0).powi(5);
+    f0.map(|f0| f0 + (1.0 - f0) * factor)
+}
+
+/// Evaluates the BRDF (diffuse + specular, without the `n_dot_l` cosine
+/// term) for a base color, metallic and roughness.
+pub fn cook_torrance(
+    n_dot_l: f32,
+    n_dot_v: f32,
+    n_dot_h: f32,
+    v_dot_h: f32,
+    base_color: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+) -> [f32; 3] {
+    let f0 = base_color.map(|c| mix(DIELECTRIC_F0, c, metallic));
+    let fresnel = fresnel_schlick(f0, v_dot_h);
+    let specular = distribution_ggx(n_dot_h, roughness)
+        * visibility_smith_ggx(n_dot_l, n_dot_v, roughness);
+
+    let mut result = [0.0; 3];
+    for i in 0..3 {
+        let diffuse = base_color[i] * (1.0 - metallic) / PI;
+        result[i] = (1.0 - fresnel[i]) * diffuse + fresnel[i] * specular;
+    }
+    result
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < EPSILON,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn distribution_peaks_at_half_vector() {
+        assert_close(distribution_ggx(1.0, 0.5), 5.092958);
+        assert_close(distribution_ggx(0.8, 0.5), 0.124340);
+        // A fully rough surface spreads energy uniformly.
+        assert_close(distribution_ggx(0.9, 1.0), 1.0 / PI);
+    }
+
+    #[test]
+    fn visibility_matches_reference() {
+        assert_close(visibility_smith_ggx(0.5, 0.7, 0.5), 0.673303);
+        assert_close(visibility_smith_ggx(1.0, 1.0, 0.3), 0.25);
+        assert_close(visibility_smith_ggx(0.0, 0.0, 0.5), 0.0);
+    }
+
+    #[test]
+    fn fresnel_goes_from_f0_to_one() {
+        let f0 = [DIELECTRIC_F0; 3];
+        assert_close(fresnel_schlick(f0, 1.0)[0], DIELECTRIC_F0);
+        assert_close(fresnel_schlick(f0, 0.5)[0], 0.07);
+        assert_close(fresnel_schlick(f0, 0.0)[0], 1.0);
+    }
+
+    #[test]
+    fn cook_torrance_dielectric() {
+        let brdf =
+            cook_torrance(0.8, 0.9, 0.95, 0.85, [0.8, 0.2, 0.1], 0.0, 0.5);
+        assert_close(brdf[0], 0.255987);
+        assert_close(brdf[1], 0.072654);
+        assert_close(brdf[2], 0.042099);
+    }
+
+    #[test]
+    fn cook_torrance_metal_has_no_diffuse() {
+        let gold = [1.0, 0.766, 0.336];
+        let brdf = cook_torrance(0.8, 0.9, 0.95, 0.85, gold, 1.0, 0.3);
+        assert_close(brdf[0], 0.081365);
+        assert_close(brdf[1], 0.062327);
+        assert_close(brdf[2], 0.027343);
+    }
+}
@@ -1,8 +1,10 @@
 use nalgebra::{matrix, Matrix4};
 
 use super::quaternion::Quaternion;
+use crate::types::Vec3;
 
 /// https://jsantell.com/3d-projection/#perspective-projection
+#[rustfmt::skip]
 pub fn from_frustrum(
     near: f32,
     far: f32,
@@ -11,7 +13,6 @@ pub fn from_frustrum(
     top: f32,
     bottom: f32,
 ) -> Matrix4<f32> {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
     matrix![
         2.0 * near / (right - left), 0.0, (right + left) / (right - left), 0.0;
         0.0, (2.0 * near) / (top - bottom), (top + bottom) / (top - bottom), 0.0;
@@ -20,6 +21,7 @@ pub fn from_frustrum(
     ]
 }
 
+#[rustfmt::skip]
 pub fn from_fov_and_aspect(
     near: f32,
     far: f32,
@@ -27,7 +29,6 @@ pub fn from_fov_and_aspect(
     aspect: f32,
 ) -> Matrix4<f32> {
     let e = 1.0 / (-fov / 2.0).tan();
-    #[cfg_attr(rustfmt, rustfmt_skip)]
     matrix![
         e / aspect, 0.0, 0.0, 0.0;
         0.0, e, 0.0, 0.0;
@@ -72,8 +73,8 @@ pub fn from_quaternion(q: &Quaternion) -> Matrix4<f32> {
 }
 
 // TODO: Check if there is a more "rustic" way of converting
+#[rustfmt::skip]
 pub fn mat_to_col_array(mat: &Matrix4<f32>) -> [f32; 16] {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
     [
         mat.m11, mat.m21, mat.m31, mat.m41, 
         mat.m12, mat.m22, mat.m32, mat.m42, 
@@ -82,8 +83,8 @@ pub fn mat_to_col_array(mat: &Matrix4<f32>) -> [f32; 16] {
     ]
 }
 
+#[rustfmt::skip]
 pub fn mat_to_row_array(mat: &Matrix4<f32>) -> [f32; 16] {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
     [
         mat.m11, mat.m12, mat.m13, mat.m14, 
         mat.m21, mat.m22, mat.m23, mat.m24, 
@@ -91,3 +92,17 @@ pub fn mat_to_row_array(mat: &Matrix4<f32>) -> [f32; 16] {
         mat.m41, mat.m42, mat.m43, mat.m44, 
     ]
 }
+
+/// Transforms `point` by `mat`, in the row-vector convention used by
+/// `LinearTransform` (the translation is on the last row).
+pub fn transform_point(mat: &Matrix4<f32>, point: Vec3) -> Vec3 {
+    let w = point.x * mat.m14 + point.y * mat.m24 + point.z * mat.m34 + mat.m44;
+    Vec3::new(
+        (point.x * mat.m11 + point.y * mat.m21 + point.z * mat.m31 + mat.m41)
+            / w,
+        (point.x * mat.m12 + point.y * mat.m22 + point.z * mat.m32 + mat.m42)
+            / w,
+        (point.x * mat.m13 + point.y * mat.m23 + point.z * mat.m33 + mat.m43)
+            / w,
+    )
+}
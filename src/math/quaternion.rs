@@ -70,7 +70,7 @@ impl ops::Mul<&Quaternion> for Quaternion {
 
 impl ops::MulAssign<&Quaternion> for Quaternion {
     fn mul_assign(&mut self, _rhs: &Self) {
-        let q = multiply(&self, _rhs);
+        let q = multiply(self, _rhs);
         *self = q;
     }
 }
@@ -0,0 +1,126 @@
+use web_sys::WebGl2RenderingContext;
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        gl_utils::set_viewport,
+        render_state::{Blend, BlendEquation, BlendFactor, RenderState},
+        render_target::{
+            bind_target, AttachmentSettings, RenderTarget, RenderTargetSettings,
+        },
+        texture::{Filter, TextureFormat},
+    },
+};
+
+pub static OIT_COMPOSITE_PROGRAM: &str = "oit_composite";
+
+/// Render targets of weighted blended order-independent transparency.
+///
+/// Transparent fragments are accumulated in any order, then composited
+/// over the opaque image. The depth buffer only holds the opaque geometry,
+/// so that hidden transparent fragments are rejected.
+pub struct OitTargets {
+    /// Color 0 holds the weighted premultiplied color in RGB and the
+    /// revealage in A, color 1 the sum of the weighted alphas.
+    pub target: RenderTarget,
+}
+
+impl OitTargets {
+    /// Creates targets of `width` by `height` pixels. Needs
+    /// `EXT_color_buffer_float`.
+    pub fn new(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+    ) -> Result<OitTargets, EngineError> {
+        let settings = RenderTargetSettings {
+            color: vec![
+                AttachmentSettings::texture(
+                    TextureFormat::Rgba16F,
+                    Filter::Nearest,
+                ),
+                AttachmentSettings::texture(
+                    TextureFormat::R16F,
+                    Filter::Nearest,
+                ),
+            ],
+            depth: Some(AttachmentSettings::renderbuffer(
+                TextureFormat::Depth24,
+            )),
+            samples: 1,
+        };
+        Ok(OitTargets {
+            target: RenderTarget::new(env, width, height, &settings)?,
+        })
+    }
+
+    /// Blending of the accumulation pass: color and weights are summed,
+    /// revealage is multiplied by `1 - alpha`.
+    pub fn blend() -> Blend {
+        Blend {
+            color_equation: BlendEquation::Add,
+            alpha_equation: BlendEquation::Add,
+            src_color: BlendFactor::One,
+            dst_color: BlendFactor::One,
+            src_alpha: BlendFactor::Zero,
+            dst_alpha: BlendFactor::OneMinusSrcAlpha,
+        }
+    }
+
+    /// Binds and clears the targets, drawing into `viewport`. The opaque
+    /// depth must then be drawn before the transparent shapes.
+    pub fn begin(&self, env: &GlEnvironment, viewport: [i32; 4]) {
+        let context = &env.context;
+        self.target.bind(context);
+        set_viewport(context, viewport);
+        // Clears are masked by the depth and color write state.
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::default());
+        context.clear_bufferfv_with_f32_array(
+            WebGl2RenderingContext::COLOR,
+            0,
+            &[0.0, 0.0, 0.0, 1.0],
+        );
+        context.clear_bufferfv_with_f32_array(
+            WebGl2RenderingContext::COLOR,
+            1,
+            &[0.0, 0.0, 0.0, 0.0],
+        );
+        context.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+    }
+
+    /// Blends the accumulated transparency in `viewport` over
+    /// `destination`, or the canvas when `None`.
+    pub fn composite(
+        &self,
+        env: &GlEnvironment,
+        destination: Option<&RenderTarget>,
+        viewport: [i32; 4],
+    ) -> Result<(), EngineError> {
+        let context = &env.context;
+        let program = env.program(OIT_COMPOSITE_PROGRAM)?;
+        bind_target(context, destination);
+        set_viewport(context, viewport);
+        env.state_cache.borrow_mut().apply(
+            context,
+            &RenderState {
+                blend: Some(Blend::alpha()),
+                ..RenderState::full_screen()
+            },
+        );
+        context.use_program(Some(&program.gl_program));
+        for (unit, sampler) in ["accumulation", "weight"].iter().enumerate() {
+            let texture = self.target.color_texture(unit).ok_or_else(|| {
+                EngineError::Internal(String::from(
+                    "OIT target has no texture.",
+                ))
+            })?;
+            texture.bind(context, unit as u32);
+            context.uniform1i(program.uniform_location(sampler), unit as i32);
+        }
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        Ok(())
+    }
+}
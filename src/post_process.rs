@@ -0,0 +1,551 @@
+use web_sys::WebGl2RenderingContext;
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        gl_utils::{create_program, get_viewport, set_viewport, Program},
+        render_state::RenderState,
+        render_target::{
+            bind_target, AttachmentSettings, RenderTarget, RenderTargetSettings,
+        },
+        shaders::FULLSCREEN_VERTEX_SHADER,
+        texture::{Filter, Texture2D, TextureFormat},
+    },
+};
+
+pub static TONE_MAPPING_PROGRAM: &str = "tone_mapping";
+pub static SRGB_PROGRAM: &str = "srgb";
+pub static FXAA_PROGRAM: &str = "fxaa";
+pub static BLOOM_THRESHOLD_PROGRAM: &str = "bloom_threshold";
+pub static BLUR_PROGRAM: &str = "blur";
+pub static BLOOM_COMBINE_PROGRAM: &str = "bloom_combine";
+pub static VIGNETTE_PROGRAM: &str = "vignette";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapping {
+    /// `c / (1 + c)`, which never saturates.
+    Reinhard,
+    /// Filmic curve approximating the ACES reference rendering transform.
+    Aces,
+}
+
+impl ToneMapping {
+    /// Value of the `toneMapping` shader uniform.
+    fn shader_value(self) -> i32 {
+        match self {
+            ToneMapping::Reinhard => 0,
+            ToneMapping::Aces => 1,
+        }
+    }
+}
+
+pub enum EffectKind {
+    /// Scales the colors by `exposure` and maps them from HDR to [0, 1].
+    ToneMapping {
+        operator: ToneMapping,
+        exposure: f32,
+    },
+    /// Encodes linear colors to sRGB for display.
+    Srgb,
+    /// Fast approximate anti-aliasing, best applied to sRGB colors.
+    Fxaa,
+    /// Adds a blur of the parts brighter than `threshold`, computed at half
+    /// resolution with `iterations` blur passes.
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        iterations: u32,
+    },
+    /// Darkens the corners by up to `intensity`, over `smoothness` of the
+    /// distance to the center.
+    Vignette { intensity: f32, smoothness: f32 },
+    /// A user fragment shader, with float uniforms set from `parameters`.
+    Custom {
+        program: Program,
+        /// Kept to compile the program again after a context loss.
+        fragment_source: String,
+        parameters: Vec<(String, f32)>,
+    },
+}
+
+pub struct Effect {
+    pub name: String,
+    pub enabled: bool,
+    pub kind: EffectKind,
+}
+
+impl Effect {
+    fn new(name: &str, kind: EffectKind) -> Effect {
+        Effect {
+            name: String::from(name),
+            enabled: false,
+            kind,
+        }
+    }
+}
+
+/// Full-screen effects applied to the rendered frame before it reaches the
+/// canvas.
+///
+/// While an effect is enabled the scene is rendered into a HDR target, then
+/// each enabled effect reads the previous result and writes the next one.
+/// The last enabled effect writes to the canvas.
+pub struct PostProcess {
+    /// Effects in the order they are applied.
+    pub effects: Vec<Effect>,
+    /// Multisampling of the scene target, which replaces the antialiasing
+    /// of the canvas.
+    pub samples: u32,
+    scene_target: Option<RenderTarget>,
+    /// Intermediate results, written to alternately.
+    ping_pong: [Option<RenderTarget>; 2],
+    /// Half resolution targets of the bloom blur.
+    bloom_targets: [Option<RenderTarget>; 2],
+}
+
+impl PostProcess {
+    /// The built-in effects, all disabled, in the order bloom, tone
+    /// mapping, vignette, sRGB and FXAA.
+    pub fn new() -> PostProcess {
+        PostProcess {
+            effects: vec![
+                Effect::new(
+                    "bloom",
+                    EffectKind::Bloom {
+                        threshold: 1.0,
+                        intensity: 0.5,
+                        iterations: 3,
+                    },
+                ),
+                Effect::new(
+                    "tone_mapping",
+                    EffectKind::ToneMapping {
+                        operator: ToneMapping::Aces,
+                        exposure: 1.0,
+                    },
+                ),
+                Effect::new(
+                    "vignette",
+                    EffectKind::Vignette {
+                        intensity: 0.4,
+                        smoothness: 0.5,
+                    },
+                ),
+                Effect::new("srgb", EffectKind::Srgb),
+                Effect::new("fxaa", EffectKind::Fxaa),
+            ],
+            samples: 4,
+            scene_target: None,
+            ping_pong: [None, None],
+            bloom_targets: [None, None],
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.effects.iter().any(|effect| effect.enabled)
+    }
+
+    pub fn effect_mut(
+        &mut self,
+        name: &str,
+    ) -> Result<&mut Effect, EngineError> {
+        self.effects
+            .iter_mut()
+            .find(|effect| effect.name == name)
+            .ok_or_else(|| no_effect(name))
+    }
+
+    /// Moves effect `name` to position `index` of the chain.
+    pub fn move_effect(
+        &mut self,
+        name: &str,
+        index: usize,
+    ) -> Result<(), EngineError> {
+        let position = self.position(name)?;
+        let effect = self.effects.remove(position);
+        self.effects.insert(index.min(self.effects.len()), effect);
+        Ok(())
+    }
+
+    /// Appends an enabled effect drawn with `fragment_source`, a GLSL ES
+    /// 3.00 fragment shader. It reads the previous result from the `source`
+    /// sampler at `in vec2 vUv`, and gets the size of a texel of `source`
+    /// in `texelSize`.
+    pub fn add_custom(
+        &mut self,
+        env: &GlEnvironment,
+        name: &str,
+        fragment_source: &str,
+    ) -> Result<(), EngineError> {
+        if self.position(name).is_ok() {
+            return Err(EngineError::InvalidArgument(format!(
+                "A post effect is already named {}.",
+                name
+            )));
+        }
+        let program = create_program(
+            &env.resources,
+            FULLSCREEN_VERTEX_SHADER,
+            fragment_source,
+        )?;
+        self.effects.push(Effect {
+            name: String::from(name),
+            enabled: true,
+            kind: EffectKind::Custom {
+                program,
+                fragment_source: String::from(fragment_source),
+                parameters: Vec::new(),
+            },
+        });
+        Ok(())
+    }
+
+    /// Removes effect `name`, deleting its program if it is custom.
+    pub fn remove(&mut self, name: &str) -> Result<(), EngineError> {
+        let position = self.position(name)?;
+        self.effects.remove(position);
+        Ok(())
+    }
+
+    /// Drops the targets, reallocated on the next frame, and compiles the
+    /// custom effects again after the context was restored.
+    pub fn restore(&mut self, env: &GlEnvironment) -> Result<(), EngineError> {
+        self.scene_target = None;
+        self.ping_pong = [None, None];
+        self.bloom_targets = [None, None];
+        for effect in &mut self.effects {
+            if let EffectKind::Custom {
+                program,
+                fragment_source,
+                ..
+            } = &mut effect.kind
+            {
+                *program = create_program(
+                    &env.resources,
+                    FULLSCREEN_VERTEX_SHADER,
+                    fragment_source,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> Result<usize, EngineError> {
+        self.effects
+            .iter()
+            .position(|effect| effect.name == name)
+            .ok_or_else(|| no_effect(name))
+    }
+
+    /// Allocates the targets at the size of the canvas and returns the one
+    /// the scene must be rendered into, or `None` when no effect is enabled.
+    pub fn prepare(
+        &mut self,
+        env: &GlEnvironment,
+    ) -> Result<Option<&RenderTarget>, EngineError> {
+        if !self.is_active() {
+            return Ok(None);
+        }
+        let context = &env.context;
+        let width = context.drawing_buffer_width() as u32;
+        let height = context.drawing_buffer_height() as u32;
+        let format = if env.color_buffer_float {
+            TextureFormat::Rgba16F
+        } else {
+            TextureFormat::Rgba8
+        };
+        let color = RenderTargetSettings {
+            color: vec![AttachmentSettings::texture(format, Filter::Linear)],
+            depth: None,
+            samples: 1,
+        };
+        let scene = RenderTargetSettings {
+            depth: Some(AttachmentSettings::renderbuffer(
+                TextureFormat::Depth24,
+            )),
+            samples: self.samples,
+            ..color.clone()
+        };
+
+        resize(env, &mut self.scene_target, width, height, &scene)?;
+        for target in &mut self.ping_pong {
+            resize(env, target, width, height, &color)?;
+        }
+        let bloom = self.effects.iter().any(|effect| {
+            effect.enabled && matches!(effect.kind, EffectKind::Bloom { .. })
+        });
+        if bloom {
+            let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+            for target in &mut self.bloom_targets {
+                resize(env, target, width, height, &color)?;
+            }
+        }
+        // Cameras may leave parts of the target undrawn, transparent like
+        // those of the canvas.
+        if let Some(target) = &self.scene_target {
+            let viewport = get_viewport(context);
+            target.bind(context);
+            env.state_cache
+                .borrow_mut()
+                .apply(context, &RenderState::default());
+            context.clear_color(0.0, 0.0, 0.0, 0.0);
+            context.clear(
+                WebGl2RenderingContext::COLOR_BUFFER_BIT
+                    | WebGl2RenderingContext::DEPTH_BUFFER_BIT,
+            );
+            bind_target(context, None);
+            set_viewport(context, viewport);
+        }
+        Ok(self.scene_target.as_ref())
+    }
+
+    /// Applies the enabled effects to the scene target filled since
+    /// `prepare`, and writes the result to the canvas.
+    pub fn apply(&self, env: &GlEnvironment) -> Result<(), EngineError> {
+        let enabled: Vec<&Effect> = self
+            .effects
+            .iter()
+            .filter(|effect| effect.enabled)
+            .collect();
+        if enabled.is_empty() {
+            return Ok(());
+        }
+        let viewport = get_viewport(&env.context);
+        let mut source = color_texture(self.scene_target.as_ref())?;
+        for (i, effect) in enabled.iter().enumerate() {
+            let destination = if i + 1 == enabled.len() {
+                None
+            } else {
+                Some(self.ping_pong[i % 2].as_ref().ok_or_else(|| {
+                    EngineError::Internal(String::from(
+                        "Post-processing targets are not prepared.",
+                    ))
+                })?)
+            };
+            let pass = Pass {
+                env,
+                destination,
+                viewport,
+            };
+            self.apply_effect(&pass, effect, source)?;
+            if destination.is_some() {
+                source = color_texture(destination)?;
+            }
+        }
+        set_viewport(&env.context, viewport);
+        Ok(())
+    }
+
+    fn apply_effect(
+        &self,
+        pass: &Pass,
+        effect: &Effect,
+        source: &Texture2D,
+    ) -> Result<(), EngineError> {
+        let context = &pass.env.context;
+        match &effect.kind {
+            EffectKind::ToneMapping { operator, exposure } => {
+                let program = pass.begin(TONE_MAPPING_PROGRAM, source)?;
+                context
+                    .uniform1f(program.uniform_location("exposure"), *exposure);
+                context.uniform1i(
+                    program.uniform_location("toneMapping"),
+                    operator.shader_value(),
+                );
+                draw_full_screen(context);
+            }
+            EffectKind::Srgb => {
+                pass.begin(SRGB_PROGRAM, source)?;
+                draw_full_screen(context);
+            }
+            EffectKind::Fxaa => {
+                pass.begin(FXAA_PROGRAM, source)?;
+                draw_full_screen(context);
+            }
+            EffectKind::Bloom {
+                threshold,
+                intensity,
+                iterations,
+            } => {
+                self.apply_bloom(
+                    pass,
+                    source,
+                    *threshold,
+                    *intensity,
+                    *iterations,
+                )?;
+            }
+            EffectKind::Vignette {
+                intensity,
+                smoothness,
+            } => {
+                let program = pass.begin(VIGNETTE_PROGRAM, source)?;
+                context.uniform1f(
+                    program.uniform_location("intensity"),
+                    *intensity,
+                );
+                context.uniform1f(
+                    program.uniform_location("smoothness"),
+                    *smoothness,
+                );
+                draw_full_screen(context);
+            }
+            EffectKind::Custom {
+                program,
+                parameters,
+                ..
+            } => {
+                pass.begin_with(program, source);
+                for (name, value) in parameters {
+                    context.uniform1f(program.uniform_location(name), *value);
+                }
+                draw_full_screen(context);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_bloom(
+        &self,
+        pass: &Pass,
+        source: &Texture2D,
+        threshold: f32,
+        intensity: f32,
+        iterations: u32,
+    ) -> Result<(), EngineError> {
+        let context = &pass.env.context;
+        let (first, second) = match &self.bloom_targets {
+            [Some(first), Some(second)] => (first, second),
+            _ => {
+                return Err(EngineError::Internal(String::from(
+                    "Bloom targets are not prepared.",
+                )))
+            }
+        };
+        let first_texture = color_texture(Some(first))?;
+        let second_texture = color_texture(Some(second))?;
+        let half = |destination| Pass {
+            destination: Some(destination),
+            ..*pass
+        };
+
+        let program = half(first).begin(BLOOM_THRESHOLD_PROGRAM, source)?;
+        context.uniform1f(program.uniform_location("threshold"), threshold);
+        draw_full_screen(context);
+        let texel = [1.0 / first.width as f32, 1.0 / first.height as f32];
+        for _ in 0..iterations {
+            for (destination, input, direction) in [
+                (second, first_texture, [texel[0], 0.0]),
+                (first, second_texture, [0.0, texel[1]]),
+            ] {
+                let program = half(destination).begin(BLUR_PROGRAM, input)?;
+                context.uniform2fv_with_f32_array(
+                    program.uniform_location("direction"),
+                    &direction,
+                );
+                draw_full_screen(context);
+            }
+        }
+
+        let program = pass.begin(BLOOM_COMBINE_PROGRAM, source)?;
+        first_texture.bind(context, 1);
+        context.uniform1i(program.uniform_location("bloom"), 1);
+        context.uniform1f(program.uniform_location("intensity"), intensity);
+        draw_full_screen(context);
+        Ok(())
+    }
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        PostProcess::new()
+    }
+}
+
+/// Where a full-screen pass writes.
+#[derive(Copy, Clone)]
+struct Pass<'a> {
+    env: &'a GlEnvironment,
+    /// Render target, or the canvas when `None`.
+    destination: Option<&'a RenderTarget>,
+    /// Viewport of the canvas.
+    viewport: [i32; 4],
+}
+
+impl<'a> Pass<'a> {
+    /// Binds the destination and the built-in program `name` reading
+    /// `source`, which is returned for the effect to set its uniforms.
+    fn begin(
+        &self,
+        name: &str,
+        source: &Texture2D,
+    ) -> Result<&'a Program, EngineError> {
+        let program = self.env.program(name)?;
+        self.begin_with(program, source);
+        Ok(program)
+    }
+
+    fn begin_with(&self, program: &Program, source: &Texture2D) {
+        let context = &self.env.context;
+        bind_target(context, self.destination);
+        if self.destination.is_none() {
+            set_viewport(context, self.viewport);
+        }
+        self.env
+            .state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::full_screen());
+        context.use_program(Some(&program.gl_program));
+        source.bind(context, 0);
+        context.uniform1i(program.uniform_location("source"), 0);
+        context.uniform2f(
+            program.uniform_location("texelSize"),
+            1.0 / source.width as f32,
+            1.0 / source.height as f32,
+        );
+    }
+}
+
+fn draw_full_screen(context: &WebGl2RenderingContext) {
+    context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+}
+
+fn color_texture(
+    target: Option<&RenderTarget>,
+) -> Result<&Texture2D, EngineError> {
+    target
+        .and_then(|target| target.color_texture(0))
+        .map(|texture| texture.as_ref())
+        .ok_or_else(|| {
+            EngineError::Internal(String::from(
+                "Post-processing targets are not prepared.",
+            ))
+        })
+}
+
+/// Makes `target` a `width` by `height` target with `settings`, recreating
+/// it when either changed.
+fn resize(
+    env: &GlEnvironment,
+    target: &mut Option<RenderTarget>,
+    width: u32,
+    height: u32,
+    settings: &RenderTargetSettings,
+) -> Result<(), EngineError> {
+    let current = target.as_ref().is_some_and(|target| {
+        target.width == width
+            && target.height == height
+            && target.settings() == settings
+    });
+    if !current {
+        // Frees the previous target before allocating the new one.
+        *target = None;
+        *target = Some(RenderTarget::new(env, width, height, settings)?);
+    }
+    Ok(())
+}
+
+fn no_effect(name: &str) -> EngineError {
+    EngineError::InvalidArgument(format!("No post effect named {}.", name))
+}
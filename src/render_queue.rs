@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use nalgebra::Matrix4;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    gl::{gl_shape::GlShape, mesh::Mesh, texture::Texture2D},
+    material::AlphaMode,
+    math::matrix::transform_point,
+};
+
+/// Draw calls of the last frame issued through render queues, and the binds
+/// they took. Without sorting, every draw would bind its program, material
+/// and vertex array.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub program_binds: u32,
+    pub material_binds: u32,
+    pub vertex_array_binds: u32,
+}
+
+#[wasm_bindgen]
+impl RenderStats {
+    /// Binds skipped because consecutive draws shared their program,
+    /// material or vertex array. Shapes without instances share the vertex
+    /// array of their mesh.
+    pub fn saved_binds(&self) -> u32 {
+        3 * self.draw_calls
+            - self.program_binds
+            - self.material_binds
+            - self.vertex_array_binds
+    }
+}
+
+/// Group of draws of a queue, drawn in this order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pass {
+    Opaque,
+    /// Alpha tested draws, after the opaque ones which then occlude them
+    /// before their fragment shaders run.
+    Masked,
+    Transparent,
+}
+
+impl Pass {
+    pub fn of(alpha_mode: AlphaMode) -> Pass {
+        match alpha_mode {
+            AlphaMode::Opaque => Pass::Opaque,
+            AlphaMode::Mask(_) => Pass::Masked,
+            AlphaMode::Blend => Pass::Transparent,
+        }
+    }
+}
+
+/// Order of a draw within a render queue, lowest first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey(u64);
+
+impl SortKey {
+    /// Groups draws by program, material, main texture and mesh, nearest
+    /// first within a group so that hidden fragments are rejected early.
+    /// Only the low 12 bits of `material`, `texture` and `mesh` are kept.
+    pub fn opaque(
+        pass: Pass,
+        program: u8,
+        material: u16,
+        texture: u16,
+        mesh: u16,
+        depth: f32,
+    ) -> SortKey {
+        SortKey(
+            (pass as u64) << 60
+                | (program as u64) << 52
+                | (material as u64 & 0xFFF) << 40
+                | (texture as u64 & 0xFFF) << 28
+                | (mesh as u64 & 0xFFF) << 16
+                | (depth_bits(depth) >> 16) as u64,
+        )
+    }
+
+    /// Orders draws farthest first, as blending needs, grouping draws at
+    /// the same depth by program and material.
+    pub fn transparent(
+        pass: Pass,
+        depth: f32,
+        program: u8,
+        material: u16,
+    ) -> SortKey {
+        SortKey(
+            (pass as u64) << 60
+                | ((u32::MAX - depth_bits(depth)) as u64) << 24
+                | (program as u64) << 16
+                | material as u64,
+        )
+    }
+}
+
+/// Bits of a non-negative depth, ordered like the depths.
+fn depth_bits(depth: f32) -> u32 {
+    depth.max(0.0).to_bits()
+}
+
+/// Shapes to draw, sorted to minimize state changes between consecutive
+/// draws.
+#[derive(Default)]
+pub struct RenderQueue<'a> {
+    items: Vec<(SortKey, &'a GlShape)>,
+    /// Programs, main textures and meshes seen so far, numbered in the
+    /// keys.
+    programs: Vec<&'static str>,
+    textures: HashMap<*const Texture2D, u16>,
+    meshes: HashMap<*const Mesh, u16>,
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn new() -> RenderQueue<'a> {
+        RenderQueue::default()
+    }
+
+    /// Queues `shape`, seen through `view_matrix`. Instanced shapes without
+    /// instances are skipped.
+    pub fn push(&mut self, shape: &'a GlShape, view_matrix: &Matrix4<f32>) {
+        if shape.instances.as_ref().is_some_and(|i| i.is_empty()) {
+            return;
+        }
+        let program_name = shape.material.program_name();
+        let program =
+            match self.programs.iter().position(|p| *p == program_name) {
+                Some(program) => program,
+                None => {
+                    self.programs.push(program_name);
+                    self.programs.len() - 1
+                }
+            } as u8;
+        let material = shape.material.parameters_hash() as u16;
+        // The camera looks down -z.
+        let depth =
+            -transform_point(view_matrix, shape.world_bounds().center()).z;
+        let pass = Pass::of(shape.alpha_mode());
+        let key = if pass == Pass::Transparent {
+            SortKey::transparent(pass, depth, program, material)
+        } else {
+            let textures = &mut self.textures;
+            let texture = shape.material.main_texture().map_or(0, |texture| {
+                let next = textures.len() as u16 + 1;
+                *textures.entry(Rc::as_ptr(texture)).or_insert(next)
+            });
+            let next = self.meshes.len() as u16;
+            let mesh =
+                *self.meshes.entry(Rc::as_ptr(&shape.mesh)).or_insert(next);
+            SortKey::opaque(pass, program, material, texture, mesh, depth)
+        };
+        self.items.push((key, shape));
+    }
+
+    /// Sorts the queued shapes, keeping the queue order of equal keys.
+    pub fn sort(&mut self) {
+        self.items.sort_by_key(|(key, _)| *key);
+    }
+
+    pub fn shapes(&self) -> impl Iterator<Item = &'a GlShape> + '_ {
+        self.items.iter().map(|(_, shape)| *shape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opaque_keys_group_state_before_depth() {
+        let near = SortKey::opaque(Pass::Opaque, 1, 7, 0, 0, 1.0);
+        let far = SortKey::opaque(Pass::Opaque, 1, 7, 0, 0, 50.0);
+        let other_mesh = SortKey::opaque(Pass::Opaque, 1, 7, 0, 1, 0.5);
+        let other_program = SortKey::opaque(Pass::Opaque, 0, 9, 3, 2, 80.0);
+        let masked = SortKey::opaque(Pass::Masked, 0, 0, 0, 0, 0.0);
+        assert!(near < far);
+        assert!(far < other_mesh);
+        assert!(other_program < near);
+        assert!(far < masked);
+    }
+
+    #[test]
+    fn transparent_keys_sort_farthest_first() {
+        let near = SortKey::transparent(Pass::Transparent, 1.0, 0, 0);
+        let far = SortKey::transparent(Pass::Transparent, 1.5, 5, 5);
+        let opaque = SortKey::opaque(Pass::Opaque, 255, 0, 0, 0, 1000.0);
+        assert!(far < near);
+        assert!(opaque < far);
+        // Shapes behind the camera are drawn last.
+        assert!(near < SortKey::transparent(Pass::Transparent, -1.0, 0, 0));
+    }
+
+    #[test]
+    fn saved_binds_count_skipped_binds() {
+        let stats = RenderStats {
+            draw_calls: 10,
+            program_binds: 2,
+            material_binds: 4,
+            vertex_array_binds: 10,
+        };
+        assert_eq!(stats.saved_binds(), 14);
+    }
+}
@@ -1,115 +1,946 @@
-use std::convert::TryInto;
+use std::ptr;
 
 use nalgebra::Matrix4;
-use wasm_bindgen::prelude::wasm_bindgen;
-use wasm_timer::Instant;
-use web_sys::{WebGl2RenderingContext, WebGlUniformLocation};
+use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue};
+use web_sys::{Performance, WebGl2RenderingContext};
 
 use crate::{
     camera::Camera,
+    deferred::{
+        light_volume, GBuffer, RenderPath, DEFERRED_EMISSION_PROGRAM,
+        DEFERRED_LIGHT_PROGRAM,
+    },
     environment::GlEnvironment,
-    gl::gl_shape::{with_vao, GlShape},
-    math::matrix::mat_to_col_array,
+    error::EngineError,
+    fog::Fog,
+    gl::{
+        gl_shape::{with_vao, GlShape},
+        gl_utils::{get_viewport, set_viewport, Program, ShaderFeatures},
+        render_state::{Blend, CullFace, DepthFunc, RenderState},
+        render_target::{bind_target, RenderTarget},
+        uniform_buffer::{
+            Std140, UniformBuffer, CAMERA_BINDING, LIGHTS_BINDING,
+        },
+    },
+    lights::{Light, MAX_LIGHTS},
+    material::AlphaMode,
+    math::matrix::{mat_to_col_array, mat_to_row_array},
+    oit::OitTargets,
+    post_process::PostProcess,
+    render_queue::{RenderQueue, RenderStats},
+    scene::Scene,
+    shadows::{
+        shadowed_lights, ShadowMaps, ShadowUniforms, MAX_CASCADES,
+        SHADOW_TEXTURE_UNIT,
+    },
+    skybox::SKYBOX_PROGRAM,
+    ssao::{Ssao, SsaoSettings, AMBIENT_OCCLUSION_TEXTURE_UNIT},
+    types::Color,
 };
 
+/// Renders depth only, with `viewProjectionMatrix` in the row-vector
+/// convention of `LinearTransform`.
+pub static DEPTH_PROGRAM: &str = "depth";
+
 #[wasm_bindgen]
 pub struct Renderer {
-    camera: Camera,
-    time_since_last_render: Option<Instant>,
+    /// Cameras drawing into the canvas, the main one first.
+    cameras: Vec<Camera>,
+    /// `now` of the last frame.
+    time_since_last_render: Option<f64>,
+    /// `now` when the renderer was created, the origin of the shader time.
+    start: f64,
+    path: RenderPath,
+    passes: Passes,
+    post_process: PostProcess,
 }
 
 #[wasm_bindgen]
 impl Renderer {
     pub fn new(camera: Camera) -> Renderer {
         Renderer {
-            camera,
+            cameras: vec![camera],
             time_since_last_render: None,
+            start: now(),
+            path: RenderPath::Forward,
+            passes: Passes::default(),
+            post_process: PostProcess::new(),
         }
     }
 }
 
+/// Milliseconds from `performance.now()` of the global scope, which unlike
+/// `window.performance` also exists in workers. `0.0` without a
+/// `performance` object.
+fn now() -> f64 {
+    js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("performance"))
+        .ok()
+        .and_then(|performance| performance.dyn_into::<Performance>().ok())
+        .map_or(0.0, |performance| performance.now())
+}
+
+/// Render pass resources kept between frames, allocated on first use.
+#[derive(Default)]
+struct Passes {
+    shadow_maps: Option<ShadowMaps>,
+    oit_targets: Option<OitTargets>,
+    ssao: Option<Ssao>,
+    g_buffer: Option<GBuffer>,
+    camera_buffer: Option<UniformBuffer>,
+    lights_buffer: Option<UniformBuffer>,
+    /// Lights block of the light the deferred path is adding.
+    deferred_light_buffer: Option<UniformBuffer>,
+    /// Seconds since the renderer was created.
+    time: f32,
+    /// Pixels the view being rendered draws into, in its target and in
+    /// the intermediate targets.
+    viewport: [i32; 4],
+}
+
+/// Per-frame values shared by every program.
+struct FrameUniforms {
+    view_projection_matrix: [f32; 16],
+    /// View matrix in the row-vector convention of `LinearTransform`.
+    view_transform: Matrix4<f32>,
+    shadows: ShadowUniforms,
+    ambient_occlusion: bool,
+}
+
 impl Renderer {
-    pub fn render(&mut self, env: &GlEnvironment, shapes: &Vec<GlShape>) {
-        let elapsed = self
-            .time_since_last_render
-            .unwrap_or(Instant::now())
-            .elapsed()
-            .as_secs();
-        self.camera.transform.update_matrix();
-        self.draw(env, &self.camera.transform.get_matrix(), shapes)
-            .unwrap();
-        self.time_since_last_render = Some(Instant::now());
+    pub fn render(
+        &mut self,
+        env: &GlEnvironment,
+        scene: &Scene,
+    ) -> Result<(), EngineError> {
+        let elapsed =
+            (now() - self.time_since_last_render.unwrap_or_else(now)) / 1000.0;
+        for camera in &mut self.cameras {
+            camera.transform.update_matrix();
+        }
+        *env.render_stats.borrow_mut() = RenderStats::default();
+        self.passes.time = ((now() - self.start) / 1000.0) as f32;
+        for view in &scene.offscreen_views {
+            self.passes.render_view(
+                env,
+                scene,
+                &view.camera,
+                Some(&view.target),
+                self.path,
+            )?;
+        }
+        let target = self.post_process.prepare(env)?;
+        let mut cameras: Vec<&Camera> = self.cameras.iter().collect();
+        cameras.sort_by_key(|camera| camera.order);
+        for camera in cameras {
+            self.passes
+                .render_view(env, scene, camera, target, self.path)?;
+        }
+        self.post_process.apply(env)?;
+        self.time_since_last_render = Some(now());
+        Ok(())
+    }
+
+    /// Drops the pass resources, reallocated on the next frame, after the
+    /// context was restored.
+    pub fn restore(&mut self, env: &GlEnvironment) -> Result<(), EngineError> {
+        self.passes = Passes::default();
+        self.post_process.restore(env)
+    }
+
+    /// Adds a camera drawing into the canvas and returns its index.
+    pub fn add_camera(&mut self, camera: Camera) -> usize {
+        self.cameras.push(camera);
+        self.cameras.len() - 1
+    }
+
+    /// Removes camera `index`, shifting the indices of the next cameras.
+    pub fn remove_camera(&mut self, index: usize) -> Option<Camera> {
+        if index < self.cameras.len() {
+            Some(self.cameras.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn camera_mut(&mut self, index: usize) -> Option<&mut Camera> {
+        self.cameras.get_mut(index)
+    }
+
+    pub fn post_process_mut(&mut self) -> &mut PostProcess {
+        &mut self.post_process
+    }
+
+    pub fn set_path(&mut self, path: RenderPath) {
+        self.path = path;
+    }
+}
+
+impl Passes {
+    /// Renders `scene` seen from `camera` into its viewport of `target`, or
+    /// of the canvas when `None`.
+    fn render_view(
+        &mut self,
+        env: &GlEnvironment,
+        scene: &Scene,
+        camera: &Camera,
+        target: Option<&RenderTarget>,
+        path: RenderPath,
+    ) -> Result<(), EngineError> {
+        let context = &env.context;
+        let (width, height) = target_size(context, target);
+        let (viewport, clip) = camera.pixel_rects(width, height);
+        if clip.width == 0 || clip.height == 0 {
+            return Ok(());
+        }
+        let shadows = self.render_shadows(env, scene, camera)?;
+        let previous_viewport = get_viewport(context);
+        self.viewport =
+            [viewport.x, viewport.y, viewport.width, viewport.height];
+        bind_target(context, target);
+        set_viewport(context, self.viewport);
+        // Clears ignore the viewport, the scissor keeps them in it.
+        env.state_cache.borrow_mut().set_clip(Some(clip));
+        let result = self.draw(env, scene, camera, target, shadows, path);
+        env.state_cache.borrow_mut().set_clip(None);
+        if let Some(target) = target {
+            target.resolve(env);
+            context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+            target.update_mipmaps(context);
+        }
+        set_viewport(context, previous_viewport);
+        result
     }
 
+    /// Renders the shadow maps of the shadowed lights of `scene`, allocating
+    /// them on first use.
+    fn render_shadows(
+        &mut self,
+        env: &GlEnvironment,
+        scene: &Scene,
+        camera: &Camera,
+    ) -> Result<ShadowUniforms, EngineError> {
+        let lights = shadowed_lights(scene);
+        if lights.is_empty() {
+            return Ok(ShadowUniforms::default());
+        }
+        let size = scene.shadows.map_size;
+        let layers = (lights.len() * MAX_CASCADES) as u32;
+        let shadow_maps = match self.shadow_maps.take() {
+            Some(maps) if maps.size == size && maps.layers >= layers => maps,
+            previous => {
+                drop(previous);
+                ShadowMaps::new(env, size, layers)?
+            }
+        };
+        let uniforms = shadow_maps.render(env, scene, camera, &lights);
+        self.shadow_maps = Some(shadow_maps);
+        uniforms
+    }
+
+    /// Draws into the bound `target`.
     fn draw(
-        &self,
+        &mut self,
         env: &GlEnvironment,
-        view_matrix: &Matrix4<f32>,
-        shapes: &Vec<GlShape>,
-    ) -> Result<(), String> {
-        env.context.clear_color(0.0, 0.0, 0.0, 1.0);
-        env.context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
-        let model_matrix_loc =
-            env.program.uniform_locations.get("modelMatrix").unwrap();
-
-        set_uniform(
-            env,
-            "viewMatrix",
-            &mat_to_col_array(&view_matrix.try_inverse().unwrap()),
-        )
-        .unwrap();
+        scene: &Scene,
+        camera: &Camera,
+        target: Option<&RenderTarget>,
+        shadows: ShadowUniforms,
+        path: RenderPath,
+    ) -> Result<(), EngineError> {
+        let view_matrix =
+            camera.transform.get_matrix().try_inverse().ok_or_else(|| {
+                EngineError::Internal(String::from(
+                    "Camera matrix is not invertible.",
+                ))
+            })?;
+        let view_projection_matrix =
+            view_matrix * camera.get_projection_matrix();
+        let inverse_view_projection_matrix =
+            view_projection_matrix.try_inverse().ok_or_else(|| {
+                EngineError::Internal(String::from(
+                    "Projection matrix is not invertible.",
+                ))
+            })?;
+        let settings = &scene.ambient_occlusion;
 
-        set_uniform(
+        // Each row of the row-vector matrices is a column of the shader's.
+        let mut camera_block = Std140::new();
+        camera_block
+            .mat4(&mat_to_row_array(&view_matrix))
+            .mat4(&mat_to_row_array(camera.get_projection_matrix()))
+            .mat4(&mat_to_row_array(&view_projection_matrix))
+            .mat4(&mat_to_row_array(&inverse_view_projection_matrix))
+            .vec3(camera.transform.get_translation().to_array())
+            .float(self.time)
+            .vec4(self.viewport.map(|value| value as f32));
+        upload_uniforms(
             env,
-            "projectionMatrix",
-            &mat_to_col_array(&self.camera.get_projection_matrix()),
-        )
-        .unwrap();
+            &mut self.camera_buffer,
+            &camera_block,
+            CAMERA_BINDING,
+        )?;
+        let lights = &scene.lights[..scene.lights.len().min(MAX_LIGHTS)];
+        upload_uniforms(
+            env,
+            &mut self.lights_buffer,
+            &lights_block(scene.ambient_light, lights),
+            LIGHTS_BINDING,
+        )?;
+
+        let mut frame = FrameUniforms {
+            view_projection_matrix: mat_to_col_array(&view_projection_matrix),
+            view_transform: view_matrix,
+            shadows,
+            ambient_occlusion: settings.enabled && env.color_buffer_float,
+        };
 
+        // Only visible shapes on the layers of the camera are drawn. Shapes
+        // sampling the target can't be drawn into it.
+        let shapes = scene.drawn_shapes().filter(|shape| {
+            shape.is_drawn_on(camera.layer_mask)
+                && target.is_none_or(|target| {
+                    !target
+                        .textures()
+                        .any(|texture| shape.material.uses_texture(texture))
+                })
+        });
+        // Transparent shapes are blended over the opaque ones, either in
+        // any order with weighted blended OIT or sorted farthest first.
+        // Without float render targets, OIT shapes are sorted too.
+        let mut opaque = Vec::new();
+        let mut weighted = Vec::new();
+        let mut sorted = Vec::new();
         for shape in shapes {
-            draw_shape(&env.context, shape, model_matrix_loc);
+            if !shape.alpha_mode().is_transparent() {
+                opaque.push(shape);
+            } else if shape.material.order_independent && env.color_buffer_float
+            {
+                weighted.push(shape);
+            } else {
+                sorted.push(shape);
+            }
+        }
+
+        if frame.ambient_occlusion {
+            let result =
+                self.render_ambient_occlusion(env, settings, target, &opaque);
+            bind_target(&env.context, target);
+            set_viewport(&env.context, self.viewport);
+            result?;
+        }
+
+        if let Some(shadow_maps) = &self.shadow_maps {
+            shadow_maps.bind(&env.context, SHADOW_TEXTURE_UNIT);
+        }
+        match &self.ssao {
+            Some(ssao) if frame.ambient_occlusion => {
+                ssao.bind(&env.context, AMBIENT_OCCLUSION_TEXTURE_UNIT)
+            }
+            _ => frame.ambient_occlusion = false,
+        }
+
+        let deferred = path == RenderPath::Deferred && env.color_buffer_float;
+        if deferred {
+            let result =
+                self.fill_g_buffer(env, scene, target, &opaque, &frame);
+            bind_target(&env.context, target);
+            set_viewport(&env.context, self.viewport);
+            result?;
+        }
+
+        // Clears are masked by the depth and color write state.
+        env.state_cache
+            .borrow_mut()
+            .apply(&env.context, &RenderState::default());
+        let mut clear_mask = 0;
+        if camera.clear_color {
+            let background = &scene.background_color;
+            env.context.clear_color(
+                background.r,
+                background.g,
+                background.b,
+                1.0,
+            );
+            clear_mask |= WebGl2RenderingContext::COLOR_BUFFER_BIT;
+        }
+        if camera.clear_depth {
+            clear_mask |= WebGl2RenderingContext::DEPTH_BUFFER_BIT;
+        }
+        if clear_mask != 0 {
+            env.context.clear(clear_mask);
+        }
+
+        match &scene.skybox {
+            Some(skybox) if camera.clear_color => {
+                let program = env.program(SKYBOX_PROGRAM)?;
+                env.context.use_program(Some(&program.gl_program));
+                set_fog_uniforms(&env.context, program, &scene.fog);
+                skybox.draw(env, program);
+            }
+            _ => {}
+        }
+
+        if deferred {
+            self.shade_g_buffer(env, scene, &frame)?;
+        } else {
+            draw_shapes(
+                env,
+                &opaque,
+                &frame,
+                scene,
+                ShaderFeatures::default(),
+                false,
+            )?;
+        }
+        if !weighted.is_empty() {
+            self.draw_weighted_blended(
+                env, target, &opaque, &weighted, &frame, scene,
+            )?;
+        }
+        draw_shapes(
+            env,
+            &sorted,
+            &frame,
+            scene,
+            ShaderFeatures::default(),
+            false,
+        )?;
+
+        match &self.ssao {
+            Some(ssao) if frame.ambient_occlusion && settings.debug_view => {
+                ssao.draw_debug(env)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Draws `opaque` into the G-buffer, sized like `target` and allocated
+    /// on first use.
+    fn fill_g_buffer(
+        &mut self,
+        env: &GlEnvironment,
+        scene: &Scene,
+        target: Option<&RenderTarget>,
+        opaque: &[&GlShape],
+        frame: &FrameUniforms,
+    ) -> Result<(), EngineError> {
+        let (width, height) = target_size(&env.context, target);
+        let g_buffer = match self.g_buffer.take() {
+            Some(g_buffer)
+                if g_buffer.target.width == width
+                    && g_buffer.target.height == height =>
+            {
+                g_buffer
+            }
+            previous => {
+                drop(previous);
+                GBuffer::new(env, width, height)?
+            }
+        };
+        g_buffer.begin(env, self.viewport);
+        let features = ShaderFeatures {
+            deferred: true,
+            ..ShaderFeatures::default()
+        };
+        let result = draw_shapes(env, opaque, frame, scene, features, false);
+        self.g_buffer = Some(g_buffer);
+        result
+    }
+
+    /// Shades the G-buffer into the bound target, also writing its depth so
+    /// that transparent shapes are occluded.
+    fn shade_g_buffer(
+        &mut self,
+        env: &GlEnvironment,
+        scene: &Scene,
+        frame: &FrameUniforms,
+    ) -> Result<(), EngineError> {
+        let context = &env.context;
+        let g_buffer = self.g_buffer.as_ref().ok_or_else(|| {
+            EngineError::Internal(String::from("G-buffer is missing."))
+        })?;
+
+        let program = env.program(DEFERRED_EMISSION_PROGRAM)?;
+        env.state_cache.borrow_mut().apply(
+            context,
+            &RenderState {
+                depth_func: DepthFunc::Always,
+                cull_face: None,
+                ..RenderState::default()
+            },
+        );
+        context.use_program(Some(&program.gl_program));
+        g_buffer.bind_textures(context, program)?;
+        set_fog_uniforms(context, program, &scene.fog);
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+
+        // Each light is added over the back faces of its volume, which
+        // cover its range once wherever the camera is.
+        let program = env.program(DEFERRED_LIGHT_PROGRAM)?;
+        context.use_program(Some(&program.gl_program));
+        g_buffer.bind_textures(context, program)?;
+        set_shadow_uniforms(context, program, &frame.shadows);
+        set_fog_uniforms(context, program, &scene.fog);
+        context.uniform1i(program.uniform_location("receiveShadows"), 1);
+        let volume_state = RenderState {
+            depth_test: false,
+            depth_write: false,
+            cull_face: Some(CullFace::Front),
+            blend: Some(Blend::additive()),
+            ..RenderState::default()
+        };
+        for (i, light) in scene.lights.iter().enumerate() {
+            // Ambient light was added with the emission.
+            upload_uniforms(
+                env,
+                &mut self.deferred_light_buffer,
+                &lights_block(
+                    Color::rgb(0.0, 0.0, 0.0),
+                    std::slice::from_ref(light),
+                ),
+                LIGHTS_BINDING,
+            )?;
+            let mut light_shadows = [-1; MAX_LIGHTS];
+            light_shadows[0] =
+                frame.shadows.light_shadows.get(i).copied().unwrap_or(-1);
+            context.uniform1iv_with_i32_array(
+                program.uniform_location("lightShadows"),
+                &light_shadows,
+            );
+            let volume = light_volume(light);
+            context.uniform1i(
+                program.uniform_location("lightVolume"),
+                volume.is_some() as i32,
+            );
+            match volume {
+                Some((center, radius)) => {
+                    context.uniform3fv_with_f32_array(
+                        program.uniform_location("volumeCenter"),
+                        &center.to_array(),
+                    );
+                    context.uniform1f(
+                        program.uniform_location("volumeRadius"),
+                        radius,
+                    );
+                    env.state_cache.borrow_mut().apply(context, &volume_state);
+                    context.draw_arrays(
+                        WebGl2RenderingContext::TRIANGLES,
+                        0,
+                        36,
+                    );
+                }
+                None => {
+                    env.state_cache.borrow_mut().apply(
+                        context,
+                        &RenderState {
+                            cull_face: None,
+                            ..volume_state
+                        },
+                    );
+                    context.draw_arrays(
+                        WebGl2RenderingContext::TRIANGLES,
+                        0,
+                        3,
+                    );
+                }
+            }
+        }
+        g_buffer.unbind_textures(context);
+        if let Some(lights_buffer) = &self.lights_buffer {
+            lights_buffer.bind(context, LIGHTS_BINDING);
         }
         Ok(())
     }
+
+    /// Renders the ambient occlusion of `opaque`, sized like `target`,
+    /// allocating the SSAO targets on first use.
+    fn render_ambient_occlusion(
+        &mut self,
+        env: &GlEnvironment,
+        settings: &SsaoSettings,
+        target: Option<&RenderTarget>,
+        opaque: &[&GlShape],
+    ) -> Result<(), EngineError> {
+        let (width, height) = target_size(&env.context, target);
+        let mut ssao = match self.ssao.take() {
+            Some(ssao) if ssao.width() == width && ssao.height() == height => {
+                ssao
+            }
+            previous => {
+                drop(previous);
+                Ssao::new(env, width, height)?
+            }
+        };
+        let result = ssao.render(env, settings, opaque, self.viewport);
+        self.ssao = Some(ssao);
+        result
+    }
+
+    /// Accumulates `transparent` in the OIT targets, occluded by the depth
+    /// of `opaque`, and composites the result over `target`.
+    fn draw_weighted_blended(
+        &mut self,
+        env: &GlEnvironment,
+        target: Option<&RenderTarget>,
+        opaque: &[&GlShape],
+        transparent: &[&GlShape],
+        frame: &FrameUniforms,
+        scene: &Scene,
+    ) -> Result<(), EngineError> {
+        let context = &env.context;
+        let (width, height) = target_size(context, target);
+        let targets = match self.oit_targets.take() {
+            Some(targets)
+                if targets.target.width == width
+                    && targets.target.height == height =>
+            {
+                targets
+            }
+            previous => {
+                drop(previous);
+                OitTargets::new(env, width, height)?
+            }
+        };
+
+        targets.begin(env, self.viewport);
+        let depth_program = env.program(DEPTH_PROGRAM)?;
+        env.state_cache.borrow_mut().apply(
+            context,
+            &RenderState {
+                color_mask: [false; 4],
+                ..RenderState::default()
+            },
+        );
+        context.use_program(Some(&depth_program.gl_program));
+        context.uniform_matrix4fv_with_f32_array(
+            depth_program.uniform_location("viewProjectionMatrix"),
+            true,
+            &frame.view_projection_matrix,
+        );
+        let (masked, unmasked): (Vec<&GlShape>, Vec<&GlShape>) =
+            opaque.iter().partition(|shape| {
+                matches!(shape.alpha_mode(), AlphaMode::Mask(_))
+            });
+        for shape in unmasked {
+            draw_shape(context, shape, depth_program);
+        }
+        // Masked shapes need their material to discard masked out
+        // fragments.
+        let masked_depth = draw_shapes(
+            env,
+            &masked,
+            frame,
+            scene,
+            ShaderFeatures::default(),
+            true,
+        );
+
+        let features = ShaderFeatures {
+            weighted_blended_oit: true,
+            ..ShaderFeatures::default()
+        };
+        let result = masked_depth
+            .and_then(|_| {
+                draw_shapes(env, transparent, frame, scene, features, false)
+            })
+            .and_then(|_| targets.composite(env, target, self.viewport));
+        bind_target(context, target);
+        self.oit_targets = Some(targets);
+        result
+    }
+}
+
+/// Size of `target` in pixels, or of the canvas when `None`.
+fn target_size(
+    context: &WebGl2RenderingContext,
+    target: Option<&RenderTarget>,
+) -> (u32, u32) {
+    match target {
+        Some(target) => (target.width, target.height),
+        None => (
+            context.drawing_buffer_width() as u32,
+            context.drawing_buffer_height() as u32,
+        ),
+    }
 }
 
-fn set_uniform(
+/// Draws `shapes` sorted by a render queue, only binding the program,
+/// material and vertex array of a draw when they differ from the previous
+/// draw's. With `depth_only`, colors are not written.
+fn draw_shapes(
     env: &GlEnvironment,
-    name: &str,
-    data: &[f32],
-) -> Result<(), String> {
-    if !env.program.uniform_locations.contains_key(name) {
-        let error = format!("Could not find {} location.", name);
-        return Err(error);
-    }
-    env.context.uniform_matrix4fv_with_f32_array(
-        env.program.uniform_locations.get(name),
-        true,
-        data,
+    shapes: &[&GlShape],
+    frame: &FrameUniforms,
+    scene: &Scene,
+    features: ShaderFeatures,
+    depth_only: bool,
+) -> Result<(), EngineError> {
+    let mut queue = RenderQueue::new();
+    for shape in shapes {
+        queue.push(shape, &frame.view_transform);
+    }
+    queue.sort();
+
+    let context = &env.context;
+    let environment_map =
+        scene.skybox.as_ref().map(|skybox| skybox.cubemap.as_ref());
+    let mut stats = env.render_stats.borrow_mut();
+    let mut current_program: Option<&str> = None;
+    let mut current_material = None;
+    let mut current_vao = None;
+    let mut variant = None;
+    for shape in queue.shapes() {
+        let program_name = shape.material.program_name();
+        if features != ShaderFeatures::default()
+            && current_program != Some(program_name)
+        {
+            variant = Some(env.program_variant(program_name, features)?);
+        }
+        let program = match &variant {
+            Some(variant) => variant,
+            None => env.program(program_name)?,
+        };
+        if current_program != Some(program_name) {
+            context.use_program(Some(&program.gl_program));
+            set_frame_uniforms(context, program, frame, scene);
+            current_program = Some(program_name);
+            current_material = None;
+            stats.program_binds += 1;
+        }
+        let mut state = shape.material.render_state_for(shape.alpha_mode());
+        if features.weighted_blended_oit {
+            state.blend = Some(OitTargets::blend());
+        }
+        if depth_only {
+            state.color_mask = [false; 4];
+        }
+        env.state_cache.borrow_mut().apply(context, &state);
+        if current_material != Some(&shape.material) {
+            shape.material.apply(context, program, environment_map);
+            current_material = Some(&shape.material);
+            stats.material_binds += 1;
+        }
+        let vao = shape.vertex_array();
+        if !current_vao.is_some_and(|current| ptr::eq(current, vao)) {
+            context.bind_vertex_array(Some(vao));
+            current_vao = Some(vao);
+            stats.vertex_array_binds += 1;
+        }
+        set_shape_uniforms(context, shape, program);
+        draw_bound_shape(context, shape);
+        stats.draw_calls += 1;
+    }
+    context.bind_vertex_array(None);
+    Ok(())
+}
+
+fn set_frame_uniforms(
+    context: &WebGl2RenderingContext,
+    program: &Program,
+    frame: &FrameUniforms,
+    scene: &Scene,
+) {
+    set_shadow_uniforms(context, program, &frame.shadows);
+    set_fog_uniforms(context, program, &scene.fog);
+    context.uniform1i(
+        program.uniform_location("ambientOcclusionMap"),
+        AMBIENT_OCCLUSION_TEXTURE_UNIT as i32,
     );
+    context.uniform1i(
+        program.uniform_location("hasAmbientOcclusionMap"),
+        frame.ambient_occlusion as i32,
+    );
+}
+
+/// Creates the buffer of `slot` on first use and uploads `data` to it,
+/// binding it to `binding`.
+fn upload_uniforms(
+    env: &GlEnvironment,
+    slot: &mut Option<UniformBuffer>,
+    data: &Std140,
+    binding: u32,
+) -> Result<(), EngineError> {
+    let buffer = match slot {
+        Some(buffer) => buffer,
+        None => slot.insert(UniformBuffer::new(&env.resources)?),
+    };
+    buffer.upload(&env.context, data, binding);
     Ok(())
 }
 
-fn draw_shape(
+/// Lights block of the lights shader chunk holding `lights`, at most
+/// `MAX_LIGHTS` of them.
+fn lights_block(ambient_light: Color, lights: &[Light]) -> Std140 {
+    let lights = &lights[..lights.len().min(MAX_LIGHTS)];
+    let light = |i: usize| lights.get(i);
+    let mut block = Std140::new();
+    block
+        .vec3(ambient_light.to_rgb_array())
+        .int(lights.len() as i32)
+        .array(MAX_LIGHTS, |block, i| {
+            block.int(light(i).map_or(0, |light| light.kind() as i32));
+        })
+        .array(MAX_LIGHTS, |block, i| {
+            block.vec3(light(i).map_or([0.0; 3], |l| l.position().to_array()));
+        })
+        .array(MAX_LIGHTS, |block, i| {
+            block.vec3(light(i).map_or([0.0; 3], |l| l.direction().to_array()));
+        })
+        .array(MAX_LIGHTS, |block, i| {
+            block.vec3(light(i).map_or([0.0; 3], |light| {
+                let color = light.color();
+                [
+                    color.r * light.intensity(),
+                    color.g * light.intensity(),
+                    color.b * light.intensity(),
+                ]
+            }));
+        })
+        .array(MAX_LIGHTS, |block, i| {
+            block.float(light(i).map_or(0.0, |light| light.range()));
+        })
+        .array(MAX_LIGHTS, |block, i| {
+            let (inner, outer) =
+                light(i).map_or((0.0, 0.0), |light| light.cone_cosines());
+            block.vec2([inner, outer]);
+        });
+    block
+}
+
+fn set_shadow_uniforms(
+    context: &WebGl2RenderingContext,
+    program: &Program,
+    shadows: &ShadowUniforms,
+) {
+    context.uniform1i(
+        program.uniform_location("shadowMap"),
+        SHADOW_TEXTURE_UNIT as i32,
+    );
+    context.uniform1iv_with_i32_array(
+        program.uniform_location("lightShadows"),
+        &shadows.light_shadows,
+    );
+    context.uniform_matrix4fv_with_f32_array(
+        program.uniform_location("shadowMatrices"),
+        true,
+        &shadows.matrices,
+    );
+    context.uniform4fv_with_f32_array(
+        program.uniform_location("shadowCascadeSplits"),
+        &shadows.cascade_splits,
+    );
+    context.uniform4fv_with_f32_array(
+        program.uniform_location("shadowParameters"),
+        &shadows.parameters,
+    );
+}
+
+fn set_fog_uniforms(
+    context: &WebGl2RenderingContext,
+    program: &Program,
+    fog: &Fog,
+) {
+    let (mode, parameters) = fog
+        .distance
+        .map_or((0, [0.0; 2]), |distance| distance.shader_values());
+    context.uniform1i(program.uniform_location("fogMode"), mode);
+    context.uniform3fv_with_f32_array(
+        program.uniform_location("fogColor"),
+        &fog.color.to_rgb_array(),
+    );
+    context.uniform2fv_with_f32_array(
+        program.uniform_location("fogParameters"),
+        &parameters,
+    );
+    context.uniform1i(
+        program.uniform_location("hasHeightFog"),
+        fog.height.is_some() as i32,
+    );
+    if let Some(height) = &fog.height {
+        context.uniform3f(
+            program.uniform_location("heightFog"),
+            height.height,
+            height.density,
+            height.falloff,
+        );
+    }
+    context.uniform1f(
+        program.uniform_location("fogSkyDistance"),
+        fog.sky_distance,
+    );
+}
+
+pub fn draw_shape(
     context: &WebGl2RenderingContext,
     shape: &GlShape,
-    model_matrix_loc: &WebGlUniformLocation,
+    program: &Program,
+) {
+    if shape.instances.as_ref().is_some_and(|i| i.is_empty()) {
+        return;
+    }
+    set_shape_uniforms(context, shape, program);
+    with_vao(context, shape.vertex_array(), |_| {
+        draw_bound_shape(context, shape)
+    });
+}
+
+fn set_shape_uniforms(
+    context: &WebGl2RenderingContext,
+    shape: &GlShape,
+    program: &Program,
 ) {
     let transformation_matrix = shape.transform.get_matrix();
 
     context.uniform_matrix4fv_with_f32_array(
-        Some(model_matrix_loc),
+        program.uniform_location("modelMatrix"),
         true,
-        &mat_to_col_array(&transformation_matrix),
+        &mat_to_col_array(transformation_matrix),
+    );
+    context.uniform1i(
+        program.uniform_location("receiveShadows"),
+        shape.receive_shadows as i32,
     );
+    let alpha_mode = shape.alpha_mode();
+    context.uniform1i(
+        program.uniform_location("alphaMode"),
+        alpha_mode.shader_value(),
+    );
+    context.uniform1f(
+        program.uniform_location("alphaCutoff"),
+        alpha_mode.cutoff(),
+    );
+}
 
-    with_vao(context, &shape.vao, |_| match &shape.element_array {
-        Some(array) => context.draw_elements_with_i32(
-            shape.mode,
-            array.len().try_into().unwrap(),
+/// Draws `shape`, whose vertex array must be bound.
+fn draw_bound_shape(context: &WebGl2RenderingContext, shape: &GlShape) {
+    let instance_count = shape
+        .instances
+        .as_ref()
+        .map(|instances| instances.len() as i32);
+    match (&shape.mesh.element_array, instance_count) {
+        (Some(array), None) => context.draw_elements_with_i32(
+            shape.mesh.mode,
+            array.len() as i32,
             WebGl2RenderingContext::UNSIGNED_INT,
             0,
         ),
-        None => context.draw_arrays(shape.mode, 0, shape.vertices_count as i32),
-    });
+        (Some(array), Some(count)) => context.draw_elements_instanced_with_i32(
+            shape.mesh.mode,
+            array.len() as i32,
+            WebGl2RenderingContext::UNSIGNED_INT,
+            0,
+            count,
+        ),
+        (None, None) => context.draw_arrays(
+            shape.mesh.mode,
+            0,
+            shape.mesh.vertices_count as i32,
+        ),
+        (None, Some(count)) => context.draw_arrays_instanced(
+            shape.mesh.mode,
+            0,
+            shape.mesh.vertices_count as i32,
+            count,
+        ),
+    }
 }
@@ -0,0 +1,138 @@
+use crate::{
+    camera::Camera,
+    context_loss::Restorer,
+    environment::GlEnvironment,
+    error::EngineError,
+    fog::Fog,
+    gl::{gl_shape::GlShape, render_target::RenderTarget},
+    lights::{Light, MAX_LIGHTS},
+    shadows::ShadowSettings,
+    skybox::Skybox,
+    ssao::SsaoSettings,
+    static_batch::{is_batched, StaticBatches},
+    types::Color,
+};
+
+/// Everything that gets drawn: shapes, the lights shading them and the
+/// background.
+pub struct Scene {
+    pub shapes: Vec<GlShape>,
+    pub lights: Vec<Light>,
+    pub ambient_light: Color,
+    /// Color the frame is cleared with, visible where there is no skybox.
+    pub background_color: Color,
+    pub skybox: Option<Skybox>,
+    pub shadows: ShadowSettings,
+    pub ambient_occlusion: SsaoSettings,
+    pub fog: Fog,
+    /// Views rendered to textures before the frame, in order.
+    pub offscreen_views: Vec<OffscreenView>,
+    /// Merged static shapes, drawn instead of them. Must be invalidated
+    /// when a static shape changes.
+    pub static_batches: StaticBatches,
+}
+
+/// A camera whose view of the scene is rendered into a target, whose
+/// textures other materials can show.
+pub struct OffscreenView {
+    pub camera: Camera,
+    pub target: RenderTarget,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            shapes: Vec::new(),
+            lights: Vec::new(),
+            ambient_light: Color::rgb(0.1, 0.1, 0.1),
+            background_color: Color::rgb(0.0, 0.0, 0.0),
+            skybox: None,
+            shadows: ShadowSettings::default(),
+            ambient_occlusion: SsaoSettings::default(),
+            fog: Fog::default(),
+            offscreen_views: Vec::new(),
+            static_batches: StaticBatches::new(),
+        }
+    }
+
+    pub fn add_shape(&mut self, shape: GlShape) -> usize {
+        if shape.is_static {
+            self.static_batches.invalidate();
+        }
+        self.shapes.push(shape);
+        self.shapes.len() - 1
+    }
+
+    /// Removes shape `index`, shifting the indices of the next shapes.
+    pub fn remove_shape(&mut self, index: usize) -> Option<GlShape> {
+        if index >= self.shapes.len() {
+            return None;
+        }
+        let shape = self.shapes.remove(index);
+        if shape.is_static {
+            self.static_batches.invalidate();
+        }
+        Some(shape)
+    }
+
+    /// Shapes to draw: the static batches and the shapes outside of them.
+    pub fn drawn_shapes(&self) -> impl Iterator<Item = &GlShape> {
+        self.shapes
+            .iter()
+            .filter(|shape| !is_batched(shape))
+            .chain(self.static_batches.shapes())
+    }
+
+    /// Rebuilds the static batches if static shapes changed. Shapes must be
+    /// prepared for render.
+    pub fn update_static_batches(
+        &mut self,
+        env: &GlEnvironment,
+    ) -> Result<(), EngineError> {
+        self.static_batches.update(env, &self.shapes)
+    }
+
+    /// Recreates the GPU objects of the scene from their CPU-side data
+    /// after the context was restored.
+    pub fn restore(&mut self, env: &GlEnvironment) -> Result<(), EngineError> {
+        let mut restorer = Restorer::new();
+        for view in &mut self.offscreen_views {
+            let target = RenderTarget::new(
+                env,
+                view.target.width,
+                view.target.height,
+                view.target.settings(),
+            )?;
+            restorer.replace_target(&view.target, &target);
+            view.target = target;
+        }
+        for shape in &mut self.shapes {
+            let mesh = restorer.mesh(env, &shape.mesh)?;
+            shape.restore(env, mesh)?;
+            restorer.material(env, &mut shape.material)?;
+        }
+        if let Some(skybox) = &mut self.skybox {
+            let cubemap = restorer.cubemap(env, &skybox.cubemap)?;
+            *skybox = Skybox::new(env, cubemap, skybox.intensity)?;
+        }
+        self.static_batches.invalidate();
+        Ok(())
+    }
+
+    pub fn add_light(&mut self, light: Light) -> usize {
+        if self.lights.len() >= MAX_LIGHTS {
+            log!(
+                "Scene has more than {} lights, extra lights are ignored.",
+                MAX_LIGHTS
+            );
+        }
+        self.lights.push(light);
+        self.lights.len() - 1
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
+}
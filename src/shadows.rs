@@ -0,0 +1,469 @@
+use nalgebra::{Matrix4, Point3, Vector3};
+use web_sys::{WebGl2RenderingContext, WebGlFramebuffer, WebGlTexture};
+
+use crate::{
+    camera::Camera,
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        gl_shape::GlShape,
+        gl_utils::{get_viewport, set_viewport, Program},
+        render_state::RenderState,
+        resources::GlResource,
+        texture::TextureFormat,
+    },
+    lights::{Light, LightKind, MAX_LIGHTS},
+    math::{bounds::Aabb, matrix::mat_to_col_array, utils::degree_to_radian},
+    renderer::{draw_shape, DEPTH_PROGRAM},
+    scene::Scene,
+    types::Vec3,
+};
+
+/// Maximum number of lights with a shadow map. Extra shadowed lights are
+/// rendered without shadows.
+pub const MAX_SHADOWED_LIGHTS: usize = 2;
+/// Maximum number of cascades of a directional light.
+pub const MAX_CASCADES: usize = 4;
+pub const SHADOW_TEXTURE_UNIT: u32 = 6;
+
+/// Shadow settings shared by every light of a scene.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of each shadow map, in texels.
+    pub map_size: u32,
+    /// View depth up to which directional lights cast shadows.
+    pub max_distance: f32,
+    /// Blend between uniform (0) and logarithmic (1) cascade splits.
+    pub cascade_split_lambda: f32,
+    /// State of the depth passes, e.g. to add a polygon offset or cull
+    /// front faces.
+    pub render_state: RenderState,
+    /// Layers of the shapes casting shadows, whether or not the camera
+    /// draws them.
+    pub layer_mask: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            map_size: 1024,
+            max_distance: 50.0,
+            cascade_split_lambda: 0.75,
+            render_state: RenderState::default(),
+            layer_mask: u32::MAX,
+        }
+    }
+}
+
+/// Values of the shadow uniforms declared by the shadows shader chunk.
+pub struct ShadowUniforms {
+    pub light_shadows: [i32; MAX_LIGHTS],
+    pub matrices: [f32; MAX_SHADOWED_LIGHTS * MAX_CASCADES * 16],
+    pub cascade_splits: [f32; MAX_SHADOWED_LIGHTS * 4],
+    pub parameters: [f32; MAX_SHADOWED_LIGHTS * 4],
+}
+
+impl Default for ShadowUniforms {
+    fn default() -> Self {
+        ShadowUniforms {
+            light_shadows: [-1; MAX_LIGHTS],
+            matrices: [0.0; MAX_SHADOWED_LIGHTS * MAX_CASCADES * 16],
+            cascade_splits: [0.0; MAX_SHADOWED_LIGHTS * 4],
+            parameters: [0.0; MAX_SHADOWED_LIGHTS * 4],
+        }
+    }
+}
+
+/// Depth texture array holding every cascade of every shadowed light, at
+/// layer `shadow * MAX_CASCADES + cascade`.
+pub struct ShadowMaps {
+    texture: GlResource<WebGlTexture>,
+    framebuffer: GlResource<WebGlFramebuffer>,
+    pub size: u32,
+    pub layers: u32,
+}
+
+impl ShadowMaps {
+    pub fn new(
+        env: &GlEnvironment,
+        size: u32,
+        layers: u32,
+    ) -> Result<ShadowMaps, EngineError> {
+        let context = &env.context;
+        let target = WebGl2RenderingContext::TEXTURE_2D_ARRAY;
+        let mut texture = env.resources.create::<WebGlTexture>()?;
+        texture.set_bytes(
+            (size * size * layers) as usize
+                * TextureFormat::Depth24.bytes_per_pixel(),
+        );
+        context.bind_texture(target, Some(&texture));
+        context.tex_storage_3d(
+            target,
+            1,
+            WebGl2RenderingContext::DEPTH_COMPONENT24,
+            size as i32,
+            size as i32,
+            layers as i32,
+        );
+        // Comparison sampling filters the result of the depth test rather
+        // than the depths.
+        let parameters = [
+            (
+                WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+                WebGl2RenderingContext::LINEAR,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_S,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_T,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_COMPARE_MODE,
+                WebGl2RenderingContext::COMPARE_REF_TO_TEXTURE,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_COMPARE_FUNC,
+                WebGl2RenderingContext::LEQUAL,
+            ),
+        ];
+        for (name, value) in parameters {
+            context.tex_parameteri(target, name, value as i32);
+        }
+        context.bind_texture(target, None);
+
+        let framebuffer = env.resources.create::<WebGlFramebuffer>()?;
+        Ok(ShadowMaps {
+            texture,
+            framebuffer,
+            size,
+            layers,
+        })
+    }
+
+    /// Renders the depth of the shadow casters of `scene` as seen from each
+    /// light of `lights`, and returns the uniforms sampling the result.
+    /// Directional lights get one map per cascade of the `camera` frustum,
+    /// spot lights a single perspective map.
+    pub fn render(
+        &self,
+        env: &GlEnvironment,
+        scene: &Scene,
+        camera: &Camera,
+        lights: &[(usize, &Light)],
+    ) -> Result<ShadowUniforms, EngineError> {
+        let context = &env.context;
+        let program = env.program(DEPTH_PROGRAM)?;
+        let casters: Vec<&GlShape> = scene
+            .drawn_shapes()
+            .filter(|shape| {
+                shape.cast_shadows
+                    && shape.is_drawn_on(scene.shadows.layer_mask)
+            })
+            .collect();
+        let caster_bounds = casters
+            .iter()
+            .map(|shape| shape.world_bounds())
+            .reduce(|bounds, other| bounds.union(&other));
+
+        let viewport = get_viewport(context);
+        context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&self.framebuffer),
+        );
+        context.viewport(0, 0, self.size as i32, self.size as i32);
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &scene.shadows.render_state);
+        context.use_program(Some(&program.gl_program));
+
+        let mut uniforms = ShadowUniforms::default();
+        let mut result = Ok(());
+        for (shadow, (light_index, light)) in lights.iter().enumerate() {
+            let settings = light.shadow();
+            let mut splits = [camera.far(); 4];
+            let matrices = match light.kind() {
+                LightKind::Directional => {
+                    let far = scene.shadows.max_distance.min(camera.far());
+                    let count = (settings.cascade_count as usize)
+                        .clamp(1, MAX_CASCADES);
+                    let cascade_splits = cascade_splits(
+                        camera.near(),
+                        far,
+                        count,
+                        scene.shadows.cascade_split_lambda,
+                    );
+                    let mut near = camera.near();
+                    let mut matrices = Vec::with_capacity(count);
+                    for (cascade, split) in cascade_splits.iter().enumerate() {
+                        matrices.push(directional_light_matrix(
+                            light.direction(),
+                            &camera.frustum_corners(near, *split),
+                            caster_bounds.as_ref(),
+                            self.size,
+                        ));
+                        splits[cascade] = *split;
+                        near = *split;
+                    }
+                    matrices
+                }
+                _ => vec![spot_light_matrix(light, caster_bounds.as_ref())],
+            };
+
+            uniforms.light_shadows[*light_index] = shadow as i32;
+            uniforms.cascade_splits[shadow * 4..shadow * 4 + 4]
+                .copy_from_slice(&splits);
+            uniforms.parameters[shadow * 4..shadow * 4 + 4].copy_from_slice(&[
+                settings.bias,
+                settings.normal_bias,
+                settings.pcf_radius as f32,
+                matrices.len() as f32,
+            ]);
+            for (cascade, matrix) in matrices.iter().enumerate() {
+                let layer = shadow * MAX_CASCADES + cascade;
+                uniforms.matrices[layer * 16..layer * 16 + 16]
+                    .copy_from_slice(&mat_to_col_array(matrix));
+                result = self.render_layer(
+                    context,
+                    program,
+                    layer as u32,
+                    matrix,
+                    &casters,
+                );
+                if result.is_err() {
+                    break;
+                }
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+
+        context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        set_viewport(context, viewport);
+        result.map(|_| uniforms)
+    }
+
+    fn render_layer(
+        &self,
+        context: &WebGl2RenderingContext,
+        program: &Program,
+        layer: u32,
+        matrix: &Matrix4<f32>,
+        casters: &[&GlShape],
+    ) -> Result<(), EngineError> {
+        context.framebuffer_texture_layer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            WebGl2RenderingContext::DEPTH_ATTACHMENT,
+            Some(&self.texture),
+            0,
+            layer as i32,
+        );
+        let status = context
+            .check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER);
+        if status != WebGl2RenderingContext::FRAMEBUFFER_COMPLETE {
+            return Err(EngineError::IncompleteFramebuffer(status));
+        }
+        context.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+        context.uniform_matrix4fv_with_f32_array(
+            program.uniform_location("viewProjectionMatrix"),
+            true,
+            &mat_to_col_array(matrix),
+        );
+        for shape in casters {
+            draw_shape(context, shape, program);
+        }
+        Ok(())
+    }
+
+    /// Binds the shadow maps to texture unit `unit`.
+    pub fn bind(&self, context: &WebGl2RenderingContext, unit: u32) {
+        context.active_texture(WebGl2RenderingContext::TEXTURE0 + unit);
+        context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D_ARRAY,
+            Some(&self.texture),
+        );
+    }
+}
+
+/// Lights of `scene` getting a shadow map, with their index in the scene.
+pub fn shadowed_lights(scene: &Scene) -> Vec<(usize, &Light)> {
+    scene
+        .lights
+        .iter()
+        .take(MAX_LIGHTS)
+        .enumerate()
+        .filter(|(_, light)| light.casts_shadows())
+        .take(MAX_SHADOWED_LIGHTS)
+        .collect()
+}
+
+/// Far view depth of each of `count` cascades covering `near` to `far`,
+/// blending uniform and logarithmic splits by `lambda`.
+/// https://developer.nvidia.com/gpugems/gpugems3/part-ii-light-and-shadows/chapter-10-parallel-split-shadow-maps-programmable-gpus
+pub fn cascade_splits(
+    near: f32,
+    far: f32,
+    count: usize,
+    lambda: f32,
+) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let ratio = i as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(ratio);
+            let uniform = near + (far - near) * ratio;
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/// Orthographic light-space matrix of a directional light covering the
+/// frustum slice `corners` and every caster between it and the light. The
+/// matrix is in the row-vector convention of `LinearTransform`.
+pub fn directional_light_matrix(
+    direction: Vec3,
+    corners: &[Vec3; 8],
+    casters: Option<&Aabb>,
+    map_size: u32,
+) -> Matrix4<f32> {
+    let view = light_view(Vec3::zero(), direction);
+    let to_light = |point: &Vec3| {
+        view.transform_point(&Point3::new(point.x, point.y, point.z))
+    };
+
+    // Fitting a sphere rather than a box keeps the extent constant when the
+    // camera rotates, and snapping its center to texels keeps shadow edges
+    // from shimmering when the camera moves.
+    let light_corners: Vec<Point3<f32>> =
+        corners.iter().map(to_light).collect();
+    let center = light_corners
+        .iter()
+        .fold(Vector3::zeros(), |sum, corner| sum + corner.coords)
+        / light_corners.len() as f32;
+    let radius = light_corners
+        .iter()
+        .map(|corner| (corner.coords - center).norm())
+        .fold(0.0, f32::max);
+    let texel = 2.0 * radius / map_size as f32;
+    let x = (center.x / texel).floor() * texel;
+    let y = (center.y / texel).floor() * texel;
+
+    // The light looks down -z, casters closer to it than the slice must
+    // still be in front of the near plane.
+    let far = -center.z + radius;
+    let near = casters
+        .map(|casters| casters.corners())
+        .iter()
+        .flatten()
+        .map(|corner| -to_light(corner).z)
+        .fold(-center.z - radius, f32::min);
+
+    let projection = Matrix4::new_orthographic(
+        x - radius,
+        x + radius,
+        y - radius,
+        y + radius,
+        near,
+        far,
+    );
+    (projection * view).transpose()
+}
+
+/// Perspective light-space matrix of a spot light, in the row-vector
+/// convention of `LinearTransform`. Lights without range reach the
+/// farthest caster.
+pub fn spot_light_matrix(
+    light: &Light,
+    casters: Option<&Aabb>,
+) -> Matrix4<f32> {
+    let position = light.position();
+    let far = if light.range() > 0.0 {
+        light.range()
+    } else {
+        casters
+            .map(|casters| casters.corners())
+            .iter()
+            .flatten()
+            .map(|corner| {
+                Vec3::new(
+                    corner.x - position.x,
+                    corner.y - position.y,
+                    corner.z - position.z,
+                )
+                .length()
+            })
+            .fold(1.0, f32::max)
+    };
+    let fov = (2.0 * degree_to_radian(light.outer_cone_angle()))
+        .clamp(0.01, degree_to_radian(170.0));
+    let projection =
+        Matrix4::new_perspective(1.0, fov, (far / 1000.0).max(0.01), far);
+    (projection * light_view(position, light.direction())).transpose()
+}
+
+fn light_view(position: Vec3, direction: Vec3) -> Matrix4<f32> {
+    let eye = Point3::new(position.x, position.y, position.z);
+    let target = eye + Vector3::new(direction.x, direction.y, direction.z);
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::z()
+    } else {
+        Vector3::y()
+    };
+    Matrix4::look_at_rh(&eye, &target, &up)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::matrix::transform_point;
+
+    fn assert_in_clip_volume(matrix: &Matrix4<f32>, point: Vec3) {
+        let clip = transform_point(matrix, point);
+        for value in clip.to_array() {
+            assert!((-1.0001..=1.0001).contains(&value), "{:?}", clip);
+        }
+    }
+
+    #[test]
+    fn cascade_splits_end_at_far() {
+        let uniform = cascade_splits(1.0, 9.0, 4, 0.0);
+        assert_eq!(uniform, vec![3.0, 5.0, 7.0, 9.0]);
+        let logarithmic = cascade_splits(1.0, 100.0, 2, 1.0);
+        assert!((logarithmic[0] - 10.0).abs() < 1e-4);
+        assert!((logarithmic[1] - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn directional_matrix_covers_slice_and_casters() {
+        let slice =
+            Aabb::new(Vec3::new(-2.0, 0.0, -6.0), Vec3::new(2.0, 1.0, -2.0));
+        let caster =
+            Aabb::new(Vec3::new(0.0, 8.0, -4.0), Vec3::new(1.0, 9.0, -3.0));
+        let matrix = directional_light_matrix(
+            Vec3::new(0.0, -1.0, -0.2).normalize(),
+            &slice.corners(),
+            Some(&caster),
+            1024,
+        );
+        for corner in slice.corners().iter().chain(caster.corners().iter()) {
+            assert_in_clip_volume(&matrix, *corner);
+        }
+    }
+
+    #[test]
+    fn spot_matrix_projects_axis_to_center() {
+        let light = Light::spot(
+            0.0, 5.0, 0.0, 0.0, -1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 10.0, 20.0, 30.0,
+        );
+        let matrix = spot_light_matrix(&light, None);
+        let center = transform_point(&matrix, Vec3::new(0.0, 0.0, 0.0));
+        assert!(center.x.abs() < 1e-5 && center.y.abs() < 1e-5);
+        assert_in_clip_volume(&matrix, Vec3::new(0.0, 0.0, 0.0));
+    }
+}
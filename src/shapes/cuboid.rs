@@ -2,6 +2,7 @@ use web_sys::WebGl2RenderingContext;
 
 use crate::{
     environment::GlEnvironment,
+    error::EngineError,
     gl::gl_shape::{GlShape, ShapeProps},
     types::Color,
 };
@@ -17,42 +18,67 @@ impl Cuboid {
         height: f32,
         depth: f32,
         colors: &[Color; 4],
-    ) -> Result<Cuboid, String> {
+    ) -> Result<Cuboid, EngineError> {
         let hwidth = width / 2.0;
         let hheight = height / 2.0;
         let hdepth = depth / 2.0;
 
-        #[cfg_attr(rustfmt, rustfmt_skip)]
-        let positions = vec![
-            hwidth, hheight, hdepth, // Front-top-right 0
-            -hwidth, hheight, hdepth, // Front-top-left 2
-            hwidth, hheight, -hdepth, // Back-top-right 3
-            -hwidth, hheight, -hdepth, // Back-top-left 4
-            hwidth, -hheight, hdepth, // Front-bottom-right 5
-            -hwidth, -hheight, hdepth, // Front-bottom-left 6
-            -hwidth, -hheight, -hdepth, // Back-bottom-left 7
-            hwidth, -hheight, -hdepth, // Back-bottom-right 8
+        #[rustfmt::skip]
+        let corners = [
+            [hwidth, hheight, hdepth], // Front-top-right 0
+            [-hwidth, hheight, hdepth], // Front-top-left 1
+            [hwidth, hheight, -hdepth], // Back-top-right 2
+            [-hwidth, hheight, -hdepth], // Back-top-left 3
+            [hwidth, -hheight, hdepth], // Front-bottom-right 4
+            [-hwidth, -hheight, hdepth], // Front-bottom-left 5
+            [-hwidth, -hheight, -hdepth], // Back-bottom-left 6
+            [hwidth, -hheight, -hdepth], // Back-bottom-right 7
         ];
 
-        #[cfg_attr(rustfmt, rustfmt_skip)]
-        let colors = vec![
-            colors[0].r, colors[0].g, colors[0].b, colors[0].a, // Front-top-left
-            colors[1].r, colors[1].g, colors[1].b, colors[1].a, // Front-top-right
-            colors[2].r, colors[2].g, colors[2].b, colors[2].a, // Front-bottom-left
-            colors[3].r, colors[3].g, colors[3].b, colors[3].a, // Front-bottom-right
-            colors[0].r, colors[0].g, colors[0].b, colors[0].a, // Back-top-right
-            colors[1].r, colors[1].g, colors[1].b, colors[1].a, // Back-top-left
-            colors[2].r, colors[2].g, colors[2].b, colors[2].a, // Back-bottom-left
-            colors[3].r, colors[3].g, colors[3].b, colors[3].a, // Back-bottom-right
+        // Each face has its own vertices so that it gets a flat normal.
+        // Corners are listed counter-clockwise when seen from outside.
+        let faces: [([usize; 4], [f32; 3]); 6] = [
+            ([5, 4, 0, 1], [0.0, 0.0, 1.0]),  // Front
+            ([7, 6, 3, 2], [0.0, 0.0, -1.0]), // Back
+            ([4, 7, 2, 0], [1.0, 0.0, 0.0]),  // Right
+            ([6, 5, 1, 3], [-1.0, 0.0, 0.0]), // Left
+            ([1, 0, 2, 3], [0.0, 1.0, 0.0]),  // Top
+            ([6, 7, 4, 5], [0.0, -1.0, 0.0]), // Bottom
         ];
 
+        let mut positions = Vec::with_capacity(faces.len() * 4 * 3);
+        let mut vertex_colors = Vec::with_capacity(faces.len() * 4 * 4);
+        let mut normals = Vec::with_capacity(faces.len() * 4 * 3);
+        let mut uvs = Vec::with_capacity(faces.len() * 4 * 2);
+        let mut indices = Vec::with_capacity(faces.len() * 6);
+        for (face, (face_corners, normal)) in faces.iter().enumerate() {
+            for corner in face_corners {
+                let color = colors[corner % colors.len()];
+                positions.extend_from_slice(&corners[*corner]);
+                vertex_colors.extend_from_slice(&color.to_array());
+                normals.extend_from_slice(normal);
+            }
+            uvs.extend_from_slice(&[0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0]);
+            let base = (face * 4) as u32;
+            indices.extend_from_slice(&[
+                base,
+                base + 1,
+                base + 2,
+                base,
+                base + 2,
+                base + 3,
+            ]);
+        }
+
         let shape = GlShape::new(ShapeProps {
-            env: &gl_environment,
-            mode: WebGl2RenderingContext::TRIANGLE_STRIP,
-            vertices_count: 8,
+            env: gl_environment,
+            mode: WebGl2RenderingContext::TRIANGLES,
+            vertices_count: faces.len() * 4,
             positions,
-            colors,
-            indices: Some(vec![3, 2, 6, 7, 4, 2, 0, 3, 1, 6, 5, 4, 1, 0]),
+            colors: vertex_colors,
+            normals,
+            uvs,
+            indices: Some(indices),
         })?;
 
         Ok(Cuboid { shape })
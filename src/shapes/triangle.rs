@@ -2,6 +2,7 @@ use web_sys::WebGl2RenderingContext;
 
 use crate::{
     environment::GlEnvironment,
+    error::EngineError,
     gl::gl_shape::{GlShape, ShapeProps},
     types::{Color, Vec3},
 };
@@ -16,29 +17,53 @@ impl Triangle {
         gl_environment: &GlEnvironment,
         vertices: &[Vec3; 3],
         colors: &[Color; 3],
-    ) -> Result<Triangle, String> {
-        #[cfg_attr(rustfmt, rustfmt_skip)]
+    ) -> Result<Triangle, EngineError> {
+        #[rustfmt::skip]
         let positions = vec![
             vertices[0].x, vertices[0].y, vertices[0].z,
             vertices[1].x, vertices[1].y, vertices[1].z,
             vertices[2].x, vertices[2].y, vertices[2].z,
         ];
-        #[cfg_attr(rustfmt, rustfmt_skip)]
+        #[rustfmt::skip]
         let colors = vec![
             colors[0].r, colors[0].g, colors[0].b, colors[0].a,
             colors[1].r, colors[1].g, colors[1].b, colors[1].a,
             colors[2].r, colors[2].g, colors[2].b, colors[2].a,
         ];
+        let normal = face_normal(vertices).to_array();
+        let normals = [normal, normal, normal].concat();
 
         let shape = GlShape::new(ShapeProps {
-            env: &gl_environment,
+            env: gl_environment,
             mode: WebGl2RenderingContext::TRIANGLES,
             vertices_count: 3,
             positions,
             colors,
+            normals,
+            uvs: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
             indices: None,
         })?;
 
         Ok(Triangle { shape })
     }
 }
+
+/// Normal of the front face, given clockwise points.
+fn face_normal(vertices: &[Vec3; 3]) -> Vec3 {
+    let a = Vec3::new(
+        vertices[1].x - vertices[0].x,
+        vertices[1].y - vertices[0].y,
+        vertices[1].z - vertices[0].z,
+    );
+    let b = Vec3::new(
+        vertices[2].x - vertices[0].x,
+        vertices[2].y - vertices[0].y,
+        vertices[2].z - vertices[0].z,
+    );
+    Vec3::new(
+        b.y * a.z - b.z * a.y,
+        b.z * a.x - b.x * a.z,
+        b.x * a.y - b.y * a.x,
+    )
+    .normalize()
+}
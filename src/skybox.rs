@@ -0,0 +1,77 @@
+use std::rc::Rc;
+
+use web_sys::WebGl2RenderingContext;
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        cubemap::TextureCube,
+        gl_shape::{with_vao, GlShape},
+        gl_utils::Program,
+        render_state::{CullFace, DepthFunc, RenderState},
+    },
+    shapes::cuboid::Cuboid,
+    types::Color,
+};
+
+pub static SKYBOX_PROGRAM: &str = "skybox";
+
+/// A cube map drawn behind all geometry, following the camera rotation.
+pub struct Skybox {
+    pub cubemap: Rc<TextureCube>,
+    pub intensity: f32,
+    cube: GlShape,
+}
+
+impl Skybox {
+    pub fn new(
+        env: &GlEnvironment,
+        cubemap: Rc<TextureCube>,
+        intensity: f32,
+    ) -> Result<Skybox, EngineError> {
+        let white = Color::rgb(1.0, 1.0, 1.0);
+        let mut cube = Cuboid::new(env, 2.0, 2.0, 2.0, &[white; 4])?.shape;
+        cube.prepare_for_render(&env.context);
+        Ok(Skybox {
+            cubemap,
+            intensity,
+            cube,
+        })
+    }
+
+    /// State of the skybox pass. The cube is seen from the inside, and sits
+    /// on the far plane without hiding anything drawn after it.
+    pub fn render_state() -> RenderState {
+        RenderState {
+            depth_write: false,
+            depth_func: DepthFunc::LessEqual,
+            cull_face: Some(CullFace::Front),
+            ..RenderState::default()
+        }
+    }
+
+    /// Draws the skybox. `program` must be the skybox program, in use and
+    /// with the camera matrices set.
+    pub fn draw(&self, env: &GlEnvironment, program: &Program) {
+        let context = &env.context;
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &Skybox::render_state());
+        self.cubemap.bind(context, 0);
+        context.uniform1i(program.uniform_location("skybox"), 0);
+        context
+            .uniform1f(program.uniform_location("intensity"), self.intensity);
+
+        with_vao(context, self.cube.vertex_array(), |_| {
+            if let Some(indices) = &self.cube.mesh.element_array {
+                context.draw_elements_with_i32(
+                    WebGl2RenderingContext::TRIANGLES,
+                    indices.len() as i32,
+                    WebGl2RenderingContext::UNSIGNED_INT,
+                    0,
+                );
+            }
+        });
+    }
+}
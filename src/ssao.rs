@@ -0,0 +1,355 @@
+use web_sys::WebGl2RenderingContext;
+
+use crate::{
+    environment::GlEnvironment,
+    error::EngineError,
+    gl::{
+        gl_shape::GlShape,
+        gl_utils::{set_viewport, Program},
+        render_state::RenderState,
+        render_target::{
+            AttachmentSettings, RenderTarget, RenderTargetSettings,
+        },
+        texture::{Filter, SamplerSettings, Texture2D, TextureFormat, Wrap},
+    },
+    renderer::draw_shape,
+    types::Vec3,
+};
+
+pub static NORMAL_DEPTH_PROGRAM: &str = "normal_depth";
+pub static SSAO_PROGRAM: &str = "ssao";
+pub static SSAO_BLUR_PROGRAM: &str = "ssao_blur";
+pub static SSAO_DEBUG_PROGRAM: &str = "ssao_debug";
+
+pub const AMBIENT_OCCLUSION_TEXTURE_UNIT: u32 = 7;
+/// Maximum number of kernel samples per pixel.
+pub const MAX_SSAO_SAMPLES: usize = 64;
+/// Width and height of the tiled rotation noise, matched by the blur.
+const NOISE_SIZE: u32 = 4;
+
+/// Screen-space ambient occlusion settings of a scene.
+///
+/// Needs `EXT_color_buffer_float`, without it ambient light isn't
+/// occluded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    /// View space radius in which surfaces occlude each other.
+    pub radius: f32,
+    /// Darkening of fully occluded points, `1.0` makes them black.
+    pub intensity: f32,
+    /// Samples per pixel, clamped to `MAX_SSAO_SAMPLES`.
+    pub sample_count: u32,
+    /// Depth offset keeping flat surfaces from occluding themselves.
+    pub bias: f32,
+    /// Shows the blurred occlusion instead of the shaded scene.
+    pub debug_view: bool,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings {
+            enabled: false,
+            radius: 0.5,
+            intensity: 1.0,
+            sample_count: 16,
+            bias: 0.025,
+            debug_view: false,
+        }
+    }
+}
+
+/// Targets and sampling data of the ambient occlusion passes.
+pub struct Ssao {
+    /// View space normals in RGB and view depths in A, 0 where nothing was
+    /// drawn.
+    normal_depth: RenderTarget,
+    occlusion: RenderTarget,
+    blurred: RenderTarget,
+    noise: Texture2D,
+    /// Kernel of `kernel.len() / 3` samples.
+    kernel: Vec<f32>,
+}
+
+impl Ssao {
+    /// Creates targets of `width` by `height` pixels. Needs
+    /// `EXT_color_buffer_float`.
+    pub fn new(
+        env: &GlEnvironment,
+        width: u32,
+        height: u32,
+    ) -> Result<Ssao, EngineError> {
+        let normal_depth = RenderTarget::new(
+            env,
+            width,
+            height,
+            &RenderTargetSettings {
+                color: vec![AttachmentSettings::texture(
+                    TextureFormat::Rgba16F,
+                    Filter::Nearest,
+                )],
+                depth: Some(AttachmentSettings::renderbuffer(
+                    TextureFormat::Depth24,
+                )),
+                samples: 1,
+            },
+        )?;
+        let occlusion_settings = RenderTargetSettings {
+            color: vec![AttachmentSettings::texture(
+                TextureFormat::Rgba8,
+                Filter::Linear,
+            )],
+            depth: None,
+            samples: 1,
+        };
+        let occlusion =
+            RenderTarget::new(env, width, height, &occlusion_settings)?;
+        let blurred =
+            RenderTarget::new(env, width, height, &occlusion_settings)?;
+        let noise = Texture2D::from_rgba_f32(
+            env,
+            NOISE_SIZE,
+            NOISE_SIZE,
+            TextureFormat::Rgba16F,
+            &rotation_noise((NOISE_SIZE * NOISE_SIZE) as usize),
+            SamplerSettings {
+                wrap_s: Wrap::Repeat,
+                wrap_t: Wrap::Repeat,
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                mipmap_filter: None,
+                anisotropy: 1.0,
+            },
+        )?;
+        Ok(Ssao {
+            normal_depth,
+            occlusion,
+            blurred,
+            noise,
+            kernel: Vec::new(),
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.normal_depth.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.normal_depth.height
+    }
+
+    /// Renders the blurred occlusion of `shapes` in `viewport`, seen through
+    /// the camera of the bound camera uniform buffer. Leaves the blur target
+    /// bound.
+    pub fn render(
+        &mut self,
+        env: &GlEnvironment,
+        settings: &SsaoSettings,
+        shapes: &[&GlShape],
+        viewport: [i32; 4],
+    ) -> Result<(), EngineError> {
+        let context = &env.context;
+        let sample_count =
+            (settings.sample_count as usize).clamp(1, MAX_SSAO_SAMPLES);
+        if self.kernel.len() != sample_count * 3 {
+            self.kernel = hemisphere_kernel(sample_count)
+                .iter()
+                .flat_map(|sample| sample.to_array())
+                .collect();
+        }
+
+        // Normals and depths of the shapes.
+        self.normal_depth.bind(context);
+        set_viewport(context, viewport);
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::default());
+        context.clear_bufferfv_with_f32_array(
+            WebGl2RenderingContext::COLOR,
+            0,
+            &[0.0; 4],
+        );
+        context.clear(WebGl2RenderingContext::DEPTH_BUFFER_BIT);
+        let program = get_program(env, NORMAL_DEPTH_PROGRAM)?;
+        context.use_program(Some(&program.gl_program));
+        for shape in shapes {
+            // Keeps the culling of double-sided materials.
+            env.state_cache.borrow_mut().apply(
+                context,
+                &RenderState {
+                    blend: None,
+                    ..shape.material.render_state
+                },
+            );
+            draw_shape(context, shape, program);
+        }
+
+        // Raw occlusion.
+        let normal_depth =
+            self.normal_depth.color_texture(0).ok_or_else(|| {
+                EngineError::Internal(String::from(
+                    "SSAO target has no texture.",
+                ))
+            })?;
+        self.occlusion.bind(context);
+        set_viewport(context, viewport);
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::full_screen());
+        let program = get_program(env, SSAO_PROGRAM)?;
+        context.use_program(Some(&program.gl_program));
+        normal_depth.bind(context, 0);
+        context.uniform1i(program.uniform_location("normalDepth"), 0);
+        self.noise.bind(context, 1);
+        context.uniform1i(program.uniform_location("noise"), 1);
+        context.uniform3fv_with_f32_array(
+            program.uniform_location("kernel"),
+            &self.kernel,
+        );
+        context.uniform1i(
+            program.uniform_location("sampleCount"),
+            sample_count as i32,
+        );
+        context.uniform1f(program.uniform_location("radius"), settings.radius);
+        context.uniform1f(program.uniform_location("bias"), settings.bias);
+        context.uniform1f(
+            program.uniform_location("intensity"),
+            settings.intensity,
+        );
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+
+        // Blur of the noise pattern.
+        self.blurred.bind(context);
+        set_viewport(context, viewport);
+        let program = get_program(env, SSAO_BLUR_PROGRAM)?;
+        context.use_program(Some(&program.gl_program));
+        self.occlusion
+            .color_texture(0)
+            .ok_or_else(|| {
+                EngineError::Internal(String::from(
+                    "SSAO target has no texture.",
+                ))
+            })?
+            .bind(context, 0);
+        context.uniform1i(program.uniform_location("ambientOcclusion"), 0);
+        normal_depth.bind(context, 1);
+        context.uniform1i(program.uniform_location("normalDepth"), 1);
+        context.uniform2f(
+            program.uniform_location("texelSize"),
+            1.0 / self.width() as f32,
+            1.0 / self.height() as f32,
+        );
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        Ok(())
+    }
+
+    /// Binds the blurred occlusion to `unit`.
+    pub fn bind(&self, context: &WebGl2RenderingContext, unit: u32) {
+        if let Some(texture) = self.blurred.color_texture(0) {
+            texture.bind(context, unit);
+        }
+    }
+
+    /// Draws the blurred occlusion over the bound framebuffer.
+    pub fn draw_debug(&self, env: &GlEnvironment) -> Result<(), EngineError> {
+        let context = &env.context;
+        env.state_cache
+            .borrow_mut()
+            .apply(context, &RenderState::full_screen());
+        let program = get_program(env, SSAO_DEBUG_PROGRAM)?;
+        context.use_program(Some(&program.gl_program));
+        self.bind(context, 0);
+        context.uniform1i(program.uniform_location("ambientOcclusion"), 0);
+        context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 3);
+        Ok(())
+    }
+}
+
+fn get_program<'a>(
+    env: &'a GlEnvironment,
+    name: &str,
+) -> Result<&'a Program, EngineError> {
+    env.program(name)
+}
+
+/// `count` sample offsets in the unit hemisphere around +z, denser near the
+/// center so that close occluders weigh more.
+pub fn hemisphere_kernel(count: usize) -> Vec<Vec3> {
+    let mut random = Random::new(0x2545_f491);
+    (0..count)
+        .map(|i| {
+            let direction = Vec3::new(
+                random.next() * 2.0 - 1.0,
+                random.next() * 2.0 - 1.0,
+                random.next(),
+            )
+            .normalize();
+            let t = i as f32 / count as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            let length = random.next() * scale;
+            Vec3::new(
+                direction.x * length,
+                direction.y * length,
+                direction.z * length,
+            )
+        })
+        .collect()
+}
+
+/// RGBA pixels of `count` random rotations around +z, as vectors in the xy
+/// plane.
+pub fn rotation_noise(count: usize) -> Vec<f32> {
+    let mut random = Random::new(0x9e37_79b9);
+    (0..count)
+        .flat_map(|_| {
+            [
+                random.next() * 2.0 - 1.0,
+                random.next() * 2.0 - 1.0,
+                0.0,
+                0.0,
+            ]
+        })
+        .collect()
+}
+
+/// Xorshift generator, so that the kernel is the same on every run.
+struct Random(u32);
+
+impl Random {
+    fn new(seed: u32) -> Random {
+        Random(seed)
+    }
+
+    /// Next value in `[0, 1)`.
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_samples_lie_in_unit_hemisphere() {
+        let kernel = hemisphere_kernel(MAX_SSAO_SAMPLES);
+        assert_eq!(kernel.len(), MAX_SSAO_SAMPLES);
+        for sample in kernel {
+            assert!(sample.z >= 0.0, "{:?}", sample);
+            assert!(sample.length() <= 1.0, "{:?}", sample);
+        }
+    }
+
+    #[test]
+    fn rotation_noise_is_in_xy_plane() {
+        let noise = rotation_noise(16);
+        assert_eq!(noise.len(), 64);
+        for pixel in noise.chunks(4) {
+            assert!(pixel[0].abs() <= 1.0 && pixel[1].abs() <= 1.0);
+            assert_eq!(&pixel[2..], &[0.0, 0.0]);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use web_sys::WebGl2RenderingContext;
+
+use crate::{
+    environment::GlEnvironment, error::EngineError, gl::gl_shape::GlShape,
+};
+
+/// Vertices a batch holds at most before another one is started.
+static MAX_BATCH_VERTICES: usize = 1 << 20;
+
+/// Static shapes merged into a few large shapes, each drawn in one call.
+/// Shapes are batched together when they are drawn the same way: same
+/// material, alpha mode, shadow settings, layers and visibility.
+#[derive(Default)]
+pub struct StaticBatches {
+    batches: Vec<GlShape>,
+    /// Whether the static shapes changed since the batches were built.
+    dirty: bool,
+}
+
+impl StaticBatches {
+    pub fn new() -> StaticBatches {
+        StaticBatches::default()
+    }
+
+    /// Rebuilds the batches before the next frame.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn shapes(&self) -> &[GlShape] {
+        &self.batches
+    }
+
+    /// Rebuilds the batches from the batched shapes of `shapes` if they
+    /// changed. The shapes must be prepared for render.
+    pub fn update(
+        &mut self,
+        env: &GlEnvironment,
+        shapes: &[GlShape],
+    ) -> Result<(), EngineError> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut groups: Vec<Group> = Vec::new();
+        for shape in shapes.iter().filter(|shape| is_batched(shape)) {
+            let group = groups.iter_mut().rev().find(|group| {
+                group.accepts(shape)
+                    && group.vertices_count + shape.mesh.vertices_count
+                        <= MAX_BATCH_VERTICES
+            });
+            let group = match group {
+                Some(group) => group,
+                None => {
+                    groups.push(Group::new(shape));
+                    groups.last_mut().unwrap()
+                }
+            };
+            shape.append_transformed(&mut group.vertices, &mut group.indices);
+            group.vertices_count += shape.mesh.vertices_count;
+        }
+
+        let mut batches = Vec::with_capacity(groups.len());
+        for group in groups {
+            let mut batch = GlShape::from_array_buffer(
+                env,
+                WebGl2RenderingContext::TRIANGLES,
+                group.vertices,
+                Some(group.indices),
+            )?;
+            batch.material = group.first.material.clone();
+            batch.alpha_mode = group.first.alpha_mode;
+            batch.cast_shadows = group.first.cast_shadows;
+            batch.receive_shadows = group.first.receive_shadows;
+            batch.layers = group.first.layers;
+            batch.visible = group.first.visible;
+            batches.push(batch);
+        }
+        // Only replaced once every batch is built, so that a failure keeps
+        // the previous batches and retries on the next update.
+        self.batches = batches;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Whether `shape` is drawn as part of a static batch rather than on its
+/// own. Only static triangle lists without instances are batched.
+pub fn is_batched(shape: &GlShape) -> bool {
+    shape.is_static
+        && shape.instances.is_none()
+        && shape.mesh.mode == WebGl2RenderingContext::TRIANGLES
+}
+
+/// Shapes merged into one batch.
+struct Group<'a> {
+    /// Shape the batch takes its material and settings from.
+    first: &'a GlShape,
+    vertices: Vec<f32>,
+    vertices_count: usize,
+    indices: Vec<u32>,
+}
+
+impl<'a> Group<'a> {
+    fn new(first: &'a GlShape) -> Group<'a> {
+        Group {
+            first,
+            vertices: Vec::new(),
+            vertices_count: 0,
+            indices: Vec::new(),
+        }
+    }
+
+    fn accepts(&self, shape: &GlShape) -> bool {
+        shape.material == self.first.material
+            && shape.alpha_mode == self.first.alpha_mode
+            && shape.cast_shadows == self.first.cast_shadows
+            && shape.receive_shadows == self.first.receive_shadows
+            && shape.layers == self.first.layers
+            && shape.visible == self.first.visible
+    }
+}
@@ -1,11 +1,5 @@
 extern crate web_sys;
 
-#[derive(Copy, Clone)]
-pub struct Buffer<T> {
-    pub buffer: T,
-    pub is_dirty: bool,
-}
-
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
     // `set_panic_hook` function at least once during initialization, and then